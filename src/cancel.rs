@@ -0,0 +1,29 @@
+//! Stopping a run early, e.g. at `--deadline`.
+//!
+//! Workers finish the file they are hashing and skip the rest, so every printed line is complete.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static SKIPPED: AtomicBool = AtomicBool::new(false);
+
+/// Ask the workers and the directory walk to stop.
+pub fn request() {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Whether the caller should stop instead of starting the next file.
+/// Returning `true` means some work is skipped, which `skipped` reports afterwards.
+pub fn should_stop() -> bool {
+    if REQUESTED.load(Ordering::Relaxed) {
+        SKIPPED.store(true, Ordering::Relaxed);
+        true
+    } else {
+        false
+    }
+}
+
+/// Whether any work was skipped because of a stop request
+pub fn skipped() -> bool {
+    SKIPPED.load(Ordering::Relaxed)
+}
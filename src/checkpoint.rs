@@ -0,0 +1,257 @@
+//! Resumable BLAKE3 hashing of a single large file.
+//!
+//! The file is split into blocks of `BLOCK_LEN` bytes. Each block is a complete subtree of the
+//! BLAKE3 tree, so the hasher state at a block boundary is just the stack of subtree chaining
+//! values. That stack and the offset are saved to the checkpoint file periodically, and hashing
+//! resumes from there after an interruption.
+
+use anyhow::{bail, Context, Result};
+use blake3::hazmat::{
+    merge_subtrees_non_root, merge_subtrees_root, ChainingValue, HasherExt, Mode,
+};
+use log::{debug, warn};
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// Size of the subtrees. Must be a power of two multiple of `blake3::CHUNK_LEN`.
+const BLOCK_LEN: usize = 1 << 20;
+
+/// Bytes hashed between checkpoints
+const CHECKPOINT_INTERVAL: u64 = 256 << 20;
+
+const HEADER: &str = "hashall-blake3-checkpoint 1";
+
+/// BLAKE3 hasher that accepts whole blocks only, except for the last one.
+#[derive(Debug, Default, PartialEq)]
+struct TreeHasher {
+    /// Number of bytes hashed
+    offset: u64,
+    /// Chaining values of the complete subtrees, largest first
+    stack: Vec<ChainingValue>,
+}
+
+impl TreeHasher {
+    /// Hash a block that is followed by more input.
+    fn push_block(&mut self, block: &[u8]) {
+        debug_assert_eq!(block.len(), BLOCK_LEN);
+        let cv = blake3::Hasher::new()
+            .set_input_offset(self.offset)
+            .update(block)
+            .finalize_non_root();
+        self.offset += BLOCK_LEN as u64;
+        self.stack.push(cv);
+        // Merge subtrees of the same size. None of them is the root because more input follows.
+        let mut n_blocks = self.offset / BLOCK_LEN as u64;
+        while n_blocks.is_multiple_of(2) {
+            let right = self.stack.pop().unwrap();
+            let left = self.stack.pop().unwrap();
+            self.stack
+                .push(merge_subtrees_non_root(&left, &right, Mode::Hash));
+            n_blocks /= 2;
+        }
+    }
+
+    /// Hash the last block (up to `BLOCK_LEN` bytes) and return the root hash.
+    fn finalize(mut self, last: &[u8]) -> blake3::Hash {
+        if self.stack.is_empty() {
+            return blake3::hash(last);
+        }
+        let mut right = blake3::Hasher::new()
+            .set_input_offset(self.offset)
+            .update(last)
+            .finalize_non_root();
+        while self.stack.len() > 1 {
+            let left = self.stack.pop().unwrap();
+            right = merge_subtrees_non_root(&left, &right, Mode::Hash);
+        }
+        merge_subtrees_root(&self.stack[0], &right, Mode::Hash)
+    }
+
+    fn save(&self, checkpoint: &Path, file_len: u64) -> Result<()> {
+        let mut contents = format!("{}\nsize {}\noffset {}\n", HEADER, file_len, self.offset);
+        for cv in &self.stack {
+            contents.push_str(&format!("cv {}\n", crate::output::to_hex(cv)));
+        }
+        // Write to `<checkpoint>.tmp` first so that an interruption doesn't corrupt the
+        // checkpoint. Replacing the extension instead could overwrite a file like `data.tmp`.
+        let mut tmp = checkpoint.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, contents)?;
+        std::fs::rename(&tmp, checkpoint)?;
+        Ok(())
+    }
+
+    /// Load a checkpoint of a file of `file_len` bytes.
+    fn load(checkpoint: &Path, file_len: u64) -> Result<Self> {
+        let contents = std::fs::read_to_string(checkpoint)?;
+        let mut lines = contents.lines();
+        if lines.next() != Some(HEADER) {
+            bail!("not a checkpoint file");
+        }
+        let mut hasher = TreeHasher::default();
+        for line in lines {
+            match line.split_once(' ') {
+                Some(("size", size)) if size.parse::<u64>()? != file_len => {
+                    bail!("the file size changed")
+                }
+                Some(("size", _)) => {}
+                Some(("offset", offset)) => hasher.offset = offset.parse()?,
+                Some(("cv", cv)) => hasher.stack.push(parse_cv(cv)?),
+                _ => bail!("invalid line: {}", line),
+            }
+        }
+        if !hasher.offset.is_multiple_of(BLOCK_LEN as u64)
+            || hasher.offset >= file_len
+            || hasher.stack.len() != (hasher.offset / BLOCK_LEN as u64).count_ones() as usize
+        {
+            bail!("inconsistent state");
+        }
+        Ok(hasher)
+    }
+}
+
+fn parse_cv(hex: &str) -> Result<ChainingValue> {
+    let mut cv = ChainingValue::default();
+    if hex.len() != cv.len() * 2 {
+        bail!("invalid chaining value: {}", hex);
+    }
+    for (i, byte) in cv.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(cv)
+}
+
+/// Read until `buffer` is full or EOF. Returns the number of bytes read.
+fn read_block(file: &mut File, buffer: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// BLAKE3 hash of the file at `path`, resuming from `checkpoint` if it exists.
+/// The checkpoint is updated while hashing and removed when done.
+pub fn hash_file(path: &Path, checkpoint: &Path) -> Result<blake3::Hash> {
+    hash_file_with_interval(path, checkpoint, CHECKPOINT_INTERVAL)
+}
+
+fn hash_file_with_interval(path: &Path, checkpoint: &Path, interval: u64) -> Result<blake3::Hash> {
+    let mut file = File::open(path).with_context(|| path.display().to_string())?;
+    let file_len = file.metadata()?.len();
+    let mut hasher = TreeHasher::default();
+    if checkpoint.exists() {
+        match TreeHasher::load(checkpoint, file_len) {
+            Ok(loaded) => {
+                debug!("Resuming from offset {}", loaded.offset);
+                hasher = loaded;
+                file.seek(SeekFrom::Start(hasher.offset))?;
+            }
+            Err(e) => warn!(
+                "{}: Ignoring the checkpoint ({}). Starting over.",
+                checkpoint.display(),
+                e
+            ),
+        }
+    }
+
+    // The last block must be finalized differently, so keep one block in hand
+    let mut block = vec![0; BLOCK_LEN];
+    let mut next = vec![0; BLOCK_LEN];
+    let mut len = read_block(&mut file, &mut block)?;
+    loop {
+        let next_len = read_block(&mut file, &mut next)?;
+        if next_len == 0 {
+            break;
+        }
+        hasher.push_block(&block);
+        if hasher.offset.is_multiple_of(interval) {
+            hasher.save(checkpoint, file_len)?;
+        }
+        std::mem::swap(&mut block, &mut next);
+        len = next_len;
+    }
+    let hash = hasher.finalize(&block[..len]);
+    if checkpoint.exists() {
+        std::fs::remove_file(checkpoint)?;
+    }
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_tree_hasher() {
+        for len in [
+            0,
+            1,
+            blake3::CHUNK_LEN,
+            BLOCK_LEN,
+            BLOCK_LEN + 1,
+            3 * BLOCK_LEN,
+            5 * BLOCK_LEN + BLOCK_LEN / 2,
+        ] {
+            let data = data(len);
+            let mut hasher = TreeHasher::default();
+            let mut blocks = data.chunks(BLOCK_LEN).collect::<Vec<_>>();
+            let last = blocks.pop().unwrap_or_default();
+            for block in blocks {
+                hasher.push_block(block);
+            }
+            assert_eq!(hasher.finalize(last), blake3::hash(&data), "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn test_resume() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("data");
+        let checkpoint = dir.path().join("checkpoint");
+        let data = data(5 * BLOCK_LEN + 123);
+        std::fs::write(&path, &data)?;
+
+        // Interrupted after three blocks
+        let mut hasher = TreeHasher::default();
+        for block in data.chunks(BLOCK_LEN).take(3) {
+            hasher.push_block(block);
+        }
+        hasher.save(&checkpoint, data.len() as u64)?;
+        assert_eq!(TreeHasher::load(&checkpoint, data.len() as u64)?, hasher);
+
+        let hash = hash_file_with_interval(&path, &checkpoint, BLOCK_LEN as u64)?;
+        assert_eq!(hash, blake3::hash(&data));
+        assert!(!checkpoint.exists());
+
+        // A checkpoint of another file is ignored
+        hasher.save(&checkpoint, 1 << 40)?;
+        let hash = hash_file_with_interval(&path, &checkpoint, BLOCK_LEN as u64)?;
+        assert_eq!(hash, blake3::hash(&data));
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_keeps_other_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        // `with_extension("tmp")` would have written both to `data.tmp`
+        let data = dir.path().join("data.tmp");
+        std::fs::write(&data, "contents")?;
+        let checkpoint = dir.path().join("data.ckpt");
+        TreeHasher::default().save(&checkpoint, 8)?;
+        assert_eq!(std::fs::read_to_string(&data)?, "contents");
+        assert!(checkpoint.exists());
+        assert!(!dir.path().join("data.ckpt.tmp").exists());
+        Ok(())
+    }
+}
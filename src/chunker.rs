@@ -0,0 +1,265 @@
+//! Content-defined chunking with a gear rolling hash, and fixed-size blocks.
+//!
+//! A chunk ends where the rolling hash of the preceding bytes matches a mask, so inserting or
+//! removing bytes only moves the boundaries near the change, as in restic and borg. The gear
+//! table is generated from a seed, and the same seed always gives the same boundaries.
+
+use digest::{Digest, FixedOutputReset};
+
+/// Chunks are at least this long, except for the last one
+pub const MIN_CHUNK_LEN: usize = 16 << 10;
+/// Chunks are cut at this length if no boundary is found
+pub const MAX_CHUNK_LEN: usize = 256 << 10;
+/// 16 bits give 64 KiB on average after `MIN_CHUNK_LEN`. The high bits depend on the last
+/// 64 bytes, the low bits only on the last few.
+const MASK: u64 = 0xffff << 48;
+
+/// How a stream is split
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chunking {
+    /// Content-defined chunks, with the gear table generated from the seed
+    ContentDefined { seed: u64 },
+    /// Blocks of `len` bytes, except for the last one
+    Fixed { len: usize },
+}
+
+/// Splits a stream of bytes into chunks.
+pub struct Chunker {
+    gear: [u64; 256],
+    hash: u64,
+    /// Bytes in the current chunk
+    len: usize,
+    min_len: usize,
+    max_len: usize,
+}
+
+/// splitmix64, to fill the gear table
+fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+impl Chunker {
+    pub fn new(chunking: Chunking) -> Self {
+        let mut gear = [0; 256];
+        let (min_len, max_len) = match chunking {
+            Chunking::ContentDefined { seed } => {
+                let mut state = seed;
+                for g in gear.iter_mut() {
+                    *g = next_random(&mut state);
+                }
+                (MIN_CHUNK_LEN, MAX_CHUNK_LEN)
+            }
+            Chunking::Fixed { len } => {
+                assert!(len > 0);
+                (len, len)
+            }
+        };
+        Chunker {
+            gear,
+            hash: 0,
+            len: 0,
+            min_len,
+            max_len,
+        }
+    }
+
+    /// Position in `data` after which the current chunk ends, if it ends in `data`.
+    /// The next call continues with a new chunk.
+    pub fn next_boundary(&mut self, data: &[u8]) -> Option<usize> {
+        if self.min_len == self.max_len {
+            // Fixed size
+            let end = self.max_len - self.len;
+            if data.len() < end {
+                self.len += data.len();
+                return None;
+            }
+            self.reset();
+            return Some(end);
+        }
+        for (i, &byte) in data.iter().enumerate() {
+            self.hash = (self.hash << 1).wrapping_add(self.gear[byte as usize]);
+            self.len += 1;
+            if self.len >= self.max_len || (self.len >= self.min_len && self.hash & MASK == 0) {
+                self.reset();
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+
+    /// Start over with a new stream.
+    pub fn reset(&mut self) {
+        self.hash = 0;
+        self.len = 0;
+    }
+}
+
+/// Chunk of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub offset: u64,
+    pub len: u64,
+    pub hash: Vec<u8>,
+}
+
+/// Hashes each chunk of a stream with `H`.
+pub struct ChunkHasher<H> {
+    chunker: Chunker,
+    hasher: H,
+    /// Offset of the current chunk
+    offset: u64,
+    /// Bytes in the current chunk
+    len: u64,
+    chunks: Vec<Chunk>,
+}
+
+impl<H: Digest + FixedOutputReset> ChunkHasher<H> {
+    pub fn new(chunking: Chunking) -> Self {
+        ChunkHasher {
+            chunker: Chunker::new(chunking),
+            hasher: H::new(),
+            offset: 0,
+            len: 0,
+            chunks: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while let Some(end) = self.chunker.next_boundary(data) {
+            Digest::update(&mut self.hasher, &data[..end]);
+            self.len += end as u64;
+            self.push();
+            data = &data[end..];
+        }
+        Digest::update(&mut self.hasher, data);
+        self.len += data.len() as u64;
+    }
+
+    fn push(&mut self) {
+        self.chunks.push(Chunk {
+            offset: self.offset,
+            len: self.len,
+            hash: Digest::finalize_reset(&mut self.hasher).to_vec(),
+        });
+        self.offset += self.len;
+        self.len = 0;
+    }
+
+    /// Chunks of the stream so far. The hasher is reset for the next stream.
+    pub fn finish(&mut self) -> Vec<Chunk> {
+        if self.len > 0 {
+            self.push();
+        }
+        self.chunker.reset();
+        self.offset = 0;
+        std::mem::take(&mut self.chunks)
+    }
+
+    /// Discard the stream so far.
+    pub fn reset(&mut self) {
+        Digest::reset(&mut self.hasher);
+        self.chunker.reset();
+        self.offset = 0;
+        self.len = 0;
+        self.chunks.clear();
+    }
+}
+
+/// Root of a binary Merkle tree over `leaves`. A parent is the hash of its children
+/// concatenated, and the last node of an odd level moves up unchanged. No leaves give the hash
+/// of empty input.
+pub fn merkle_root(leaves: &[Vec<u8>], hash: impl Fn(&[u8]) -> Vec<u8>) -> Vec<u8> {
+    if leaves.is_empty() {
+        return hash(&[]);
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => hash(&[left.as_slice(), right.as_slice()].concat()),
+                [single] => single.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level.pop().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pseudo-random bytes, so that there are boundaries before `MAX_CHUNK_LEN`
+    fn data(len: usize) -> Vec<u8> {
+        let mut state = 1;
+        (0..len).map(|_| next_random(&mut state) as u8).collect()
+    }
+
+    #[test]
+    fn test_chunk_hasher() {
+        let data = data(1 << 20);
+        let mut hasher = ChunkHasher::<md5::Md5>::new(Chunking::ContentDefined { seed: 0 });
+        hasher.update(&data);
+        let chunks = hasher.finish();
+
+        // Feeding the data in pieces gives the same chunks
+        for piece in data.chunks(1000) {
+            hasher.update(piece);
+        }
+        assert_eq!(hasher.finish(), chunks);
+
+        let mut offset = 0;
+        for (i, chunk) in chunks.iter().enumerate() {
+            assert_eq!(chunk.offset, offset);
+            assert!(chunk.len as usize <= MAX_CHUNK_LEN);
+            assert!(chunk.len as usize >= MIN_CHUNK_LEN || i == chunks.len() - 1);
+            let contents = &data[offset as usize..(offset + chunk.len) as usize];
+            assert_eq!(chunk.hash, md5::Md5::digest(contents).to_vec());
+            offset += chunk.len;
+        }
+        assert_eq!(offset, data.len() as u64);
+        assert!(chunks.len() > 4, "{} chunks", chunks.len());
+
+        // Another seed gives other boundaries
+        let mut hasher = ChunkHasher::<md5::Md5>::new(Chunking::ContentDefined { seed: 1 });
+        hasher.update(&data);
+        assert_ne!(hasher.finish(), chunks);
+
+        // No chunks for empty input
+        assert!(hasher.finish().is_empty());
+    }
+
+    #[test]
+    fn test_fixed() {
+        let data = data(10_000);
+        let mut hasher = ChunkHasher::<md5::Md5>::new(Chunking::Fixed { len: 4096 });
+        for piece in data.chunks(1000) {
+            hasher.update(piece);
+        }
+        let chunks = hasher.finish();
+        let lens: Vec<_> = chunks
+            .iter()
+            .map(|chunk| (chunk.offset, chunk.len))
+            .collect();
+        assert_eq!(lens, [(0, 4096), (4096, 4096), (8192, 1808)]);
+        assert_eq!(chunks[2].hash, md5::Md5::digest(&data[8192..]).to_vec());
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let hash = |data: &[u8]| md5::Md5::digest(data).to_vec();
+        let leaves: Vec<_> = [b"a", b"b", b"c"].iter().map(|leaf| hash(*leaf)).collect();
+        let ab = hash(&[leaves[0].clone(), leaves[1].clone()].concat());
+        assert_eq!(
+            merkle_root(&leaves, hash),
+            hash(&[ab, leaves[2].clone()].concat())
+        );
+        assert_eq!(merkle_root(&leaves[..1], hash), leaves[0]);
+        assert_eq!(merkle_root(&[], hash), hash(b""));
+    }
+}
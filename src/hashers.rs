@@ -0,0 +1,114 @@
+//! Adapters for hash functions that don't implement the `digest` traits.
+//!
+//! Wrapping them in `digest::FixedOutputReset` lets `BufHash` handle them
+//! exactly like the RustCrypto hashers.
+
+use digest::{
+    consts::{U4, U8},
+    FixedOutput, FixedOutputReset, HashMarker, Output, OutputSizeUser, Reset, Update,
+};
+
+/// 64-bit XXH3. The digest is written big-endian, which is how `xxhsum -H3` prints it.
+#[derive(Clone, Default)]
+pub struct Xxh3(xxhash_rust::xxh3::Xxh3);
+
+impl HashMarker for Xxh3 {}
+
+impl OutputSizeUser for Xxh3 {
+    type OutputSize = U8;
+}
+
+impl Update for Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+}
+
+impl FixedOutput for Xxh3 {
+    fn finalize_into(self, out: &mut Output<Self>) {
+        out.copy_from_slice(&self.0.digest().to_be_bytes());
+    }
+}
+
+impl Reset for Xxh3 {
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+}
+
+impl FixedOutputReset for Xxh3 {
+    fn finalize_into_reset(&mut self, out: &mut Output<Self>) {
+        out.copy_from_slice(&self.0.digest().to_be_bytes());
+        self.0.reset();
+    }
+}
+
+/// CRC-32 (IEEE), as printed by `crc32` and `7z h`. The digest is written big-endian.
+#[derive(Clone, Default)]
+pub struct Crc32(crc32fast::Hasher);
+
+impl HashMarker for Crc32 {}
+
+impl OutputSizeUser for Crc32 {
+    type OutputSize = U4;
+}
+
+impl Update for Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+}
+
+impl FixedOutput for Crc32 {
+    fn finalize_into(self, out: &mut Output<Self>) {
+        out.copy_from_slice(&self.0.finalize().to_be_bytes());
+    }
+}
+
+impl Reset for Crc32 {
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+}
+
+impl FixedOutputReset for Crc32 {
+    fn finalize_into_reset(&mut self, out: &mut Output<Self>) {
+        out.copy_from_slice(&self.0.clone().finalize().to_be_bytes());
+        self.0.reset();
+    }
+}
+
+/// Adler-32, as used by zlib. The digest is written big-endian, like `Crc32`.
+#[derive(Clone, Default)]
+pub struct Adler32(adler::Adler32);
+
+impl HashMarker for Adler32 {}
+
+impl OutputSizeUser for Adler32 {
+    type OutputSize = U4;
+}
+
+impl Update for Adler32 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.write_slice(data);
+    }
+}
+
+impl FixedOutput for Adler32 {
+    fn finalize_into(self, out: &mut Output<Self>) {
+        out.copy_from_slice(&self.0.checksum().to_be_bytes());
+    }
+}
+
+impl Reset for Adler32 {
+    fn reset(&mut self) {
+        self.0 = adler::Adler32::new();
+    }
+}
+
+impl FixedOutputReset for Adler32 {
+    fn finalize_into_reset(&mut self, out: &mut Output<Self>) {
+        out.copy_from_slice(&self.0.checksum().to_be_bytes());
+        Reset::reset(self);
+    }
+}
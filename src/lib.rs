@@ -0,0 +1,1407 @@
+//! Hashing of files, stdin, and the entries of archives.
+//!
+//! A hasher made by [`BufHashFactory`] sends a [`Record`] with the digest of each file or
+//! archive entry over a channel, and the caller decides how to print or store it. The
+//! `hashall` binary hashes with a pool of these and prints the records with
+//! [`output::Printer`]. [`hash_path`] hashes a single path and returns the records.
+//!
+//! ```no_run
+//! use hashall::{hash_path, Algorithm};
+//!
+//! for record in hash_path("archive.zip".as_ref(), Algorithm::Sha256, true)? {
+//!     println!("{}  {}", hashall::output::to_hex(&record.hash), record.full_path().display());
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::{bail, Result};
+use chunker::{ChunkHasher, Chunking};
+use digest::{generic_array::GenericArray, Digest, FixedOutputReset};
+use flate2::read::{DeflateDecoder, GzDecoder};
+use log::{debug, trace, warn};
+use std::{
+    fs::File,
+    io::{Read, Seek},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+};
+use tar::Archive;
+
+pub mod cancel;
+pub mod checkpoint;
+pub mod chunker;
+mod hashers;
+pub mod manifest;
+mod ostree;
+pub mod output;
+pub mod spill;
+
+pub use output::Record;
+
+/// Alignment of the read buffer and of the read size for `--direct`.
+const DIRECT_ALIGN: usize = 4096;
+
+/// Levels of archives in archives read with `--archive-recursive`, so that a crafted archive
+/// can't recurse without end
+const MAX_NESTING_DEPTH: usize = 16;
+
+#[cfg(target_os = "linux")]
+fn open_direct(path: &Path) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+}
+
+#[cfg(target_os = "linux")]
+fn advise_sequential(file: &File) {
+    use std::os::unix::io::AsRawFd;
+    // Just a hint to increase read-ahead, so failure is ignored.
+    unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_SEQUENTIAL) };
+}
+
+/// Bytes read to tell text from binary
+const SNIFF_LEN: u64 = 8 << 10;
+
+/// Contents selected by `--only-text` and `--only-binary`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentKind {
+    Text,
+    Binary,
+}
+
+/// Whether the first `SNIFF_LEN` bytes of the file look like `kind`. Empty files are text.
+fn is_content_kind(path: &Path, kind: ContentKind) -> std::io::Result<bool> {
+    let mut prefix = Vec::with_capacity(SNIFF_LEN as usize);
+    File::open(path)?.take(SNIFF_LEN).read_to_end(&mut prefix)?;
+    let is_text = content_inspector::inspect(&prefix).is_text();
+    Ok(is_text == (kind == ContentKind::Text))
+}
+
+/// Permission bits, including setuid, setgid, and sticky
+const MODE_MASK: u32 = 0o7777;
+
+/// Permission bits of a file. `None` on platforms without Unix modes.
+fn file_mode(metadata: &std::fs::Metadata) -> Option<u32> {
+    #[cfg(unix)]
+    let mode = Some(std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & MODE_MASK);
+    #[cfg(not(unix))]
+    let mode = {
+        let _ = metadata;
+        None
+    };
+    mode
+}
+
+/// Target path of a symlink, as the raw bytes on Unix and as UTF-8 elsewhere
+fn link_target(path: &Path) -> std::io::Result<Vec<u8>> {
+    let target = std::fs::read_link(path)?;
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(target.as_os_str()).to_vec();
+    #[cfg(not(unix))]
+    let bytes = target.to_string_lossy().into_owned().into_bytes();
+    Ok(bytes)
+}
+
+/// Hash function selected with `--hash`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum Algorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+    #[value(name = "sha3-256")]
+    Sha3_256,
+    #[value(name = "sha3-512")]
+    Sha3_512,
+    Xxh3,
+    Blake3,
+    Crc32,
+    Adler32,
+    /// Command given by --external-hasher
+    #[value(skip)]
+    External,
+}
+
+impl Algorithm {
+    /// Upper-case name used in labelled output formats
+    pub fn label(&self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+            Algorithm::Sha3_256 => "SHA3-256",
+            Algorithm::Sha3_512 => "SHA3-512",
+            Algorithm::Xxh3 => "XXH3",
+            Algorithm::Blake3 => "BLAKE3",
+            Algorithm::Crc32 => "CRC32",
+            Algorithm::Adler32 => "ADLER32",
+            Algorithm::External => "EXTERNAL",
+        }
+    }
+
+    /// Whether the hash is an integer, which can be printed in either byte order
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            Algorithm::Xxh3 | Algorithm::Crc32 | Algorithm::Adler32
+        )
+    }
+
+    /// Algorithm named `label` (case-insensitive), e.g. in BSD-style checksum files
+    pub fn from_label(label: &str) -> Option<Self> {
+        <Algorithm as clap::ValueEnum>::value_variants()
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.label().eq_ignore_ascii_case(label))
+    }
+
+    /// Algorithms that give hashes of `len` hex digits
+    pub fn with_hex_len(len: usize) -> Vec<Self> {
+        <Algorithm as clap::ValueEnum>::value_variants()
+            .iter()
+            .copied()
+            .filter(|algorithm| algorithm.digest(b"").len() * 2 == len)
+            .collect()
+    }
+
+    /// Hasher whose type is chosen at run time, for the other algorithms of `--hash`
+    fn dyn_hasher(&self) -> Box<dyn digest::DynDigest> {
+        match self {
+            Algorithm::Md5 => Box::new(md5::Md5::new()),
+            Algorithm::Sha1 => Box::new(sha1::Sha1::new()),
+            Algorithm::Sha256 => Box::new(sha2::Sha256::new()),
+            Algorithm::Sha512 => Box::new(sha2::Sha512::new()),
+            Algorithm::Sha3_256 => Box::new(sha3::Sha3_256::new()),
+            Algorithm::Sha3_512 => Box::new(sha3::Sha3_512::new()),
+            Algorithm::Xxh3 => Box::new(hashers::Xxh3::default()),
+            Algorithm::Blake3 => Box::new(blake3::Hasher::new()),
+            Algorithm::Crc32 => Box::new(hashers::Crc32::default()),
+            Algorithm::Adler32 => Box::new(hashers::Adler32::default()),
+            Algorithm::External => unreachable!("external hashers only hash files"),
+        }
+    }
+
+    /// Hash of `data` in memory
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::Md5 => md5::Md5::digest(data).to_vec(),
+            Algorithm::Sha1 => sha1::Sha1::digest(data).to_vec(),
+            Algorithm::Sha256 => sha2::Sha256::digest(data).to_vec(),
+            Algorithm::Sha512 => sha2::Sha512::digest(data).to_vec(),
+            Algorithm::Sha3_256 => sha3::Sha3_256::digest(data).to_vec(),
+            Algorithm::Sha3_512 => sha3::Sha3_512::digest(data).to_vec(),
+            Algorithm::Xxh3 => hashers::Xxh3::digest(data).to_vec(),
+            Algorithm::Blake3 => <blake3::Hasher as Digest>::digest(data).to_vec(),
+            Algorithm::Crc32 => hashers::Crc32::digest(data).to_vec(),
+            Algorithm::Adler32 => hashers::Adler32::digest(data).to_vec(),
+            Algorithm::External => unreachable!("external hashers only hash files"),
+        }
+    }
+}
+
+/// Hashes files, archives, and stdin, and sends a `Record` for each file or archive entry.
+/// Paths are relative to the base directory of the hasher.
+pub trait Digester {
+    fn digest_file(&mut self, path: &Path) -> Result<()>;
+    /// Hash each entry of the archive at `path`
+    fn digest_archive(&mut self, path: &Path, archive_type: ArchiveType) -> Result<()>;
+    /// Hash stdin and send its record as `name`
+    fn digest_stdin(&mut self, name: &Path) -> Result<()>;
+    /// Hash the entries with indices in `entries` of the zip archive at `path`, so that an
+    /// archive can be split across hashers (see `zip_len`). Hashers that can't read parts of
+    /// an archive hash all of it for the range starting at 0.
+    fn digest_zip_entries(&mut self, path: &Path, entries: Range<usize>) -> Result<()> {
+        if entries.start == 0 {
+            self.digest_archive(path, ArchiveType::Zip)?;
+        }
+        Ok(())
+    }
+}
+
+/// How files are read and hashed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HashOptions {
+    /// Read files using memory-mapped I/O
+    pub mmap: bool,
+    /// Files smaller than this are read with the buffer even with `mmap`
+    pub mmap_threshold: u64,
+    /// Read files with O_DIRECT (Linux only)
+    pub direct: bool,
+    /// Hash archives that can't be read as plain files
+    pub retry_archive: bool,
+    /// Also hash the entries of archives in archives
+    pub archive_recursive: bool,
+    /// Hash the digest again, e.g. SHA256d
+    pub double: bool,
+    /// Also hash the chunks or blocks of each file
+    pub chunking: Option<Chunking>,
+    /// Skip files whose first bytes don't look like this
+    pub only: Option<ContentKind>,
+    /// Warn about zip entries whose size differs from the size in the header
+    pub verify_sizes: bool,
+    /// Also compute CRC-32 in the same pass and check it against zip headers
+    pub also_crc32: bool,
+    /// Hash the target paths of symlinks instead of following them
+    pub symlink_target: bool,
+    /// Also send the permission bits of files and archive entries
+    pub show_mode: bool,
+    /// Don't send the permission bits of archive entries
+    pub ignore_archive_modes: bool,
+    /// Hash an OSTree file header before the contents (sha256 only)
+    pub ostree: bool,
+    /// Read stdin as a tar stream and copy it to stdout
+    pub tar_tee: bool,
+    /// Hash the entries of stdin if it starts with the signature of a zip archive
+    pub stdin_archive: bool,
+}
+
+/// Hasher that reads files through a buffer with a `Digest` implementation
+pub struct BufHash<H: Digest + FixedOutputReset> {
+    hasher: H,
+    algorithm: Algorithm,
+    hash: digest::Output<H>,
+    base: Arc<Path>,
+    sender: mpsc::Sender<Record>,
+    /// Read buffer, allocated once per worker and algorithm and reused for every file.
+    /// Each read is passed to `Digest::update` as a slice. Hashing the same contents with
+    /// several algorithms must pass that slice to each hasher instead of reading again or
+    /// allocating a buffer per hasher.
+    buffer: Vec<u8>,
+    /// Hashers of the other `--hash` algorithms, updated with the same slices as `hasher`
+    also: Vec<(Algorithm, Box<dyn digest::DynDigest>)>,
+    options: HashOptions,
+    /// Bytes hashed since the last record
+    size: u64,
+    chunks: Option<ChunkHasher<H>>,
+    crc32: Option<crc32fast::Hasher>,
+    /// Permission bits of the next record
+    mode: Option<u32>,
+    /// Number of archives in archives being read, for `HashOptions::archive_recursive`
+    depth: usize,
+}
+
+impl<H> BufHash<H>
+where
+    H: Digest + FixedOutputReset,
+    <H as digest::OutputSizeUser>::OutputSize: std::ops::Add,
+    <<H as digest::OutputSizeUser>::OutputSize as std::ops::Add>::Output:
+        digest::generic_array::ArrayLength<u8>,
+{
+    pub fn new(
+        algorithm: Algorithm,
+        buffer_size: usize,
+        base: Arc<Path>,
+        sender: mpsc::Sender<Record>,
+        options: HashOptions,
+        also: &[Algorithm],
+    ) -> Self {
+        let hasher = H::new();
+        let hash = GenericArray::default();
+        let buffer = if options.direct {
+            // Leave room to align the start of the buffer
+            vec![0; buffer_size.next_multiple_of(DIRECT_ALIGN) + DIRECT_ALIGN]
+        } else {
+            vec![0; buffer_size]
+        };
+        BufHash {
+            hasher,
+            algorithm,
+            hash,
+            base,
+            sender,
+            buffer,
+            also: also
+                .iter()
+                .map(|&algorithm| (algorithm, algorithm.dyn_hasher()))
+                .collect(),
+            options,
+            size: 0,
+            chunks: options.chunking.map(ChunkHasher::new),
+            crc32: options.also_crc32.then(crc32fast::Hasher::new),
+            mode: None,
+            depth: 0,
+        }
+    }
+
+    /// Path to open `path`, which is relative to the base directory
+    fn fs_path(&self, path: &Path) -> PathBuf {
+        self.base.join(path)
+    }
+
+    /// Hash `readable` and send the record. `archive` is the archive that contains `path`.
+    /// Returns the number of bytes read and the CRC-32 with `also_crc32`.
+    fn _digest_send<R: Read>(
+        &mut self,
+        archive: Option<&Path>,
+        path: &Path,
+        mut readable: R,
+    ) -> Result<(u64, Option<u32>)> {
+        loop {
+            let n = readable.read(&mut self.buffer)?;
+            if n == 0 {
+                break;
+            }
+            Digest::update(&mut self.hasher, &self.buffer[..n]);
+            for (_, hasher) in &mut self.also {
+                hasher.update(&self.buffer[..n]);
+            }
+            if let Some(chunks) = &mut self.chunks {
+                chunks.update(&self.buffer[..n]);
+            }
+            if let Some(crc32) = &mut self.crc32 {
+                crc32.update(&self.buffer[..n]);
+            }
+            self.size += n as u64;
+        }
+        let size = self.size;
+        let crc32 = self._finalize_send(archive, path)?;
+        Ok((size, crc32))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn _digest_direct(&mut self, path: &Path, mut file: File) -> Result<()> {
+        let offset = self.buffer.as_ptr().align_offset(DIRECT_ALIGN);
+        let len = (self.buffer.len() - offset) / DIRECT_ALIGN * DIRECT_ALIGN;
+        let fs_path = self.fs_path(path);
+        let buffer = &mut self.buffer[offset..offset + len];
+        let mut direct = true;
+        loop {
+            let n = match file.read(buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                // After a short read the offset is unaligned, which some file systems reject.
+                // The rest is read buffered from the same offset.
+                Err(e) if direct && e.raw_os_error() == Some(libc::EINVAL) => {
+                    debug!(
+                        "{}: O_DIRECT read failed ({}). Reading the rest buffered.",
+                        path.display(),
+                        e
+                    );
+                    let position = file.stream_position()?;
+                    file = File::open(&fs_path)?;
+                    file.seek(std::io::SeekFrom::Start(position))?;
+                    direct = false;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            Digest::update(&mut self.hasher, &buffer[..n]);
+            for (_, hasher) in &mut self.also {
+                hasher.update(&buffer[..n]);
+            }
+            if let Some(chunks) = &mut self.chunks {
+                chunks.update(&buffer[..n]);
+            }
+            if let Some(crc32) = &mut self.crc32 {
+                crc32.update(&buffer[..n]);
+            }
+            self.size += n as u64;
+        }
+        self._finalize_send(None, path)?;
+        Ok(())
+    }
+
+    /// `result`, after discarding what was hashed for a record that failed, so that it
+    /// doesn't end up in the next one
+    fn reset_on_error(&mut self, result: Result<()>) -> Result<()> {
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    fn _digest_file(&mut self, path: &Path) -> Result<()> {
+        if self.options.symlink_target && self.fs_path(path).is_symlink() {
+            let target = link_target(&self.fs_path(path))?;
+            return self._digest_send(None, path, target.as_slice()).map(|_| ());
+        }
+        if let Some(kind) = self.options.only {
+            if !is_content_kind(&self.fs_path(path), kind)? {
+                debug!("{}: Skipped (not {:?})", path.display(), kind);
+                return Ok(());
+            }
+        }
+        if self.options.show_mode {
+            self.mode = file_mode(&std::fs::metadata(self.fs_path(path))?);
+        }
+        #[cfg(target_os = "linux")]
+        if self.options.direct {
+            match open_direct(&self.fs_path(path)) {
+                Ok(file) => return self._digest_direct(path, file),
+                Err(e) => {
+                    static FALLBACK: std::sync::Once = std::sync::Once::new();
+                    FALLBACK.call_once(|| {
+                        warn!(
+                            "{}: O_DIRECT is not supported ({}). Falling back to buffered read.",
+                            path.display(),
+                            e
+                        )
+                    });
+                }
+            }
+        }
+        let file = File::open(self.fs_path(path))?;
+        #[cfg(target_os = "linux")]
+        advise_sequential(&file);
+        if self.options.mmap
+            && file
+                .metadata()
+                .is_ok_and(|metadata| metadata.len() >= self.options.mmap_threshold)
+        {
+            // Safety: the map is dropped before returning. Like with buffered reads,
+            // modifying the file while it is being hashed gives a meaningless hash.
+            match unsafe { memmap2::Mmap::map(&file) } {
+                Ok(mmap) => {
+                    Digest::update(&mut self.hasher, &mmap);
+                    for (_, hasher) in &mut self.also {
+                        hasher.update(&mmap);
+                    }
+                    if let Some(chunks) = &mut self.chunks {
+                        chunks.update(&mmap);
+                    }
+                    if let Some(crc32) = &mut self.crc32 {
+                        crc32.update(&mmap);
+                    }
+                    self.size = mmap.len() as u64;
+                    self._finalize_send(None, path)?;
+                    return Ok(());
+                }
+                Err(e) => debug!("mmap failed for {}: {}", path.display(), e),
+            }
+        }
+        self._digest_send(None, path, file)?;
+        Ok(())
+    }
+
+    fn _digest_archive(&mut self, path: &Path, archive_type: ArchiveType) -> Result<()> {
+        debug!(target: "hashall::archive", "{}: reading as {:?}", path.display(), archive_type);
+        if !self.options.retry_archive {
+            return match archive_type {
+                ArchiveType::Zip => self.digest_zip(path),
+                archive_type => self.digest_tar(path, archive_type),
+            };
+        }
+        // The records of the entries are held back until the whole archive is read, so that
+        // an archive that fails is printed only as a file
+        let (held_sender, held) = mpsc::channel();
+        let sender = std::mem::replace(&mut self.sender, held_sender);
+        let result = match archive_type {
+            ArchiveType::Zip => self.digest_zip(path),
+            archive_type => self.digest_tar(path, archive_type),
+        };
+        self.sender = sender;
+        match result {
+            Ok(()) => {
+                for record in held.try_iter() {
+                    self.sender
+                        .send(record)
+                        .map_err(|_| anyhow::anyhow!("Printer disconnected"))?;
+                }
+                Ok(())
+            }
+            Err(e) => {
+                warn!(
+                    "{}: Failed to read as an archive ({}). Hashing as a file.",
+                    path.display(),
+                    e
+                );
+                self.reset();
+                self.digest_file(path)
+            }
+        }
+    }
+
+    /// Discard what was hashed since the last record, e.g. after a read error
+    fn reset(&mut self) {
+        Digest::reset(&mut self.hasher);
+        for (_, hasher) in &mut self.also {
+            hasher.reset();
+        }
+        if let Some(chunks) = &mut self.chunks {
+            chunks.reset();
+        }
+        if let Some(crc32) = &mut self.crc32 {
+            crc32.reset();
+        }
+        self.size = 0;
+        self.mode = None;
+    }
+
+    /// Send the record and return the CRC-32 with `also_crc32`.
+    fn _finalize_send(&mut self, archive: Option<&Path>, path: &Path) -> Result<Option<u32>> {
+        digest::FixedOutputReset::finalize_into_reset(&mut self.hasher, &mut self.hash);
+        if self.options.double {
+            Digest::update(&mut self.hasher, &self.hash);
+            digest::FixedOutputReset::finalize_into_reset(&mut self.hasher, &mut self.hash);
+        }
+        let also_hashes = self
+            .also
+            .iter_mut()
+            .map(|(algorithm, hasher)| {
+                let mut hash = hasher.finalize_reset();
+                if self.options.double {
+                    hasher.update(&hash);
+                    hash = hasher.finalize_reset();
+                }
+                (*algorithm, hash.into_vec())
+            })
+            .collect();
+        let crc32 = self
+            .crc32
+            .as_mut()
+            .map(|crc32| std::mem::take(crc32).finalize());
+        self.sender
+            .send(Record {
+                archive: archive.map(Path::to_path_buf),
+                path: path.to_path_buf(),
+                hash: self.hash.to_vec(),
+                algorithm: self.algorithm,
+                size: Some(std::mem::take(&mut self.size)),
+                chunks: self
+                    .chunks
+                    .as_mut()
+                    .map(ChunkHasher::finish)
+                    .unwrap_or_default(),
+                crc32,
+                mode: self.mode.take(),
+                also_hashes,
+                seq: 0,
+            })
+            .map_err(|_| anyhow::anyhow!("Printer disconnected"))?;
+        Ok(crc32)
+    }
+
+    fn digest_zip(&mut self, path: &Path) -> Result<()> {
+        let file = File::open(self.fs_path(path))?;
+        self._digest_zip(path, file, 0..usize::MAX)
+    }
+
+    /// Hash the entries with indices in `entries` of the zip archive read from `reader`.
+    /// `path` is the path of the archive in the records, e.g. `outer.tar/inner.zip` for a
+    /// nested archive.
+    fn _digest_zip<R: Read + Seek>(
+        &mut self,
+        path: &Path,
+        reader: R,
+        entries: Range<usize>,
+    ) -> Result<()> {
+        let mut archive = zip::ZipArchive::new(reader)?;
+        let mut n_files = 0;
+        for i in entries.start..entries.end.min(archive.len()) {
+            if cancel::should_stop() {
+                break;
+            }
+            // The zip crate fails on a CRC mismatch after all the data is read. To report the
+            // mismatch instead, read stored and deflated entries raw and inflate them here.
+            // Encrypted entries fail in `by_index` and must not be read raw, and neither are
+            // nested archives.
+            let raw = self.crc32.is_some() && archive.by_index(i).is_ok() && {
+                let file = archive.by_index_raw(i)?;
+                matches!(
+                    file.compression(),
+                    zip::CompressionMethod::Stored | zip::CompressionMethod::Deflated
+                ) && self.nested_archive(&path.join(file.name())).is_none()
+            };
+            let mut file = if raw {
+                archive.by_index_raw(i)?
+            } else {
+                archive.by_index(i)?
+            };
+            if file.is_dir() {
+                continue;
+            }
+            let zip_path = PathBuf::from(file.name());
+            if let Some(archive_type) = self.nested_archive(&path.join(&zip_path)) {
+                self._digest_nested(&path.join(&zip_path), archive_type, &mut file)?;
+                continue;
+            }
+            if self.options.show_mode && !self.options.ignore_archive_modes {
+                self.mode = file.unix_mode().map(|mode| mode & MODE_MASK);
+            }
+            let header_size = file.size();
+            let header_crc32 = file.crc32();
+            trace!(
+                target: "hashall::archive",
+                "{}: {} ({} bytes)",
+                path.display(),
+                zip_path.display(),
+                header_size
+            );
+            let (size, crc32) = if raw && file.compression() == zip::CompressionMethod::Deflated {
+                self._digest_send(Some(path), &zip_path, DeflateDecoder::new(&mut file))?
+            } else {
+                self._digest_send(Some(path), &zip_path, &mut file)?
+            };
+            if let Some(crc32) = crc32.filter(|&crc32| crc32 != header_crc32) {
+                warn!(
+                    "{}[{}]: CRC32 mismatch (header {:08x}, actual {:08x})",
+                    path.display(),
+                    zip_path.display(),
+                    header_crc32,
+                    crc32
+                );
+            }
+            if self.options.verify_sizes && size != header_size {
+                warn!(
+                    "{}[{}]: size mismatch (header {}, actual {})",
+                    path.display(),
+                    zip_path.display(),
+                    header_size,
+                    size
+                );
+            }
+            n_files += 1;
+        }
+        debug!(target: "hashall::archive", "{}: {} files", path.display(), n_files);
+        Ok(())
+    }
+
+    /// Entries are streamed through the read buffer, so memory use doesn't depend on entry sizes.
+    /// Only the decompressors keep a window (32 KiB for gzip, < 4 MiB for bzip2,
+    /// the dictionary for xz, and at most 128 MiB for zstd).
+    fn _digest_tar<R: Read>(&mut self, path: &Path, readable: R) -> Result<()> {
+        let mut archive = Archive::new(readable);
+        let mut n_files = 0;
+        for file in archive.entries()? {
+            if cancel::should_stop() {
+                break;
+            }
+            let mut file = file?;
+            if file.header().entry_type().is_dir() {
+                continue;
+            }
+            let tar_path = file.path()?.into_owned();
+            if let Some(archive_type) = self.nested_archive(&path.join(&tar_path)) {
+                self._digest_nested(&path.join(&tar_path), archive_type, &mut file)?;
+                continue;
+            }
+            if self.options.show_mode && !self.options.ignore_archive_modes {
+                self.mode = file.header().mode().ok().map(|mode| mode & MODE_MASK);
+            }
+            trace!(
+                target: "hashall::archive",
+                "{}: {} ({} bytes)",
+                path.display(),
+                tar_path.display(),
+                file.size()
+            );
+            self._digest_send(Some(path), &tar_path, &mut file)?;
+            n_files += 1;
+        }
+        debug!(target: "hashall::archive", "{}: {} files", path.display(), n_files);
+        Ok(())
+    }
+
+    fn digest_tar(&mut self, path: &Path, archive_type: ArchiveType) -> Result<()> {
+        let file = File::open(self.fs_path(path))?;
+        self._digest_tar(path, tar_decoder(archive_type, file)?)
+    }
+
+    /// Type of the archive at `path` in an archive if its entries are hashed too. Archives
+    /// nested deeper than `MAX_NESTING_DEPTH` are hashed as files.
+    fn nested_archive(&self, path: &Path) -> Option<ArchiveType> {
+        if !self.options.archive_recursive {
+            return None;
+        }
+        let archive_type = ArchiveType::from_path(path)?;
+        if self.depth >= MAX_NESTING_DEPTH {
+            static TOO_DEEP: std::sync::Once = std::sync::Once::new();
+            TOO_DEEP.call_once(|| {
+                warn!(
+                    "{}: archives nested more than {} deep are hashed as files",
+                    path.display(),
+                    MAX_NESTING_DEPTH
+                )
+            });
+            return None;
+        }
+        Some(archive_type)
+    }
+
+    /// Hash the entries of the archive in an archive, read from `reader`. `path` is the
+    /// archive path joined with the path of the entry.
+    fn _digest_nested(
+        &mut self,
+        path: &Path,
+        archive_type: ArchiveType,
+        reader: &mut dyn Read,
+    ) -> Result<()> {
+        debug!(target: "hashall::archive", "{}: reading as {:?}", path.display(), archive_type);
+        self.depth += 1;
+        let result = match archive_type {
+            ArchiveType::Zip => {
+                spool(reader).and_then(|file| self._digest_zip(path, file, 0..usize::MAX))
+            }
+            archive_type => {
+                tar_decoder(archive_type, reader).and_then(|reader| self._digest_tar(path, reader))
+            }
+        };
+        self.depth -= 1;
+        result
+    }
+}
+
+/// Temporary file with the contents of `reader`, for a zip in an archive. Zip needs random
+/// access, so the entry is copied first.
+fn spool(reader: &mut dyn Read) -> Result<File> {
+    let mut file = tempfile::tempfile()?;
+    std::io::copy(reader, &mut file)?;
+    file.rewind()?;
+    Ok(file)
+}
+
+/// Decompressed stream of a tar archive of `archive_type`, which must not be `Zip`
+fn tar_decoder<'a, R: Read + 'a>(
+    archive_type: ArchiveType,
+    reader: R,
+) -> Result<Box<dyn Read + 'a>> {
+    Ok(match archive_type {
+        ArchiveType::Zip => unreachable!("zip is not a tar stream"),
+        ArchiveType::Tar => Box::new(reader),
+        ArchiveType::TarGz => Box::new(GzDecoder::new(reader)),
+        ArchiveType::TarZstd => {
+            let mut decoder = zstd::Decoder::new(reader)?;
+            // Refuse frames that need a window larger than 128 MiB (zstd's default limit)
+            decoder.window_log_max(27)?;
+            Box::new(decoder)
+        }
+        ArchiveType::TarBz2 => Box::new(bzip2::read::BzDecoder::new(reader)),
+        ArchiveType::TarXz => Box::new(xz2::read::XzDecoder::new(reader)),
+    })
+}
+
+/// Hashes files with an external command.
+///
+/// The contents of each file are piped to the command's stdin. The command must print the hash
+/// in hex as the first word of its stdout (e.g. `sha1sum` prints `<hash>  -`) and exit with 0.
+struct ExternalHash {
+    command: Arc<str>,
+    base: Arc<Path>,
+    sender: mpsc::Sender<Record>,
+}
+
+impl ExternalHash {
+    fn shell(&self) -> std::process::Command {
+        let (shell, flag) = if cfg!(windows) {
+            ("cmd", "/C")
+        } else {
+            ("sh", "-c")
+        };
+        let mut command = std::process::Command::new(shell);
+        command.args([flag, &self.command]);
+        command
+    }
+
+    /// Run the command with `stdin` and send the hash it prints as the hash of `path`
+    fn run(&self, path: &Path, stdin: std::process::Stdio, size: Option<u64>) -> Result<()> {
+        let output = self
+            .shell()
+            .stdin(stdin)
+            .stderr(std::process::Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            bail!(
+                "{}: {} failed ({})",
+                path.display(),
+                self.command,
+                output.status
+            );
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let hash = stdout
+            .split_whitespace()
+            .next()
+            .and_then(output::from_hex)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{}: {} printed no hash in hex: {:?}",
+                    path.display(),
+                    self.command,
+                    stdout
+                )
+            })?;
+        self.sender
+            .send(Record {
+                archive: None,
+                path: path.to_path_buf(),
+                hash,
+                algorithm: Algorithm::External,
+                size,
+                chunks: Vec::new(),
+                crc32: None,
+                mode: None,
+                also_hashes: Vec::new(),
+                seq: 0,
+            })
+            .map_err(|_| anyhow::anyhow!("Printer disconnected"))
+    }
+}
+
+impl Digester for ExternalHash {
+    fn digest_file(&mut self, path: &Path) -> Result<()> {
+        let file = File::open(self.base.join(path))?;
+        let size = file.metadata()?.len();
+        self.run(path, file.into(), Some(size))
+    }
+
+    fn digest_stdin(&mut self, name: &Path) -> Result<()> {
+        self.run(name, std::process::Stdio::inherit(), None)
+    }
+
+    fn digest_archive(&mut self, path: &Path, _archive_type: ArchiveType) -> Result<()> {
+        bail!(
+            "{}: archives can't be hashed with an external command",
+            path.display()
+        )
+    }
+}
+
+/// Computes the checksums of OSTree content objects: the SHA-256 of the file header
+/// described in `ostree` followed by the contents. Symlinks are hashed as symlinks with
+/// `--hash-symlink-target` and followed otherwise.
+struct OstreeHash(BufHash<sha2::Sha256>);
+
+impl Digester for OstreeHash {
+    #[cfg(unix)]
+    fn digest_file(&mut self, path: &Path) -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let fs_path = self.0.fs_path(path);
+        let symlink = self.0.options.symlink_target && fs_path.is_symlink();
+        let metadata = if symlink {
+            std::fs::symlink_metadata(&fs_path)?
+        } else {
+            std::fs::metadata(&fs_path)?
+        };
+        let target = if symlink {
+            link_target(&fs_path)?
+        } else {
+            Vec::new()
+        };
+        // Skipped files must not leave their header in the hasher
+        if let (Some(kind), false) = (self.0.options.only, symlink) {
+            if !is_content_kind(&fs_path, kind)? {
+                debug!("{}: Skipped (not {:?})", path.display(), kind);
+                return Ok(());
+            }
+        }
+        let header = ostree::file_header(metadata.uid(), metadata.gid(), metadata.mode(), &target);
+        Digest::update(&mut self.0.hasher, header);
+        if symlink {
+            self.0._finalize_send(None, path)?;
+            return Ok(());
+        }
+        let result = self.0.digest_file(path);
+        if result.is_err() {
+            self.0.reset();
+        }
+        result
+    }
+
+    #[cfg(not(unix))]
+    fn digest_file(&mut self, path: &Path) -> Result<()> {
+        bail!(
+            "{}: OSTree checksums need Unix owners and modes",
+            path.display()
+        )
+    }
+
+    fn digest_archive(&mut self, path: &Path, _archive_type: ArchiveType) -> Result<()> {
+        bail!(
+            "{}: OSTree checksums of archive entries are not supported",
+            path.display()
+        )
+    }
+
+    fn digest_stdin(&mut self, _name: &Path) -> Result<()> {
+        bail!("OSTree checksums of stdin are not supported")
+    }
+}
+
+/// Creates the hasher of each algorithm with the same options, e.g. one per worker thread
+#[derive(Debug, Clone)]
+pub struct BufHashFactory {
+    buffer_size: usize,
+    options: HashOptions,
+    /// Command for `Algorithm::External`
+    external: Option<Arc<str>>,
+    /// Algorithms computed in the same read as the one of each input
+    also: Vec<Algorithm>,
+}
+
+impl BufHashFactory {
+    pub fn new(
+        buffer_size: usize,
+        options: HashOptions,
+        external: Option<Arc<str>>,
+        also: Vec<Algorithm>,
+    ) -> Self {
+        BufHashFactory {
+            buffer_size,
+            options,
+            external,
+            also,
+        }
+    }
+    pub fn create(
+        &self,
+        algorithm: Algorithm,
+        base: Arc<Path>,
+        sender: mpsc::Sender<Record>,
+    ) -> Box<dyn Digester> {
+        match algorithm {
+            Algorithm::External => Box::new(ExternalHash {
+                command: self
+                    .external
+                    .clone()
+                    .expect("external hasher without a command"),
+                base,
+                sender,
+            }),
+            Algorithm::Md5 => Box::new(self.create_hasher::<md5::Md5>(algorithm, base, sender)),
+            Algorithm::Sha1 => Box::new(self.create_hasher::<sha1::Sha1>(algorithm, base, sender)),
+            Algorithm::Sha256 if self.options.ostree => Box::new(OstreeHash(
+                self.create_hasher::<sha2::Sha256>(algorithm, base, sender),
+            )),
+            Algorithm::Sha256 => {
+                Box::new(self.create_hasher::<sha2::Sha256>(algorithm, base, sender))
+            }
+            Algorithm::Sha512 => {
+                Box::new(self.create_hasher::<sha2::Sha512>(algorithm, base, sender))
+            }
+            Algorithm::Sha3_256 => {
+                Box::new(self.create_hasher::<sha3::Sha3_256>(algorithm, base, sender))
+            }
+            Algorithm::Sha3_512 => {
+                Box::new(self.create_hasher::<sha3::Sha3_512>(algorithm, base, sender))
+            }
+            Algorithm::Xxh3 => {
+                Box::new(self.create_hasher::<hashers::Xxh3>(algorithm, base, sender))
+            }
+            Algorithm::Blake3 => {
+                Box::new(self.create_hasher::<blake3::Hasher>(algorithm, base, sender))
+            }
+            Algorithm::Crc32 => {
+                Box::new(self.create_hasher::<hashers::Crc32>(algorithm, base, sender))
+            }
+            Algorithm::Adler32 => {
+                Box::new(self.create_hasher::<hashers::Adler32>(algorithm, base, sender))
+            }
+        }
+    }
+    fn create_hasher<H>(
+        &self,
+        algorithm: Algorithm,
+        base: Arc<Path>,
+        sender: mpsc::Sender<Record>,
+    ) -> BufHash<H>
+    where
+        H: Digest + FixedOutputReset,
+        <H as digest::OutputSizeUser>::OutputSize: std::ops::Add,
+        <<H as digest::OutputSizeUser>::OutputSize as std::ops::Add>::Output:
+            digest::generic_array::ArrayLength<u8>,
+    {
+        BufHash::<H>::new(
+            algorithm,
+            self.buffer_size,
+            base,
+            sender,
+            self.options,
+            &self.also,
+        )
+    }
+}
+
+/// Archive formats read with `--archive`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveType {
+    Zip,
+    Tar,
+    TarGz,
+    TarZstd,
+    TarBz2,
+    TarXz,
+}
+
+impl ArchiveType {
+    /// Archive type by the extensions of the file name. Matching is case-sensitive.
+    ///
+    /// 1. The last extension: `zip`, `tar`, `tgz`, `taz`, `tz2`, `tbz`, `tbz2`, or `txz`
+    /// 2. The last two extensions: `tar.gz`, `tar.zst`, `tar.bz2`, or `tar.xz`
+    /// 3. Otherwise, not an archive (e.g. `backup.tar.gz.txt`, `my.tar.custom`)
+    ///
+    /// Extensions follow the dots after a non-empty stem, so a leading dot is part of the
+    /// name (`.tar` has no extension and `.tar.gz` has only `gz`).
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy();
+        let name = name.strip_prefix('.').unwrap_or(&name);
+        // Extensions in reverse order followed by the stem
+        let parts: Vec<&str> = name.rsplitn(3, '.').collect();
+
+        match parts.as_slice() {
+            ["zip", _, ..] => Some(ArchiveType::Zip),
+            ["tar", _, ..] => Some(ArchiveType::Tar),
+            ["tgz" | "taz", _, ..] => Some(ArchiveType::TarGz),
+            ["tz2" | "tbz" | "tbz2", _, ..] => Some(ArchiveType::TarBz2),
+            ["txz", _, ..] => Some(ArchiveType::TarXz),
+            ["gz", "tar", _] => Some(ArchiveType::TarGz),
+            ["zst", "tar", _] => Some(ArchiveType::TarZstd),
+            ["bz2", "tar", _] => Some(ArchiveType::TarBz2),
+            ["xz", "tar", _] => Some(ArchiveType::TarXz),
+            _ => None,
+        }
+    }
+
+    /// Archive type by the first bytes of the file, for names without an archive extension.
+    ///
+    /// Zip files start with `PK`, and tar files have `ustar` at offset 257 of the first header.
+    /// Files starting with the magic bytes of gzip, zstd, bzip2, or xz are archives only if they
+    /// decompress to a tar, so a compressed single file is not one.
+    pub fn sniff(path: &Path) -> std::io::Result<Option<Self>> {
+        let mut header = Vec::with_capacity(TAR_BLOCK_LEN as usize);
+        File::open(path)?
+            .take(TAR_BLOCK_LEN)
+            .read_to_end(&mut header)?;
+        let archive_type = match header.as_slice() {
+            header if is_zip_signature(header) => return Ok(Some(ArchiveType::Zip)),
+            [0x1f, 0x8b, ..] => ArchiveType::TarGz,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => ArchiveType::TarZstd,
+            [b'B', b'Z', b'h', ..] => ArchiveType::TarBz2,
+            [0xfd, b'7', b'z', b'X', b'Z', 0, ..] => ArchiveType::TarXz,
+            header if is_tar_header(header) => return Ok(Some(ArchiveType::Tar)),
+            _ => return Ok(None),
+        };
+        let mut header = Vec::with_capacity(TAR_BLOCK_LEN as usize);
+        let decoded = tar_decoder(archive_type, File::open(path)?).and_then(|decoder| {
+            decoder.take(TAR_BLOCK_LEN).read_to_end(&mut header)?;
+            Ok(())
+        });
+        Ok((decoded.is_ok() && is_tar_header(&header)).then_some(archive_type))
+    }
+}
+
+/// Number of entries (including directories) of the zip archive at `path`, read from its
+/// central directory
+pub fn zip_len(path: &Path) -> Result<usize> {
+    Ok(zip::ZipArchive::new(File::open(path)?)?.len())
+}
+
+/// Whether `bytes` starts with the signature of a zip archive: a local file header, or the
+/// end of the central directory of an empty archive
+fn is_zip_signature(bytes: &[u8]) -> bool {
+    matches!(bytes, [b'P', b'K', 3, 4, ..] | [b'P', b'K', 5, 6, ..])
+}
+
+/// Size of a tar header block
+const TAR_BLOCK_LEN: u64 = 512;
+
+/// Whether `block` starts with a POSIX (ustar) or GNU tar header
+fn is_tar_header(block: &[u8]) -> bool {
+    block.get(257..262) == Some(b"ustar")
+}
+
+impl<H> Digester for BufHash<H>
+where
+    H: Digest + FixedOutputReset,
+    <H as digest::OutputSizeUser>::OutputSize: std::ops::Add,
+    <<H as digest::OutputSizeUser>::OutputSize as std::ops::Add>::Output:
+        digest::generic_array::ArrayLength<u8>,
+{
+    fn digest_file(&mut self, path: &Path) -> Result<()> {
+        let result = self._digest_file(path);
+        self.reset_on_error(result)
+    }
+
+    fn digest_archive(&mut self, path: &Path, archive_type: ArchiveType) -> Result<()> {
+        let result = self._digest_archive(path, archive_type);
+        self.reset_on_error(result)
+    }
+
+    fn digest_zip_entries(&mut self, path: &Path, entries: Range<usize>) -> Result<()> {
+        debug!(target: "hashall::archive", "{}: reading entries {:?}", path.display(), entries);
+        let result = File::open(self.fs_path(path))
+            .map_err(anyhow::Error::from)
+            .and_then(|file| self._digest_zip(path, file, entries));
+        self.reset_on_error(result)
+    }
+
+    fn digest_stdin(&mut self, name: &Path) -> Result<()> {
+        if self.options.tar_tee {
+            let mut tee = Tee {
+                reader: std::io::stdin().lock(),
+                writer: std::io::stdout().lock(),
+            };
+            self._digest_tar(name, &mut tee)?;
+            tee.finish()?;
+            return Ok(());
+        }
+        let mut stdin = std::io::stdin().lock();
+        if self.options.stdin_archive {
+            let mut signature = Vec::with_capacity(4);
+            (&mut stdin).take(4).read_to_end(&mut signature)?;
+            let mut stdin = signature.as_slice().chain(stdin);
+            if is_zip_signature(&signature) {
+                // Copied to a temporary file like a zip in an archive
+                return self._digest_nested(name, ArchiveType::Zip, &mut stdin);
+            }
+            self._digest_send(None, name, stdin)?;
+            return Ok(());
+        }
+        self._digest_send(None, name, stdin)?;
+        Ok(())
+    }
+}
+
+/// Copies the bytes read from `reader` to `writer`, for `--tar-tee`.
+///
+/// Only the bytes that the tar reader consumes pass through `read`, so `finish` copies the
+/// rest (the end-of-archive blocks, the padding to the record size, and anything after).
+/// The output is then byte-identical to the input.
+struct Tee<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: Read, W: std::io::Write> Read for Tee<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.writer.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+impl<R: Read, W: std::io::Write> Tee<R, W> {
+    fn finish(mut self) -> std::io::Result<()> {
+        std::io::copy(&mut self.reader, &mut self.writer)?;
+        self.writer.flush()
+    }
+}
+
+/// Size of the read buffer of `hash_path`, the default of `--buffer`
+const DEFAULT_BUFFER_SIZE: usize = 1 << 20;
+
+/// Hash the file at `path` with the default options and return its record. With `archive`,
+/// the entries of an archive (by `ArchiveType::from_path`) are hashed instead, in the order
+/// of the archive.
+pub fn hash_path(path: &Path, algorithm: Algorithm, archive: bool) -> Result<Vec<Record>> {
+    if algorithm == Algorithm::External {
+        bail!("External hashers need a command. Use BufHashFactory instead.");
+    }
+    let factory = BufHashFactory::new(
+        DEFAULT_BUFFER_SIZE,
+        HashOptions::default(),
+        None,
+        Vec::new(),
+    );
+    let (sender, receiver) = mpsc::channel();
+    let mut hasher = factory.create(algorithm, Arc::from(Path::new("")), sender);
+    match ArchiveType::from_path(path).filter(|_| archive) {
+        Some(archive_type) => hasher.digest_archive(path, archive_type)?,
+        None => hasher.digest_file(path)?,
+    }
+    drop(hasher);
+    Ok(receiver.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_hex_len() {
+        assert_eq!(Algorithm::with_hex_len(16), [Algorithm::Xxh3]);
+        assert_eq!(Algorithm::with_hex_len(32), [Algorithm::Md5]);
+        assert_eq!(Algorithm::with_hex_len(40), [Algorithm::Sha1]);
+        assert_eq!(
+            Algorithm::with_hex_len(64),
+            [Algorithm::Sha256, Algorithm::Sha3_256, Algorithm::Blake3]
+        );
+        assert_eq!(
+            Algorithm::with_hex_len(8),
+            [Algorithm::Crc32, Algorithm::Adler32]
+        );
+        assert!(Algorithm::with_hex_len(10).is_empty());
+    }
+
+    #[test]
+    fn test_sha3() {
+        // FIPS 202 examples
+        assert_eq!(
+            output::to_hex(&Algorithm::Sha3_256.digest(b"abc")),
+            "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"
+        );
+        assert_eq!(
+            output::to_hex(&Algorithm::Sha3_512.digest(b"abc")),
+            "b751850b1a57168a5693cd924b6b096e08f621827444f70d884f5d0240d2712e\
+             10e116e9192af3c91a7ec57647e3934057340b4cf408d5a56592f8274eec53f0"
+        );
+        assert_eq!(Algorithm::from_label("sha3-256"), Some(Algorithm::Sha3_256));
+    }
+
+    #[test]
+    fn test_hash_path() {
+        let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+        let records = hash_path(&data.join("file.txt"), Algorithm::Md5, false).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].path, data.join("file.txt"));
+        assert_eq!(
+            output::to_hex(&records[0].hash),
+            "ac175545a9b0f6da0d5c03f5135563d8"
+        );
+
+        let records = hash_path(&data.join("archive.zip"), Algorithm::Md5, true).unwrap();
+        let entries: Vec<_> = records
+            .iter()
+            .map(|record| (record.full_path(), output::to_hex(&record.hash)))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (
+                    data.join("archive.zip/file.txt"),
+                    "ac175545a9b0f6da0d5c03f5135563d8".to_string()
+                ),
+                (
+                    data.join("archive.zip/directory/file.txt"),
+                    "6657b6593444bd9a13d0131d47bef4f5".to_string()
+                ),
+                (
+                    data.join("archive.zip/.hidden_file.txt"),
+                    "28f9f80606380557b3a5034417227add".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_archive_type() {
+        assert!(ArchiveType::from_path(Path::new("file.txt")).is_none());
+        assert_eq!(
+            ArchiveType::from_path(Path::new("archive.zip")).unwrap(),
+            ArchiveType::Zip
+        );
+        assert_eq!(
+            ArchiveType::from_path(Path::new("archive.tar")).unwrap(),
+            ArchiveType::Tar
+        );
+
+        assert_eq!(
+            ArchiveType::from_path(Path::new("archive.tar.gz")).unwrap(),
+            ArchiveType::TarGz
+        );
+        assert_eq!(
+            ArchiveType::from_path(Path::new("archive.tgz")).unwrap(),
+            ArchiveType::TarGz
+        );
+        assert_eq!(
+            ArchiveType::from_path(Path::new("archive.taz")).unwrap(),
+            ArchiveType::TarGz
+        );
+        assert!(ArchiveType::from_path(Path::new("archive.gz")).is_none(),);
+        assert!(ArchiveType::from_path(Path::new("archive.tar.gz.txt")).is_none(),);
+        // Directory inputs don't reach this function
+        assert_eq!(
+            ArchiveType::from_path(Path::new("archive.tar.gz/")).unwrap(),
+            ArchiveType::TarGz
+        );
+
+        assert_eq!(
+            ArchiveType::from_path(Path::new("archive.tar.zst")).unwrap(),
+            ArchiveType::TarZstd
+        );
+        assert_eq!(
+            ArchiveType::from_path(Path::new("archive.tar.bz2")).unwrap(),
+            ArchiveType::TarBz2
+        );
+        assert_eq!(
+            ArchiveType::from_path(Path::new("archive.tz2")).unwrap(),
+            ArchiveType::TarBz2
+        );
+        assert_eq!(
+            ArchiveType::from_path(Path::new("archive.tbz")).unwrap(),
+            ArchiveType::TarBz2
+        );
+        assert_eq!(
+            ArchiveType::from_path(Path::new("archive.tbz2")).unwrap(),
+            ArchiveType::TarBz2
+        );
+        assert_eq!(
+            ArchiveType::from_path(Path::new("archive.tar.xz")).unwrap(),
+            ArchiveType::TarXz
+        );
+        assert_eq!(
+            ArchiveType::from_path(Path::new("archive.txz")).unwrap(),
+            ArchiveType::TarXz
+        );
+
+        // Multiple dots
+        for (name, expected) in [
+            ("my.tar.custom", None),
+            ("backup.tar.gz.txt", None),
+            ("archive.zip.txt", None),
+            ("archive.tar.zip", Some(ArchiveType::Zip)),
+            ("archive.zip.tar", Some(ArchiveType::Tar)),
+            ("archive.tar.tar", Some(ArchiveType::Tar)),
+            ("archive.gz.tar.gz", Some(ArchiveType::TarGz)),
+            ("v1.2.3.tar.bz2", Some(ArchiveType::TarBz2)),
+            ("dir.tar/archive.tgz", Some(ArchiveType::TarGz)),
+            ("dir.tar/archive.gz", None),
+            ("archive.txt.gz", None),
+            ("archive.tar.gz.zst", None),
+            ("archive..gz", None),
+            ("archive.TAR.GZ", None),
+            // No stem
+            ("tar.gz", None),
+            ("zip", None),
+            (".tar", None),
+            (".tar.gz", None),
+            (".hidden.tar.gz", Some(ArchiveType::TarGz)),
+            (".hidden.zip", Some(ArchiveType::Zip)),
+        ] {
+            assert_eq!(
+                ArchiveType::from_path(Path::new(name)),
+                expected,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_sniff() -> Result<()> {
+        let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+        for (name, expected) in [
+            ("archive.zip", Some(ArchiveType::Zip)),
+            ("archive.tar", Some(ArchiveType::Tar)),
+            ("archive.tar.gz", Some(ArchiveType::TarGz)),
+            ("archive.tar.zst", Some(ArchiveType::TarZstd)),
+            ("archive.tar.bz2", Some(ArchiveType::TarBz2)),
+            ("archive.tar.xz", Some(ArchiveType::TarXz)),
+            ("file.txt", None),
+        ] {
+            assert_eq!(ArchiveType::sniff(&data.join(name))?, expected, "{}", name);
+        }
+
+        // Compressed files that aren't tars, and files shorter than the magic bytes
+        let dir = tempfile::tempdir()?;
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut gz, &[b'a'; 1000])?;
+        std::fs::write(dir.path().join("a.gz"), gz.finish()?)?;
+        std::fs::write(dir.path().join("short"), b"PK")?;
+        std::fs::write(dir.path().join("empty"), b"")?;
+        for name in ["a.gz", "short", "empty"] {
+            assert_eq!(
+                ArchiveType::sniff(&dir.path().join(name))?,
+                None,
+                "{}",
+                name
+            );
+        }
+        assert!(ArchiveType::sniff(&dir.path().join("missing")).is_err());
+        Ok(())
+    }
+}
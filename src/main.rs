@@ -1,20 +1,32 @@
 use anyhow::{bail, Result};
-use clap::Parser;
-use digest::{generic_array::GenericArray, Digest, FixedOutputReset};
-use flate2::read::GzDecoder;
-use log::debug;
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use log::{debug, error, trace, warn};
 use std::{
+    collections::{HashMap, HashSet},
     fs::File,
-    io::Read,
+    io::Write,
+    ops::Range,
     path::{Path, PathBuf},
 };
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
     thread,
 };
-use tar::Archive;
 use walkdir::{DirEntry, WalkDir};
 
+use hashall::output::{
+    Baseline, BrokenPipe, ByteOrder, Comparison, Compression, Groups, Output, PrintFormat,
+    PrintOptions, Printer, Record, Sidecar, SidecarContents, SyslogTee, Trees,
+};
+use hashall::{
+    cancel, checkpoint, chunker::Chunking, manifest, output, spill, Algorithm, ArchiveType,
+    BufHashFactory, ContentKind, Digester, HashOptions,
+};
+
 fn is_hidden(entry: &DirEntry) -> bool {
     entry
         .file_name()
@@ -24,48 +36,225 @@ fn is_hidden(entry: &DirEntry) -> bool {
 
 pub struct ThreadPool {
     workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    sender: Option<mpsc::Sender<Queued>>,
+    listed: Option<Listed>,
+    /// Position of the next job if the records are tagged with it
+    next_seq: Option<u64>,
+    failures: Arc<AtomicUsize>,
+    /// Directory the paths of the jobs are relative to
+    base: Arc<Path>,
+}
+
+/// How the inputs are distributed to the workers
+struct PoolOptions {
+    /// Number of workers
+    n_jobs: usize,
+    /// Pin each worker to a CPU core
+    pin_threads: bool,
+    /// Paths not to hash
+    listed: Option<Listed>,
+    /// Limit on the files open at once across the workers
+    open_files: Option<Arc<OpenFiles>>,
+    /// Tag the records with the position of their job in submission order
+    ordered: bool,
+    /// Number of files that failed, in the workers or while walking directories. Each is
+    /// reported and the rest are still hashed.
+    failures: Arc<AtomicUsize>,
+}
+
+/// Report a file that failed and count it in `failures`
+fn report_failure(failures: &AtomicUsize, error: impl std::fmt::Display) {
+    error!("{}", error);
+    failures.fetch_add(1, Ordering::Relaxed);
+}
+
+/// State shared by the workers of a pool
+#[derive(Clone)]
+struct Shared {
+    open_files: Option<Arc<OpenFiles>>,
+    failures: Arc<AtomicUsize>,
+}
+
+/// Counting semaphore bounding the files open at once (`--max-open-files`)
+struct OpenFiles {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl OpenFiles {
+    fn new(limit: usize) -> Self {
+        assert!(limit > 0);
+        OpenFiles {
+            available: Mutex::new(limit),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Block until a file can be opened. The slot is released when the guard is dropped.
+    fn acquire(&self) -> OpenFileGuard<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        OpenFileGuard(self)
+    }
+}
+
+struct OpenFileGuard<'a>(&'a OpenFiles);
+
+impl Drop for OpenFileGuard<'_> {
+    fn drop(&mut self) {
+        *self.0.available.lock().unwrap() += 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// Paths in a checksum file given with `--skip-listed`
+struct Listed(HashSet<String>);
+
+impl Listed {
+    fn read(manifest: &Path) -> Result<Self> {
+        let entries = manifest::read_manifest(manifest)?;
+        Ok(Listed(
+            entries
+                .into_iter()
+                .map(|entry| manifest::normalize_separators(&entry.path))
+                .collect(),
+        ))
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.0
+            .contains(&manifest::normalize_separators(&path.display().to_string()))
+    }
 }
 
 enum Job {
-    File(PathBuf),
-    Archive((PathBuf, ArchiveType)),
+    File(PathBuf, Algorithm),
+    Archive((PathBuf, ArchiveType), Algorithm),
+    /// A file hashed as an archive if its contents are one (`--sniff-archives`)
+    Sniff(PathBuf, Algorithm),
+    /// Part of a zip archive (`--threads-per-archive`)
+    ZipEntries((PathBuf, Range<usize>), Algorithm),
 }
 
+/// Job with its position in submission order if the records are tagged with it (`--ordered`)
+type Queued = (Job, Option<u64>);
+
 impl ThreadPool {
     /// Create a new `ThreadPool`.
     ///
-    /// The size is the number of threads in the pool.
+    /// `n_jobs` is the number of threads in the pool.
+    /// With `pin_threads`, worker `i` runs on the `i`-th core (modulo the number of cores).
     ///
     /// # Panics
     ///
-    /// The `new` function will panic if the size is zero.
-    fn new(size: usize, hasher_factory: BufHashFactory) -> ThreadPool {
+    /// The `new` function will panic if `n_jobs` is zero.
+    fn new(
+        options: PoolOptions,
+        hasher_factory: BufHashFactory,
+        base: &Path,
+        record_sender: mpsc::Sender<Record>,
+    ) -> ThreadPool {
+        let PoolOptions {
+            n_jobs: size,
+            pin_threads,
+            listed,
+            open_files,
+            ordered,
+            failures,
+        } = options;
+        let shared = Shared {
+            open_files,
+            failures,
+        };
         assert!(size > 0);
+        debug!(target: "hashall::pool", "Starting {} workers", size);
 
         let (sender, receiver) = mpsc::channel();
 
         let receiver = Arc::new(Mutex::new(receiver));
+        let base: Arc<Path> = Arc::from(base);
+
+        let core_ids = if pin_threads {
+            let core_ids = core_affinity::get_core_ids().unwrap_or_default();
+            if core_ids.is_empty() {
+                warn!("Failed to get the CPU cores. Threads are not pinned.");
+            }
+            core_ids
+        } else {
+            Vec::new()
+        };
 
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            workers.push(Worker::new(id, hasher_factory, Arc::clone(&receiver)));
+            let core_id = (!core_ids.is_empty()).then(|| core_ids[id % core_ids.len()]);
+            workers.push(Worker::new(
+                id,
+                core_id,
+                hasher_factory.clone(),
+                Arc::clone(&base),
+                Arc::clone(&receiver),
+                record_sender.clone(),
+                shared.clone(),
+            ));
         }
 
         ThreadPool {
             workers,
             sender: Some(sender),
+            listed,
+            next_seq: ordered.then_some(0),
+            failures: shared.failures,
+            base,
         }
     }
-    fn process_file(&mut self, path: PathBuf) {
-        self.sender.as_ref().unwrap().send(Job::File(path)).unwrap();
+
+    /// Position of the next job in submission order if the records are tagged with it
+    fn take_seq(&mut self) -> Option<u64> {
+        let seq = self.next_seq?;
+        self.next_seq = Some(seq + 1);
+        Some(seq)
+    }
+
+    /// Whether `path` is listed in the `--skip-listed` checksum file
+    fn is_listed(&self, path: &Path) -> bool {
+        self.listed
+            .as_ref()
+            .is_some_and(|listed| listed.contains(path))
     }
-    fn process_archive(&mut self, path: PathBuf, archive_type: ArchiveType) {
+    fn process_file(&mut self, path: PathBuf, algorithm: Algorithm) {
+        let seq = self.take_seq();
         self.sender
             .as_ref()
             .unwrap()
-            .send(Job::Archive((path, archive_type)))
+            .send((Job::File(path, algorithm), seq))
+            .unwrap();
+    }
+    fn process_archive(&mut self, path: PathBuf, archive_type: ArchiveType, algorithm: Algorithm) {
+        let seq = self.take_seq();
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send((Job::Archive((path, archive_type), algorithm), seq))
+            .unwrap();
+    }
+    fn process_zip_entries(&mut self, path: PathBuf, entries: Range<usize>, algorithm: Algorithm) {
+        let seq = self.take_seq();
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send((Job::ZipEntries((path, entries), algorithm), seq))
+            .unwrap();
+    }
+    fn process_sniffed(&mut self, path: PathBuf, algorithm: Algorithm) {
+        let seq = self.take_seq();
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send((Job::Sniff(path, algorithm), seq))
             .unwrap();
     }
 }
@@ -82,30 +271,111 @@ impl Drop for ThreadPool {
     }
 }
 
+/// Send the records received so far with `seq` as their position
+fn forward_tagged(receiver: &mpsc::Receiver<Record>, sender: &mpsc::Sender<Record>, seq: u64) {
+    for record in receiver.try_iter() {
+        // The printer is gone only if the output failed, which is reported there
+        let _ = sender.send(Record { seq, ..record });
+    }
+}
+
 struct Worker {
     thread: Option<thread::JoinHandle<()>>,
 }
 
 impl Worker {
+    /// Spawn a thread named `hashall-worker-{id}`, pinned to `core_id` if given.
     fn new(
         id: usize,
+        core_id: Option<core_affinity::CoreId>,
         hasher_factory: BufHashFactory,
-        receiver: Arc<Mutex<mpsc::Receiver<Job>>>,
+        base: Arc<Path>,
+        receiver: Arc<Mutex<mpsc::Receiver<Queued>>>,
+        record_sender: mpsc::Sender<Record>,
+        shared: Shared,
     ) -> Worker {
-        let thread = thread::spawn(move || {
-            let mut hasher = hasher_factory.create();
+        let thread = thread::Builder::new().name(format!("hashall-worker-{id}"));
+        let thread = thread.spawn(move || {
+            let name = thread::current().name().unwrap_or_default().to_string();
+            debug!(target: "hashall::pool", "Worker {id}: started in thread {name}");
+            if let Some(core_id) = core_id {
+                if core_affinity::set_for_current(core_id) {
+                    debug!(target: "hashall::pool", "Worker {id}: pinned to core {}", core_id.id);
+                } else {
+                    warn!("Failed to pin worker {id} to core {}", core_id.id);
+                }
+            }
+            // Hashers are created on first use because inputs can select their own algorithm
+            let mut hashers: HashMap<Algorithm, Box<dyn Digester>> = HashMap::new();
+            // Records of jobs with a position go through here to be tagged with it
+            let (tagged_sender, tagged_receiver) = mpsc::channel();
             loop {
                 let message = receiver.lock().unwrap().recv();
 
                 match message {
-                    Ok(job) => match job {
-                        Job::File(path) => hasher.digest_file(&path).unwrap(),
-                        Job::Archive((path, archive_type)) => {
-                            hasher.digest_archive(&path, archive_type).unwrap();
+                    // Drain the remaining jobs without hashing
+                    Ok(_) if cancel::should_stop() => {}
+                    Ok((job, seq)) => {
+                        let algorithm = match job {
+                            Job::File(_, algorithm)
+                            | Job::Archive(_, algorithm)
+                            | Job::Sniff(_, algorithm)
+                            | Job::ZipEntries(_, algorithm) => algorithm,
+                        };
+                        let hasher = hashers.entry(algorithm).or_insert_with(|| {
+                            let sender = if seq.is_some() {
+                                tagged_sender.clone()
+                            } else {
+                                record_sender.clone()
+                            };
+                            hasher_factory.create(algorithm, Arc::clone(&base), sender)
+                        });
+                        let open = shared
+                            .open_files
+                            .as_ref()
+                            .map(|open_files| open_files.acquire());
+                        let (path, result) = match job {
+                            Job::File(path, _) => {
+                                trace!(target: "hashall::pool", "Worker {id}: {}", path.display());
+                                let result = hasher.digest_file(&path);
+                                (path, result)
+                            }
+                            Job::Archive((path, archive_type), _) => {
+                                trace!(target: "hashall::pool", "Worker {id}: {}", path.display());
+                                let result = hasher.digest_archive(&path, archive_type);
+                                (path, result)
+                            }
+                            Job::ZipEntries((path, entries), _) => {
+                                trace!(target: "hashall::pool", "Worker {id}: {} {:?}", path.display(), entries);
+                                let result = hasher.digest_zip_entries(&path, entries);
+                                (path, result)
+                            }
+                            Job::Sniff(path, _) => {
+                                trace!(target: "hashall::pool", "Worker {id}: {}", path.display());
+                                // Unreadable files are reported by `digest_file`
+                                let result = match ArchiveType::sniff(&base.join(&path)) {
+                                    Ok(Some(archive_type)) => {
+                                        debug!(target: "hashall::archive", "{}: sniffed {:?}", path.display(), archive_type);
+                                        hasher.digest_archive(&path, archive_type)
+                                    }
+                                    _ => hasher.digest_file(&path),
+                                };
+                                (path, result)
+                            }
+                        };
+                        drop(open);
+                        if let Err(e) = result {
+                            report_failure(
+                                &shared.failures,
+                                format_args!("{}: {:#}", path.display(), e),
+                            );
+                        }
+                        if let Some(seq) = seq {
+                            forward_tagged(&tagged_receiver, &record_sender, seq);
                         }
-                    },
+                    }
                     Err(_) => {
-                        debug!("Worker {id} disconnected; shutting down.");
+                        debug!(target: "hashall::pool", "Worker {id} disconnected; shutting down.");
                         break;
                     }
                 }
@@ -113,346 +383,1836 @@ impl Worker {
         });
 
         Worker {
-            thread: Some(thread),
+            thread: Some(thread.expect("failed to spawn a worker thread")),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, clap::ValueEnum)]
-enum Algorithm {
-    Md5,
-    Sha1,
+/// Bundles of options. Options given explicitly on the command line take precedence.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Preset {
+    /// --hash xxh3 --buffer 16M --mmap
+    Fast,
+    /// --hash sha256 --buffer 1M
+    Secure,
+    /// --hash md5 --format sum (same output as md5sum)
+    Compat,
+}
+
+impl Preset {
+    /// Overwrite the options in `args` that were not set explicitly in `matches`.
+    fn apply(self, args: &mut Args, matches: &ArgMatches) {
+        let is_default = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                None | Some(ValueSource::DefaultValue)
+            )
+        };
+        match self {
+            Preset::Fast => {
+                if is_default("hash") {
+                    args.hash = Algorithm::Xxh3;
+                }
+                if is_default("buffer") {
+                    args.buffer = "16M".to_string();
+                }
+                if is_default("mmap") {
+                    args.mmap = true;
+                }
+            }
+            Preset::Secure => {
+                if is_default("hash") {
+                    args.hash = Algorithm::Sha256;
+                }
+                if is_default("buffer") {
+                    args.buffer = "1M".to_string();
+                }
+            }
+            Preset::Compat => {
+                if is_default("hash") {
+                    args.hash = Algorithm::Md5;
+                }
+                if is_default("format") {
+                    args.format = PrintFormat::Sum;
+                }
+            }
+        }
+    }
+}
+
+/// Parse a separator for `--format fields`. `\0`, `\t`, `\n`, `\r`, and `\\` are unescaped.
+/// Letters, digits, `.`, and `-` are refused because they appear in hashes and sizes.
+fn parse_separator(s: &str) -> Result<String, String> {
+    let mut separator = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            separator.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('0') => separator.push('\0'),
+            Some('t') => separator.push('\t'),
+            Some('n') => separator.push('\n'),
+            Some('r') => separator.push('\r'),
+            Some('\\') => separator.push('\\'),
+            _ => return Err(format!("unknown escape in {:?}", s)),
+        }
+    }
+    if separator.is_empty() {
+        return Err("empty separator".to_string());
+    }
+    if separator
+        .chars()
+        .any(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+    {
+        return Err("letters, digits, '.', and '-' can't be separators".to_string());
+    }
+    Ok(separator)
 }
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(group(clap::ArgGroup::new("sorted").args(["canonical", "sort"])))]
 struct Args {
-    /// Input directories or files
-    #[arg(required = true)]
+    /// Input directories or files. `-` is stdin.
+    #[arg(required_unless_present_any = ["check", "files_from"])]
     input: Vec<PathBuf>,
 
-    /// Hashing algorithm
-    #[arg(long, default_value = "md5")]
+    /// Path printed for stdin (`-`)
+    #[arg(long, value_name = "LABEL", default_value = "-")]
+    stdin_name: PathBuf,
+
+    /// Hashing algorithm. Append `:ALGORITHM` to an input to override it for that input,
+    /// e.g. `legacy.bin:md5`. Repeat to compute several hashes in one read of each file
+    /// (e.g. `--hash md5 --hash sha256`). The others are printed after the first one.
+    #[arg(long = "hash", id = "hash", value_name = "HASH", default_value = "md5")]
+    hashes: Vec<Algorithm>,
+
+    /// First of `hashes`
+    #[arg(skip = Algorithm::Md5)]
     hash: Algorithm,
 
-    /// Hash all files including hidden files
+    /// Hash all files including hidden files (same as --all-files --all-dirs)
     #[arg(short, long)]
     all: bool,
 
+    /// Hash hidden files
+    #[arg(long)]
+    all_files: bool,
+
+    /// Descend into hidden directories (e.g. `.git`)
+    #[arg(long)]
+    all_dirs: bool,
+
     /// Hash files in subdirectories recursively
     #[arg(short, long)]
     recursive: bool,
 
-    /// Buffer size for reading and hashing
-    #[arg(short, long, default_value = "1M")]
+    /// Walk at most N levels of directories with --recursive. 1 is the files directly in the
+    /// inputs, 2 adds the files in their subdirectories, and so on.
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "recursive",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    max_depth: Option<usize>,
+
+    /// Buffer size for reading and hashing, per worker. `auto` divides 1/16 of the available
+    /// memory among the workers, between 64K and 16M (1M if the available memory is unknown).
+    #[arg(short, long, default_value = "1M", env = "HASHALL_BUFFER")]
     buffer: String,
 
-    /// Hash files in archive files (zip, tar, tar.{gz,bz2,xz,zst})
+    /// Hash files in archive files (zip, tar, tar.{gz,bz2,xz,zst}). A zip on stdin is
+    /// recognized by its signature and copied to a temporary file.
     #[arg(long)]
     archive: bool,
 
+    /// Hash archives that fail to be read (corrupt or misnamed) as plain files. The entries
+    /// of an archive are printed once all of them are read.
+    #[arg(long, requires = "archive")]
+    retry_archive: bool,
+
+    /// Also hash the entries of archives in archives, up to 16 levels deep, instead of
+    /// hashing them as files. They are printed as `outer.tar/inner.zip/file.txt`.
+    #[arg(long, requires = "archive")]
+    archive_recursive: bool,
+
+    /// Also hash files without an archive extension as archives if their first bytes are one
+    /// (zip, or tar uncompressed or compressed). Note that e.g. `.docx` and `.jar` files are zip
+    /// archives.
+    #[arg(long, requires = "archive")]
+    sniff_archives: bool,
+
+    /// Split each zip archive into N ranges of entries hashed by different workers, so that a
+    /// large archive doesn't leave the other workers idle. Tar archives can only be read
+    /// sequentially and are not split. The ranges are hashed independently, so neither
+    /// --sort (which keeps the entries in the order they arrive) nor --retry-archive (which
+    /// needs the whole archive to fail) can be used.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "1",
+        requires = "archive",
+        conflicts_with_all = ["sort", "retry_archive"],
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    threads_per_archive: usize,
+
+    /// Warn about zip entries whose decompressed size differs from the size in the header,
+    /// which means corruption or a crafted archive
+    #[arg(long, requires = "archive")]
+    verify_sizes: bool,
+
+    /// Also print the CRC-32 of each file after the hash, computed in the same read.
+    /// With --archive, it's checked against the CRC-32 stored for each zip entry.
+    #[arg(long, conflicts_with_all = ["checkpoint", "names_only", "external_hasher"])]
+    also_crc32: bool,
+
+    /// Hash the digest again, e.g. SHA256d (sha256 of the sha256 digest) with --hash sha256
+    #[arg(long)]
+    double: bool,
+
     /// Print format
     #[arg(short, long, default_value = "sum")]
     format: PrintFormat,
 
-    /// Number of jobs. 0 means number of logical cores.
-    #[arg(short, long, default_value = "0")]
+    /// Skip the files and directories whose path relative to the walked directory matches
+    /// GLOB. A GLOB without `/` matches names at any depth (e.g. `target`, `*.tmp`). Excluded
+    /// directories are not walked. Inputs given as files are always hashed.
+    #[arg(long, value_name = "GLOB", value_parser = parse_glob)]
+    exclude: Vec<Glob>,
+
+    /// Only hash the files whose path relative to the walked directory matches GLOB, written
+    /// as for --exclude. Directories are still walked, and --exclude wins over --include.
+    /// Repeatable.
+    #[arg(long, value_name = "GLOB", value_parser = parse_glob)]
+    include: Vec<Glob>,
+
+    /// Shortcut for --format tsv
+    #[arg(long, conflicts_with = "format")]
+    tsv: bool,
+
+    /// Shortcut for --format tag (`MD5 (path) = hash`, same as `md5sum --tag`)
+    #[arg(long, conflicts_with_all = ["format", "tsv"])]
+    tag: bool,
+
+    /// Separator of the fields for --format fields (e.g. '\t', ' ', or '\0')
+    #[arg(long, default_value = "\\t", value_parser = parse_separator)]
+    field_sep: String,
+
+    /// Terminator of each record for --format fields (e.g. '\n' or '\0')
+    #[arg(long, default_value = "\\n", value_parser = parse_separator)]
+    record_sep: String,
+
+    /// Print a manifest that is identical across machines and runs, to keep in version control:
+    /// the sum format sorted by path, with `/` as the separator and without leading `./`.
+    /// Inputs must be relative paths.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "format", "tsv", "group_by_hash", "find_dupes", "diff_baseline", "dir_hashes"
+        ]
+    )]
+    canonical: bool,
+
+    /// Print the files sorted by path after all of them are hashed. Archive entries stay
+    /// together at the archive path, in the order they are stored in the archive.
+    #[arg(long, conflicts_with = "canonical")]
+    sort: bool,
+
+    /// Print the files in the order of the inputs (and of the walk in directories) after all
+    /// of them are hashed, as if hashed with a single job
+    #[arg(long, conflicts_with_all = ["canonical", "sort"])]
+    ordered: bool,
+
+    /// Number of records --canonical and --sort sort in memory. Beyond that, sorted runs are
+    /// written to temporary files and merged at the end, to keep the memory bounded for huge
+    /// trees.
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "sorted",
+        default_value_t = spill::DEFAULT_THRESHOLD,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    spill_threshold: usize,
+
+    /// Seed of the content-defined chunker for --format chunks-json.
+    /// Chunk boundaries are only comparable between runs with the same seed.
+    #[arg(long, default_value = "0")]
+    chunk_seed: u64,
+
+    /// Print the hash of each block of SIZE bytes (e.g. 1M) with its index, and the root of
+    /// a binary Merkle tree over the block hashes, to verify parts of large files later
+    #[arg(
+        long,
+        value_name = "SIZE",
+        conflicts_with_all = [
+            "format", "tsv", "canonical", "group_by_hash", "find_dupes", "diff_baseline",
+            "dir_hashes", "checkpoint", "names_only", "external_hasher"
+        ]
+    )]
+    block_tree: Option<String>,
+
+    /// Number of jobs. 0 means number of logical cores, but no more than the inputs when
+    /// they are all files.
+    #[arg(short, long, default_value = "0", env = "HASHALL_JOBS")]
     jobs: usize,
+
+    /// Hint that reading dominates (e.g. many small files or a spinning disk): with --jobs 0,
+    /// use at most 4 workers so that they don't contend for the disk
+    #[arg(long, overrides_with = "cpu_bound")]
+    io_bound: bool,
+
+    /// Hint that hashing dominates (the default): with --jobs 0, use one worker per
+    /// logical core. Overrides an earlier --io-bound.
+    #[arg(long, overrides_with = "io_bound")]
+    cpu_bound: bool,
+
+    /// Pin each worker thread to a CPU core
+    #[arg(long)]
+    pin_threads: bool,
+
+    /// Maximum number of files open at once across the workers.
+    /// Workers wait for a slot when the limit is reached.
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_open_files: Option<usize>,
+
+    /// Read files using memory-mapped I/O
+    #[arg(long)]
+    mmap: bool,
+
+    /// Files smaller than SIZE are read with the buffer even with --mmap (or --preset fast),
+    /// since mapping costs more than copying small files
+    #[arg(long, value_name = "SIZE", default_value = "1M")]
+    mmap_threshold: String,
+
+    /// Bypass the page cache when reading files (O_DIRECT, Linux only)
+    #[arg(long)]
+    direct: bool,
+
+    /// Preset of options (individual options override the preset)
+    #[arg(long)]
+    preset: Option<Preset>,
+
+    /// Resolve inputs relative to DIR instead of the current directory.
+    /// Printed paths stay relative to DIR.
+    #[arg(short = 'C', long, value_name = "DIR")]
+    directory: Option<PathBuf>,
+
+    /// Print the paths relative to DIR, e.g. to make checksum files of absolute inputs
+    /// portable. Paths of archive entries stay relative to their archive, and paths outside
+    /// DIR are printed as they are with a warning.
+    #[arg(long, value_name = "DIR", conflicts_with = "sidecar")]
+    base_dir: Option<PathBuf>,
+
+    /// Print only files that are new or changed compared to a checksum file in the sum format
+    #[arg(long, value_name = "FILE")]
+    diff_baseline: Option<PathBuf>,
+
+    /// Also print files in the baseline that were not hashed (requires --diff-baseline)
+    #[arg(long, requires = "diff_baseline")]
+    show_deleted: bool,
+
+    /// Print archive paths in a separate column (CSV) instead of joined with the entry paths
+    #[arg(long)]
+    archive_column: bool,
+
+    /// Print archive entries by their paths in the archive only (`file.txt` instead of
+    /// `archive.zip/file.txt`)
+    #[arg(long, requires = "archive", conflicts_with_all = ["archive_column", "dir_hashes"])]
+    dereference_archive_paths: bool,
+
+    /// Join archive paths and entry paths with SEP instead of `/`, e.g. `!` for
+    /// `archive.zip!file.txt`. Archives in archives are still joined with `/`.
+    #[arg(
+        long,
+        value_name = "SEP",
+        requires = "archive",
+        conflicts_with_all = ["archive_column", "dir_hashes", "dereference_archive_paths"]
+    )]
+    archive_separator: Option<String>,
+
+    /// With --diff-baseline, print all files prefixed with their status:
+    /// '=' unchanged, '+' new, '*' changed, '-' deleted
+    #[arg(long)]
+    status: bool,
+
+    /// Save the progress of hashing a single large file to FILE and resume from it when
+    /// restarted (requires --hash blake3)
+    #[arg(long, value_name = "FILE")]
+    checkpoint: Option<PathBuf>,
+
+    /// Don't hash the paths listed in a checksum file (e.g. the partial output of an
+    /// interrupted run). Paths must match as printed. Archives are skipped only if the archive
+    /// path itself is listed.
+    #[arg(long, value_name = "MANIFEST", conflicts_with_all = ["checkpoint", "names_only"])]
+    skip_listed: Option<PathBuf>,
+
+    /// Print files grouped by hash: the hash followed by the paths, with a blank line
+    /// between groups
+    #[arg(long, conflicts_with = "diff_baseline")]
+    group_by_hash: bool,
+
+    /// Print only groups of two or more files (requires --group-by-hash)
+    #[arg(long, requires = "group_by_hash")]
+    duplicates_only: bool,
+
+    /// Find files with the same contents: the same as --group-by-hash --duplicates-only
+    #[arg(long, conflicts_with_all = ["group_by_hash", "diff_baseline"])]
+    find_dupes: bool,
+
+    /// Hash two directories recursively and print a unified diff of their
+    /// `<hash>  <relative path>` lines. Exits with 1 if they differ.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "format", "tsv", "canonical", "block_tree", "group_by_hash", "find_dupes",
+            "diff_baseline", "dir_hashes", "names_only"
+        ]
+    )]
+    compare: bool,
+
+    /// Hash two directories recursively like --compare, but print the relative paths that
+    /// differ grouped by status: `<path>: ADDED`, then `REMOVED`, then `CHANGED`
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "format", "tsv", "canonical", "block_tree", "group_by_hash", "find_dupes",
+            "diff_baseline", "dir_hashes", "names_only", "compare"
+        ]
+    )]
+    diff: bool,
+
+    /// Print a single hash per input over the sorted `<hash>  <relative path>` lines of its
+    /// files (walked recursively), as a fingerprint of the whole tree
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "canonical", "block_tree", "group_by_hash", "find_dupes", "diff_baseline",
+            "dir_hashes", "names_only", "compare", "diff", "sidecar", "base_dir", "checkpoint",
+            "external_hasher"
+        ]
+    )]
+    tree_hash: bool,
+
+    /// Write the output to FILE instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Compress the output file. The extension is appended unless FILE already has it.
+    #[arg(long, requires = "output")]
+    compress_output: Option<Compression>,
+
+    /// Write the hash of each file to `<file>.<algorithm>` (e.g. `file.txt.sha256`) next to it
+    /// instead of printing it. CONTENTS is `sum` (`<hash>  <file name>`) or `hash`. Files that
+    /// can't be written are reported and skipped, and archive entries are skipped.
+    #[arg(
+        long,
+        value_name = "CONTENTS",
+        num_args = 0..=1,
+        default_missing_value = "sum",
+        conflicts_with_all = [
+            "format", "tsv", "output", "canonical", "block_tree", "group_by_hash", "find_dupes",
+            "diff_baseline", "dir_hashes", "names_only", "compare", "diff"
+        ]
+    )]
+    sidecar: Option<SidecarContents>,
+
+    /// Also send each output line to the system logger (facility user, severity info)
+    #[arg(long)]
+    syslog: bool,
+
+    /// What to do when the output is closed early, e.g. by `head`
+    #[arg(long, value_name = "ACTION", default_value = "quiet")]
+    broken_pipe: BrokenPipe,
+
+    /// Byte order of integer-valued hashes (crc32, adler32, and xxh3)
+    #[arg(long, default_value = "be")]
+    byte_order: ByteOrder,
+
+    /// Print the hashes with `-` inserted every N hex characters, for systems that store them
+    /// grouped (e.g. 4 gives `ac17-5545-...`). Not in formats read by other tools, like bazel,
+    /// git-lfs, and spdx.
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u16).range(1..),
+        conflicts_with_all = ["block_tree", "diff_baseline", "dir_hashes", "compare", "diff"]
+    )]
+    group_hash: Option<u16>,
+
+    /// Print the hashes in upper-case hex (e.g. `AC1755...`). Paths are printed as they are.
+    /// Not in formats read by other tools, like bazel, git-lfs, and spdx.
+    #[arg(long, conflicts_with = "dir_hashes")]
+    uppercase: bool,
+
+    /// Print the hashes in base64 (standard alphabet, padded) instead of hex, as in
+    /// Subresource Integrity strings. --check, --expect, and --diff-baseline read hex only.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "uppercase", "group_hash", "dir_hashes", "diff_baseline", "check", "expect"
+        ]
+    )]
+    base64: bool,
+
+    /// End each record with NUL instead of a newline and print the paths unescaped, like
+    /// `sha256sum -z` (sum, tag, csv, and tsv formats). The CSV header is not printed, and
+    /// --files-from reads NUL-separated paths, e.g. from `find -print0`.
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = [
+            "total", "block_tree", "dir_hashes", "group_by_hash", "find_dupes", "compare",
+            "diff", "diff_baseline", "sidecar", "check", "expect"
+        ]
+    )]
+    zero: bool,
+
+    /// Stop after DURATION (e.g. 30s, 5m), print the hashes computed so far,
+    /// and exit with status 124
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    deadline: Option<std::time::Duration>,
+
+    /// Also print a hash of each walked directory, computed over its files' names and hashes
+    /// (not recursive). The directory paths end with `/`.
+    #[arg(long, conflicts_with_all = ["group_by_hash", "find_dupes", "diff_baseline"])]
+    dir_hashes: bool,
+
+    /// Print one hash per input over the sorted relative paths of its files, without reading
+    /// the contents. Detects added, removed, and renamed files.
+    #[arg(long, conflicts_with_all = ["checkpoint", "dir_hashes"])]
+    names_only: bool,
+
+    /// Print the permission bits in octal (e.g. 644) after the hash (sum, csv, tsv, jsonl,
+    /// and json formats). `-` if unknown, e.g. on Windows.
+    #[arg(long)]
+    show_mode: bool,
+
+    /// Print `-` instead of the modes of archive entries, which often differ from the modes
+    /// of the extracted files
+    #[arg(long, requires = "show_mode")]
+    ignore_archive_modes: bool,
+
+    /// Print the size in bytes after the hash (sum, csv, and tsv formats)
+    #[arg(long)]
+    size: bool,
+
+    /// Print sizes in human-readable units (e.g. 1.5K, 340K, 1.2M; powers of 1024)
+    #[arg(long, requires = "size")]
+    human: bool,
+
+    /// Print the number of files and their total size at the end, as a `#` comment line
+    /// (on stderr for csv, tsv, and fields, where every line is a row)
+    #[arg(long)]
+    total: bool,
+
+    /// Show the number of files and bytes hashed so far on stderr, updated as each file
+    /// is done. Nothing is shown if stderr is not a terminal.
+    #[arg(long)]
+    progress: bool,
+
+    /// Hash only files that look like text, judging from their first 8 KiB.
+    /// Archive entries are not filtered.
+    #[arg(
+        long,
+        conflicts_with_all = ["only_binary", "checkpoint", "names_only", "external_hasher"]
+    )]
+    only_text: bool,
+
+    /// Hash only files that look binary, judging from their first 8 KiB.
+    /// Archive entries are not filtered.
+    #[arg(long, conflicts_with_all = ["checkpoint", "names_only", "external_hasher"])]
+    only_binary: bool,
+
+    /// Hash the target path of each symlink (as given by readlink) instead of the contents it
+    /// points to. Symlinks in walked directories are hashed too, instead of being skipped.
+    #[arg(long, conflicts_with_all = ["checkpoint", "names_only", "external_hasher"])]
+    hash_symlink_target: bool,
+
+    /// Follow symlinks found in directories, hashing the files they point to and walking the
+    /// directories they point to. Symlinks given as inputs are followed unless
+    /// --no-dereference is given. Symlink loops are reported and skipped.
+    #[arg(long, conflicts_with = "hash_symlink_target")]
+    follow_symlinks: bool,
+
+    /// Warn about directory inputs without files to hash, telling empty directories from
+    /// directories whose files are all skipped (hidden, symlinks, --exclude, or --include)
+    #[arg(long)]
+    warn_empty: bool,
+
+    /// Don't follow symlinks given as inputs: skip them with a warning, or hash them as links
+    /// with --hash-symlink-target (even if they point to directories or nowhere).
+    /// By default, they are followed like the files and directories they point to.
+    #[arg(long, conflicts_with = "follow_symlinks")]
+    no_dereference: bool,
+
+    /// Hash each file with a shell command instead of --hash. The file is piped to its stdin
+    /// and the first word of its stdout must be the hash in hex (e.g. 'sha1sum').
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        conflicts_with_all = ["hash", "archive", "checkpoint", "dir_hashes", "names_only", "double"]
+    )]
+    external_hasher: Option<String>,
+
+    /// Read stdin (`-`) as a tar stream, hash its members, and copy the stream unchanged to
+    /// stdout. The hashes are written to --output.
+    #[arg(long, requires = "output", conflicts_with_all = ["external_hasher", "check"])]
+    tar_tee: bool,
+
+    /// Also hash the paths listed in FILE, one per line (`-` is stdin), e.g. from `find`.
+    /// Blank lines are skipped. Each path is handled like an input given as an argument.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["check", "compare", "diff"])]
+    files_from: Option<PathBuf>,
+
+    /// Check that a single file has hash HASH. Prints `<file>: OK` or `<file>: FAILED` and
+    /// exits with 1 if it doesn't match. The algorithm is inferred from the length of HASH
+    /// unless --hash is given.
+    #[arg(long, value_name = "HASH")]
+    expect: Option<String>,
+
+    /// Verify the files listed in a checksum file in the sum format (like `md5sum -c`).
+    /// Prints `<file>: OK` or `<file>: FAILED` and exits with 1 if any file doesn't match or
+    /// can't be read. The algorithm of each line is inferred from the length of its hash
+    /// unless --hash is given.
+    #[arg(
+        long,
+        value_name = "MANIFEST",
+        conflicts_with_all = [
+            "input", "expect", "format", "tsv", "canonical", "checkpoint", "names_only",
+            "sidecar", "compare", "diff"
+        ]
+    )]
+    check: Option<PathBuf>,
+
+    /// Print only the files that fail --check
+    #[arg(long, requires = "check")]
+    quiet: bool,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 struct Flags {
-    all: bool,
+    /// Include hidden files
+    hidden_files: bool,
+    /// Descend into hidden directories
+    hidden_dirs: bool,
     recursive: bool,
+    /// Depth limit of `recursive`
+    max_depth: Option<usize>,
     archive: bool,
+    /// Detect archives without an archive extension by their contents
+    sniff_archives: bool,
+    /// Number of jobs each zip archive is split into
+    threads_per_archive: usize,
+    /// Also process symlinks found in directories
+    symlinks: bool,
+    /// Follow symlinks found in directories
+    follow_symlinks: bool,
+    /// Don't follow symlinks given as inputs
+    no_dereference: bool,
+    /// Warn about directory inputs without files to hash
+    warn_empty: bool,
+    algorithm: Algorithm,
+    /// Paths relative to the walked directory that are skipped, with their subdirectories
+    exclude: Option<Arc<GlobSet>>,
+    /// If given, only the files whose path relative to the walked directory matches are hashed
+    include: Option<Arc<GlobSet>>,
+    /// Canonical path of the `--output` file, which is not hashed when found in a directory
+    output: Option<PathBuf>,
 }
 
-impl From<&Args> for Flags {
-    fn from(args: &Args) -> Self {
-        Flags {
-            all: args.all,
+impl Flags {
+    /// Whether the archive `path` is split across the workers. Only zip archives can be.
+    fn splits(&self, path: &Path, archive_type: ArchiveType) -> bool {
+        if self.threads_per_archive == 1 {
+            return false;
+        }
+        if archive_type != ArchiveType::Zip {
+            debug!(target: "hashall::archive", "{}: tar archives are not split", path.display());
+            return false;
+        }
+        true
+    }
+
+    /// Whether the file at `path` under `root` passes `--include`
+    fn is_included(&self, root: &Path, path: &Path) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(path.strip_prefix(root).unwrap_or(path)))
+    }
+
+    /// Whether `entry` is the file being written by `--output`
+    fn is_output(&self, entry: &DirEntry) -> bool {
+        self.output.as_ref().is_some_and(|output| {
+            // Only the entries with the same name are resolved
+            output.file_name() == Some(entry.file_name())
+                && entry
+                    .path()
+                    .canonicalize()
+                    .is_ok_and(|path| &path == output)
+        })
+    }
+}
+
+impl TryFrom<&Args> for Flags {
+    type Error = anyhow::Error;
+
+    fn try_from(args: &Args) -> Result<Self> {
+        Ok(Flags {
+            hidden_files: args.all || args.all_files,
+            hidden_dirs: args.all || args.all_dirs,
             recursive: args.recursive,
+            max_depth: args.max_depth,
             archive: args.archive,
-        }
+            sniff_archives: args.sniff_archives,
+            threads_per_archive: args.threads_per_archive,
+            symlinks: args.hash_symlink_target,
+            follow_symlinks: args.follow_symlinks,
+            no_dereference: args.no_dereference,
+            warn_empty: args.warn_empty,
+            algorithm: args.hash,
+            exclude: glob_set(&args.exclude)?,
+            include: glob_set(&args.include)?,
+            // The file is created before the inputs are walked
+            output: args.output.as_deref().and_then(|path| {
+                Output::file_path(path, args.compress_output)
+                    .canonicalize()
+                    .ok()
+            }),
+        })
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
-enum PrintFormat {
-    /// Hash and filename (same format as md5sum)
-    Sum,
-    /// CSV
-    Csv,
+fn glob_set(globs: &[Glob]) -> Result<Option<Arc<GlobSet>>> {
+    if globs.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(glob.clone());
+    }
+    Ok(Some(Arc::new(builder.build()?)))
 }
 
-fn escape_csv(s: &str) -> String {
-    if s.contains(',') {
-        format!("\"{}\"", s.replace('\"', "\"\""))
+/// Parse a pattern for `--exclude` and `--include`. A pattern without `/` matches names at any depth
+/// (`target` is `**/target`), and a trailing `/` is ignored. `*` doesn't match `/`.
+fn parse_glob(s: &str) -> Result<Glob, String> {
+    let pattern = s.strip_suffix('/').unwrap_or(s);
+    let pattern = if pattern.contains('/') {
+        pattern.to_string()
     } else {
-        s.to_string()
+        format!("**/{pattern}")
+    };
+    GlobBuilder::new(&pattern)
+        .literal_separator(true)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+fn process_file(pool: &mut ThreadPool, input: PathBuf, flags: &Flags) {
+    if pool.is_listed(&input) {
+        debug!("{}: Skipped (listed)", input.display());
+        return;
+    }
+    if flags.archive {
+        if let Some(archive_type) = ArchiveType::from_path(&input) {
+            if flags.splits(&input, archive_type) {
+                return process_zip(pool, input, flags);
+            }
+            pool.process_archive(input, archive_type, flags.algorithm);
+        } else if flags.sniff_archives {
+            pool.process_sniffed(input, flags.algorithm);
+        } else {
+            pool.process_file(input, flags.algorithm);
+        }
+    } else {
+        pool.process_file(input, flags.algorithm);
     }
 }
 
-fn escaped_display(path: &Path) -> String {
-    escape_csv(&path.display().to_string())
+/// Queue the entries of the zip archive `input` as `flags.threads_per_archive` jobs.
+/// Archives whose entries can't be listed are queued whole to report the error
+/// (or to be hashed as a file with `--retry-archive`).
+fn process_zip(pool: &mut ThreadPool, input: PathBuf, flags: &Flags) {
+    let len = match hashall::zip_len(&pool.base.join(&input)) {
+        Ok(len) => len,
+        Err(_) => return pool.process_archive(input, ArchiveType::Zip, flags.algorithm),
+    };
+    for entries in split_entries(len, flags.threads_per_archive) {
+        pool.process_zip_entries(input.clone(), entries, flags.algorithm);
+    }
 }
 
-trait DigestPrint {
-    fn digest_file(&mut self, path: &Path) -> Result<()>;
-    fn digest_archive(&mut self, path: &Path, archive_type: ArchiveType) -> Result<()>;
+/// Split `0..len` into at most `n` contiguous ranges of about the same length
+fn split_entries(len: usize, n: usize) -> Vec<Range<usize>> {
+    let chunk = len.div_ceil(n).max(1);
+    (0..len)
+        .step_by(chunk)
+        .map(|start| start..(start + chunk).min(len))
+        .collect()
 }
 
-struct BufHash<H: Digest + FixedOutputReset> {
-    hasher: H,
-    hash: digest::Output<H>,
-    format: PrintFormat,
-    buffer: Vec<u8>,
-}
-
-impl<H> BufHash<H>
-where
-    H: Digest + FixedOutputReset,
-    <H as digest::OutputSizeUser>::OutputSize: std::ops::Add,
-    <<H as digest::OutputSizeUser>::OutputSize as std::ops::Add>::Output:
-        digest::generic_array::ArrayLength<u8>,
-{
-    fn new(buffer_size: usize, format: PrintFormat) -> Self {
-        let hasher = H::new();
-        let hash = GenericArray::default();
-        let buffer = vec![0; buffer_size];
-        BufHash {
-            hasher,
-            hash,
-            format,
-            buffer,
-        }
-    }
-
-    fn _digest_print<R: Read>(&mut self, path: &Path, mut readable: R) -> Result<()> {
-        loop {
-            let n = readable.read(&mut self.buffer)?;
-            if n == 0 {
-                break;
+/// Entries under `root` selected by `flags`. Excluded directories are not walked.
+fn walk(root: &Path, flags: &Flags) -> impl Iterator<Item = walkdir::Result<DirEntry>> {
+    let walker = if flags.recursive {
+        WalkDir::new(root).max_depth(flags.max_depth.unwrap_or(usize::MAX))
+    } else {
+        WalkDir::new(root).min_depth(1).max_depth(1)
+    }
+    .follow_links(flags.follow_symlinks);
+    let flags = flags.clone();
+    let root = root.to_path_buf();
+    walker
+        .into_iter()
+        // The input itself is walked even if it looks hidden (e.g. ".")
+        .filter_entry(move |e| {
+            if e.depth() == 0 {
+                return true;
             }
-            Digest::update(&mut self.hasher, &self.buffer[..n]);
+            let shown = if e.file_type().is_dir() {
+                flags.hidden_dirs
+            } else {
+                flags.hidden_files
+            };
+            let excluded = flags.exclude.as_ref().is_some_and(|exclude| {
+                exclude.is_match(e.path().strip_prefix(&root).unwrap_or(e.path()))
+            });
+            (shown || !is_hidden(e)) && !excluded
+        })
+        .filter(|entry| match entry {
+            Err(e) if e.loop_ancestor().is_some() => {
+                error!(
+                    "Skipped a symlink loop: {} points to its parent {}",
+                    e.path().unwrap_or(Path::new("")).display(),
+                    e.loop_ancestor().unwrap().display()
+                );
+                false
+            }
+            _ => true,
+        })
+}
+
+/// Hash of the sorted paths of the files in `input`, relative to `input` and separated by `/`,
+/// each followed by a newline. File contents are not read.
+fn hash_names(base: &Path, input: &Path, flags: &Flags) -> Result<Vec<u8>> {
+    let root = base.join(input);
+    let mut names = Vec::new();
+    for entry in walk(&root, flags) {
+        let entry = entry?;
+        if entry.file_type().is_file() && flags.is_included(&root, entry.path()) {
+            let path = entry.path().strip_prefix(&root)?;
+            let name = if path.as_os_str().is_empty() {
+                // The input is a file
+                entry.file_name().to_string_lossy().into_owned()
+            } else {
+                path.components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            };
+            names.push(name);
         }
-        digest::FixedOutputReset::finalize_into_reset(&mut self.hasher, &mut self.hash);
+    }
+    names.sort();
+    let listing: String = names.into_iter().map(|name| name + "\n").collect();
+    Ok(flags.algorithm.digest(listing.as_bytes()))
+}
 
-        // No need for manual locking because println! locks stdout.
-        match self.format {
-            PrintFormat::Sum => {
-                println!("{:x}  {}", self.hash, path.display());
+fn process_dir(pool: &mut ThreadPool, base: &Path, input: &Path, flags: &Flags) -> Result<()> {
+    let root = base.join(input);
+    debug!(
+        target: "hashall::walk",
+        "Walking {} (recursive: {}, max depth: {:?}, hidden files: {}, hidden dirs: {})",
+        root.display(),
+        flags.recursive,
+        flags.max_depth,
+        flags.hidden_files,
+        flags.hidden_dirs
+    );
+    let mut n_files = 0;
+    for entry in walk(&root, flags) {
+        if cancel::should_stop() {
+            break;
+        }
+        let entry = match entry {
+            Ok(entry) => entry,
+            // e.g. a directory that can't be read or a dangling symlink with --follow-symlinks
+            Err(e) => {
+                report_failure(&pool.failures, e);
+                continue;
             }
-            PrintFormat::Csv => {
-                println!("{:x},{}", self.hash, escaped_display(path));
+        };
+        let is_symlink = flags.symlinks && entry.path_is_symlink();
+        if (entry.file_type().is_file() || is_symlink) && flags.is_included(&root, entry.path()) {
+            if flags.is_output(&entry) {
+                debug!("{}: Skipped (output)", entry.path().display());
+                continue;
             }
+            let path = match entry.path().strip_prefix(base) {
+                Ok(path) => path.to_path_buf(),
+                Err(_) => entry.into_path(),
+            };
+            trace!(target: "hashall::walk", "{}", path.display());
+            if is_symlink {
+                // Not opened, so never an archive
+                pool.process_file(path, flags.algorithm);
+            } else {
+                process_file(pool, path, flags);
+            }
+            n_files += 1;
         }
-        Ok(())
     }
+    debug!(target: "hashall::walk", "{}: {} files", root.display(), n_files);
+    if flags.warn_empty && n_files == 0 && !cancel::should_stop() {
+        warn_empty(&root, input, flags);
+    }
+    Ok(())
+}
+
+/// Warn that the directory `input` at `root` has no files to hash, and why
+fn warn_empty(root: &Path, input: &Path, flags: &Flags) {
+    let max_depth = if flags.recursive {
+        flags.max_depth.unwrap_or(usize::MAX)
+    } else {
+        1
+    };
+    // The files the walk would see without the filters
+    let n_skipped = WalkDir::new(root)
+        .min_depth(1)
+        .max_depth(max_depth)
+        .follow_links(flags.follow_symlinks)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| !entry.file_type().is_dir())
+        .count();
+    let has_subdirs = std::fs::read_dir(root).is_ok_and(|mut entries| {
+        entries.any(|entry| entry.is_ok_and(|entry| entry.path().is_dir()))
+    });
+    let hint = if !flags.recursive && has_subdirs {
+        " (subdirectories are walked with -r)"
+    } else {
+        ""
+    };
+    if n_skipped == 0 {
+        warn!("{}: No files to hash{}", input.display(), hint);
+    } else {
+        warn!(
+            "{}: All {} {} skipped (hidden, symlinks, --exclude, or --include){}",
+            input.display(),
+            n_skipped,
+            if n_skipped == 1 {
+                "file is"
+            } else {
+                "files are"
+            },
+            hint
+        );
+    }
+}
 
-    fn digest_zip(&mut self, path: &Path) -> Result<()> {
-        let file = File::open(path)?;
-        let mut archive = zip::ZipArchive::new(file)?;
-        for i in 0..archive.len() {
-            let mut file = archive.by_index(i)?;
-            if file.is_dir() {
-                continue;
+fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches)?;
+    let also_hash = args.hashes.split_off(1);
+    args.hash = args.hashes[0];
+    if let Some(preset) = args.preset {
+        preset.apply(&mut args, &matches);
+    }
+    // Checked after the preset, which can turn on --mmap
+    if matches.value_source("mmap_threshold") == Some(ValueSource::CommandLine) && !args.mmap {
+        bail!("--mmap-threshold requires --mmap or --preset fast");
+    }
+    if args.tsv {
+        args.format = PrintFormat::Tsv;
+    }
+    if args.tag {
+        args.format = PrintFormat::Tag;
+    }
+    if args.diff {
+        args.compare = true;
+    }
+    if args.find_dupes {
+        args.group_by_hash = true;
+        args.duplicates_only = true;
+    }
+    if let Some(list) = &args.files_from {
+        if list.as_os_str() == STDIN && args.input.iter().any(|input| input.as_os_str() == STDIN) {
+            bail!("stdin can't be both hashed and read with --files-from");
+        }
+        let paths = read_file_list(list, args.zero)?;
+        debug!("{}: {} paths", list.display(), paths.len());
+        args.input.extend(paths);
+    }
+    if args.canonical {
+        if let Some(input) = args.input.iter().find(|input| input.is_absolute()) {
+            bail!("--canonical requires relative inputs: {}", input.display());
+        }
+        args.format = PrintFormat::Sum;
+    }
+    if args.external_hasher.is_some() {
+        args.hash = Algorithm::External;
+    }
+    if !also_hash.is_empty() {
+        if !matches!(
+            args.format,
+            PrintFormat::Sum
+                | PrintFormat::Csv
+                | PrintFormat::Tsv
+                | PrintFormat::Fields
+                | PrintFormat::Jsonl
+                | PrintFormat::Json
+        ) {
+            bail!(
+                "Multiple --hash values require the sum, csv, tsv, fields, jsonl, or json format"
+            );
+        }
+        for (used, option) in [
+            (args.diff_baseline.is_some(), "--diff-baseline"),
+            (args.group_by_hash, "--group-by-hash"),
+            (args.compare, "--compare"),
+            (args.tree_hash, "--tree-hash"),
+            (args.sidecar.is_some(), "--sidecar"),
+            (args.dir_hashes, "--dir-hashes"),
+            (args.names_only, "--names-only"),
+            (args.checkpoint.is_some(), "--checkpoint"),
+            (args.block_tree.is_some(), "--block-tree"),
+            (args.expect.is_some(), "--expect"),
+            (args.check.is_some(), "--check"),
+        ] {
+            if used {
+                bail!("Multiple --hash values can't be used with {}", option);
             }
-            let zip_path = path.join(file.name());
-            self._digest_print(&zip_path, &mut file)?;
         }
-        Ok(())
+        let mut algorithms = HashSet::from([args.hash]);
+        if let Some(algorithm) = also_hash.iter().find(|&&a| !algorithms.insert(a)) {
+            bail!(
+                "--hash {} is given twice",
+                algorithm.label().to_ascii_lowercase()
+            );
+        }
+        // The columns are named by the --hash values, so an input can't replace one
+        let base = args.directory.clone().unwrap_or_default();
+        if let Some(input) = args.input.iter().find_map(|input| {
+            let (path, algorithm) = split_algorithm(&base, input.clone());
+            algorithm.map(|_| path)
+        }) {
+            bail!(
+                "{}: per-input algorithms (`path:ALGO`) can't be used with multiple --hash values",
+                input.display()
+            );
+        }
     }
-
-    fn _digest_tar<R: Read>(&mut self, path: &Path, readable: R) -> Result<()> {
-        let mut archive = Archive::new(readable);
-        for file in archive.entries()? {
-            let mut file = file?;
-            if file.header().entry_type().is_dir() {
-                continue;
+    // Formats read by other tools need the hashes as they are
+    for (option, used) in [
+        ("--base64", args.base64),
+        ("--group-hash", args.group_hash.is_some()),
+        ("--uppercase", args.uppercase),
+    ] {
+        if used && !args.format.allows_hash_encoding() {
+            bail!(
+                "{} can only be used with the sum, csv, tsv, fields, tag, jsonl, and json formats",
+                option
+            );
+        }
+    }
+    if args.zero
+        && !matches!(
+            args.format,
+            PrintFormat::Sum | PrintFormat::Csv | PrintFormat::Tsv | PrintFormat::Tag
+        )
+    {
+        bail!("--zero can only be used with the sum, csv, tsv, and tag formats");
+    }
+    if args.tar_tee && !args.input.iter().any(|input| input.as_os_str() == STDIN) {
+        bail!("--tar-tee requires `-` as an input");
+    }
+    let differs = Arc::new(AtomicBool::new(false));
+    let comparison = if args.compare {
+        let base = args.directory.clone().unwrap_or_default();
+        let option = if args.diff { "--diff" } else { "--compare" };
+        let [a, b] = args.input.as_slice() else {
+            bail!("{} requires two directories", option);
+        };
+        if a == b {
+            bail!("{} requires two different directories", option);
+        }
+        for dir in [a, b] {
+            if !base.join(dir).is_dir() {
+                bail!("{} requires directories: {}", option, dir.display());
             }
-            let tar_path = path.join(file.path()?);
-            self._digest_print(&tar_path, &mut file)?;
         }
-        Ok(())
+        args.recursive = true;
+        Some(Comparison::new(
+            a.clone(),
+            b.clone(),
+            differs.clone(),
+            args.diff,
+        ))
+    } else {
+        None
+    };
+
+    let trees = if args.tree_hash {
+        args.recursive = true;
+        let base = args.directory.clone().unwrap_or_default();
+        let roots = args
+            .input
+            .iter()
+            .map(|input| {
+                let (path, algorithm) = split_algorithm(&base, input.clone());
+                (path, algorithm.unwrap_or(args.hash))
+            })
+            .collect();
+        Some(Trees::new(roots))
+    } else {
+        None
+    };
+
+    let n_jobs = if args.jobs == 0 {
+        let base = args.directory.clone().unwrap_or_default();
+        let tasks = (args.check.is_none()
+            && !args.input.iter().any(|input| base.join(input).is_dir()))
+        .then_some(args.input.len() * args.threads_per_archive);
+        auto_jobs(
+            std::thread::available_parallelism()?.get(),
+            args.io_bound,
+            tasks,
+        )
+    } else {
+        args.jobs
+    };
+    debug!("n_jobs: {}", n_jobs);
+
+    let buffer_size = if args.buffer == "auto" {
+        auto_buffer_size(available_memory(), n_jobs)
+    } else {
+        parse_size::parse_size(&args.buffer).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to parse buffer size: {} (example: 1M, 1MiB, 1MB, 1Mib, 1m, 1, auto, ...)",
+                e
+            )
+        })? as usize
+    };
+    debug!("buffer_size: {}", buffer_size);
+    check_buffer_memory(buffer_size, n_jobs, available_memory())?;
+    let mmap_threshold = parse_size::parse_size(&args.mmap_threshold).map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to parse mmap threshold: {} (example: 1M, 64K, 0, ...)",
+            e
+        )
+    })?;
+    let block_tree = match &args.block_tree {
+        Some(size) => match parse_size::parse_size(size) {
+            Ok(0) => bail!("--block-tree must be positive"),
+            Ok(size) => Some(size as usize),
+            Err(e) => bail!("Failed to parse block size: {} (example: 1M, 256K, ...)", e),
+        },
+        None => None,
+    };
+
+    #[cfg(not(target_os = "linux"))]
+    if args.direct {
+        warn!("--direct is only supported on Linux. Falling back to buffered read.");
     }
 
-    fn digest_tar(&mut self, path: &Path) -> Result<()> {
-        let file = File::open(path)?;
-        self._digest_tar(path, file)
+    if args.format == PrintFormat::Signify
+        && !matches!(args.hash, Algorithm::Sha256 | Algorithm::Sha512)
+    {
+        bail!("signify supports only SHA256 and SHA512. Use --hash sha256 or --hash sha512.");
     }
 
-    fn digest_tar_gz(&mut self, path: &Path) -> Result<()> {
-        let file = File::open(path)?;
-        self._digest_tar(path, GzDecoder::new(file))
+    if args.format == PrintFormat::Bazel
+        && !matches!(args.hash, Algorithm::Sha256 | Algorithm::Sha512)
+    {
+        bail!("bazel supports only SHA256 and SHA512. Use --hash sha256 or --hash sha512.");
     }
 
-    fn digest_tar_zstd(&mut self, path: &Path) -> Result<()> {
-        let file = File::open(path)?;
-        self._digest_tar(path, zstd::Decoder::new(file)?)
+    if args.format == PrintFormat::GitLfs && args.hash != Algorithm::Sha256 {
+        bail!("git-lfs supports only SHA256. Use --hash sha256.");
     }
 
-    fn digest_tar_bz2(&mut self, path: &Path) -> Result<()> {
-        let file = File::open(path)?;
-        self._digest_tar(path, bzip2::read::BzDecoder::new(file))
+    if args.format == PrintFormat::Ostree {
+        if args.hash != Algorithm::Sha256 {
+            bail!("ostree supports only SHA256. Use --hash sha256.");
+        }
+        if args.only_text || args.double {
+            bail!("ostree can't be used with --only-text or --double");
+        }
     }
 
-    fn digest_tar_xz(&mut self, path: &Path) -> Result<()> {
-        let file = File::open(path)?;
-        self._digest_tar(path, xz2::read::XzDecoder::new(file))
+    if args.format == PrintFormat::Fields
+        && (args.field_sep.contains(&args.record_sep) || args.record_sep.contains(&args.field_sep))
+    {
+        bail!("--field-sep and --record-sep must be distinct");
     }
-}
 
-#[derive(Debug, Clone, Copy)]
-struct BufHashFactory {
-    buffer_size: usize,
-    format: PrintFormat,
-    algorithm: Algorithm,
-}
+    if args.format == PrintFormat::Spdx && args.hash != Algorithm::Sha256 {
+        warn!("SPDX tools expect SHA256 checksums. Consider --hash sha256.");
+    }
 
-impl BufHashFactory {
-    fn new(buffer_size: usize, format: PrintFormat, algorithm: Algorithm) -> Self {
-        BufHashFactory {
+    if args.format == PrintFormat::ChunksJson
+        && (args.external_hasher.is_some() || args.checkpoint.is_some() || args.names_only)
+    {
+        bail!("chunks-json can't be used with --external-hasher, --checkpoint, or --names-only");
+    }
+
+    if let Some(expected) = &args.expect {
+        let expected = expected.to_ascii_lowercase();
+        let hash_is_default = matches!(
+            matches.value_source("hash"),
+            None | Some(ValueSource::DefaultValue)
+        );
+        if hash_is_default && args.external_hasher.is_none() {
+            args.hash = infer_algorithm(&expected)?;
+        }
+        debug!("Expecting {} hash {}", args.hash.label(), expected);
+        let base = args.directory.unwrap_or_default();
+        let [input] = args.input.as_slice() else {
+            bail!("--expect requires a single file input");
+        };
+        if !base.join(input).is_file() {
+            bail!("--expect requires a single file input");
+        }
+        let factory = BufHashFactory::new(
             buffer_size,
-            format,
+            HashOptions {
+                mmap: args.mmap,
+                mmap_threshold,
+                direct: args.direct,
+                double: args.double,
+                ostree: args.format == PrintFormat::Ostree,
+                ..Default::default()
+            },
+            args.external_hasher.map(Arc::from),
+            Vec::new(),
+        );
+        let (sender, receiver) = mpsc::channel();
+        factory
+            .create(args.hash, Arc::from(base.as_path()), sender)
+            .digest_file(input)?;
+        let record = receiver.recv()?;
+        let matched = output::to_hex(&record.hash) == expected;
+        let status = if matched { "OK" } else { "FAILED" };
+        let mut out = Output::create(args.output.as_deref(), args.compress_output)?;
+        let result = write_status(&mut out, &input.display().to_string(), status)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| out.finish());
+        if let Err(e) = &result {
+            args.broken_pipe.handle(e);
+        }
+        result?;
+        if !matched {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if args.checkpoint.is_some() {
+        if args.hash != Algorithm::Blake3 {
+            bail!("--checkpoint is only supported with --hash blake3");
+        }
+        if args.input.len() != 1
+            || !args
+                .directory
+                .clone()
+                .unwrap_or_default()
+                .join(&args.input[0])
+                .is_file()
+        {
+            bail!("--checkpoint requires a single file input");
+        }
+    }
+
+    let listed = args.skip_listed.as_deref().map(Listed::read).transpose()?;
+
+    let baseline = match &args.diff_baseline {
+        Some(path) => Some(Baseline::new(
+            manifest::read_manifest(path)?,
+            args.show_deleted,
+            args.status,
+        )),
+        None => None,
+    };
+
+    if let Some(manifest) = &args.check {
+        let hash_is_default = matches!(
+            matches.value_source("hash"),
+            None | Some(ValueSource::DefaultValue)
+        );
+        let algorithm = (!hash_is_default || args.external_hasher.is_some()).then_some(args.hash);
+        let factory = BufHashFactory::new(
+            buffer_size,
+            HashOptions {
+                mmap: args.mmap,
+                mmap_threshold,
+                direct: args.direct,
+                double: args.double,
+                ..Default::default()
+            },
+            args.external_hasher.map(Arc::from),
+            Vec::new(),
+        );
+        let pool_options = PoolOptions {
+            n_jobs,
+            pin_threads: args.pin_threads,
+            listed: None,
+            open_files: args.max_open_files.map(|n| Arc::new(OpenFiles::new(n))),
+            ordered: false,
+            failures: Arc::default(),
+        };
+        let base = args.directory.unwrap_or_default();
+        let mut out = Output::create(args.output.as_deref(), args.compress_output)?;
+        let result = check(
+            manifest,
+            &base,
             algorithm,
+            args.quiet,
+            pool_options,
+            factory,
+            &mut out,
+        )
+        .and_then(|matched| {
+            out.finish()?;
+            Ok(matched)
+        });
+        if let Err(e) = &result {
+            args.broken_pipe.handle(e);
         }
+        if !result? {
+            std::process::exit(1);
+        }
+        return Ok(());
     }
-    fn create(&self) -> Box<dyn DigestPrint> {
-        match self.algorithm {
-            Algorithm::Md5 => Box::new(BufHash::<md5::Md5>::new(self.buffer_size, self.format)),
-            Algorithm::Sha1 => Box::new(BufHash::<sha1::Sha1>::new(self.buffer_size, self.format)),
+
+    let (record_sender, record_receiver) = mpsc::channel();
+    let printer = Printer::new(
+        SyslogTee::new(
+            Output::create(args.output.as_deref(), args.compress_output)?,
+            args.syslog,
+        ),
+        PrintOptions {
+            format: args.format,
+            algorithm: args.hash,
+            also_hash: also_hash.clone(),
+            archive_column: args.archive_column,
+            byte_order: args.byte_order,
+            dir_hashes: args.dir_hashes,
+            size: args.size,
+            human: args.human,
+            total: args.total,
+            crc32: args.also_crc32,
+            mode: args.show_mode,
+            canonical: args.canonical,
+            sort: args.sort,
+            ordered: args.ordered,
+            block_tree: block_tree.is_some(),
+            field_sep: args.field_sep.clone(),
+            record_sep: args.record_sep.clone(),
+            group_hash: args.group_hash.map(usize::from),
+            uppercase: args.uppercase,
+            base64: args.base64,
+            zero: args.zero,
+            base_dir: args.base_dir.clone(),
+            strip_archive: args.dereference_archive_paths,
+            archive_separator: args.archive_separator.clone(),
+            spill_threshold: args.spill_threshold,
+            progress: args.progress,
+            broken_pipe: args.broken_pipe,
+            sidecar: args.sidecar.map(|contents| Sidecar {
+                contents,
+                base: args.directory.clone().unwrap_or_default(),
+            }),
+            // Like `head`, a single file is printed without a header
+            pointer_headers: match args.input.as_slice() {
+                [input] => {
+                    let base = args.directory.clone().unwrap_or_default();
+                    !base.join(input).is_file()
+                        || args.archive && ArchiveType::from_path(input).is_some()
+                }
+                _ => true,
+            },
+        },
+        baseline,
+        args.group_by_hash
+            .then(|| Groups::new(args.duplicates_only)),
+        comparison,
+        trees,
+    );
+    let broken_pipe = args.broken_pipe;
+    let printer = thread::spawn(move || {
+        let result = printer.run(record_receiver)?.into_inner().finish();
+        if let Err(e) = &result {
+            broken_pipe.handle(e);
         }
+        result
+    });
+
+    if let Some(checkpoint) = &args.checkpoint {
+        let base = args.directory.unwrap_or_default();
+        let path = args.input.into_iter().next().unwrap();
+        let fs_path = base.join(&path);
+        let result = checkpoint::hash_file(&fs_path, checkpoint).and_then(|mut hash| {
+            if args.double {
+                hash = blake3::hash(hash.as_bytes());
+            }
+            record_sender
+                .send(Record {
+                    archive: None,
+                    path,
+                    hash: hash.as_bytes().to_vec(),
+                    algorithm: Algorithm::Blake3,
+                    size: Some(fs_path.metadata()?.len()),
+                    chunks: Vec::new(),
+                    crc32: None,
+                    mode: None,
+                    also_hashes: Vec::new(),
+                    seq: 0,
+                })
+                .map_err(|_| anyhow::anyhow!("Printer disconnected"))
+        });
+        drop(record_sender);
+        printer.join().unwrap()?;
+        return result;
     }
-}
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum ArchiveType {
-    Zip,
-    Tar,
-    TarGz,
-    TarZstd,
-    TarBz2,
-    TarXz,
+    if let Some(deadline) = args.deadline {
+        thread::spawn(move || {
+            thread::sleep(deadline);
+            cancel::request();
+            debug!("Deadline exceeded");
+        });
+    }
+
+    let flags = Flags::try_from(&args)?;
+    if args.names_only {
+        let base = args.directory.unwrap_or_default();
+        let result = args.input.into_iter().try_for_each(|input| {
+            if !base.join(&input).exists() {
+                bail!("{}: No such file or directory", input.display());
+            }
+            let hash = hash_names(&base, &input, &flags)?;
+            record_sender
+                .send(Record {
+                    archive: None,
+                    path: input,
+                    hash,
+                    algorithm: flags.algorithm,
+                    size: None,
+                    chunks: Vec::new(),
+                    crc32: None,
+                    mode: None,
+                    also_hashes: Vec::new(),
+                    seq: 0,
+                })
+                .map_err(|_| anyhow::anyhow!("Printer disconnected"))
+        });
+        drop(record_sender);
+        printer.join().unwrap()?;
+        return result;
+    }
+
+    let failures = Arc::new(AtomicUsize::new(0));
+    let result = hash_inputs(
+        &args.directory.unwrap_or_default(),
+        args.input,
+        flags,
+        PoolOptions {
+            n_jobs,
+            pin_threads: args.pin_threads,
+            listed,
+            open_files: args.max_open_files.map(|n| Arc::new(OpenFiles::new(n))),
+            ordered: args.ordered,
+            failures: Arc::clone(&failures),
+        },
+        BufHashFactory::new(
+            buffer_size,
+            HashOptions {
+                mmap: args.mmap,
+                mmap_threshold,
+                direct: args.direct,
+                retry_archive: args.retry_archive,
+                archive_recursive: args.archive_recursive,
+                double: args.double,
+                chunking: if let Some(len) = block_tree {
+                    Some(Chunking::Fixed { len })
+                } else if args.format == PrintFormat::ChunksJson {
+                    Some(Chunking::ContentDefined {
+                        seed: args.chunk_seed,
+                    })
+                } else {
+                    None
+                },
+                only: if args.only_text {
+                    Some(ContentKind::Text)
+                } else if args.only_binary {
+                    Some(ContentKind::Binary)
+                } else {
+                    None
+                },
+                verify_sizes: args.verify_sizes,
+                also_crc32: args.also_crc32,
+                symlink_target: args.hash_symlink_target,
+                show_mode: args.show_mode,
+                ignore_archive_modes: args.ignore_archive_modes,
+                ostree: args.format == PrintFormat::Ostree,
+                tar_tee: args.tar_tee,
+                stdin_archive: args.archive,
+            },
+            args.external_hasher.map(Arc::from),
+            also_hash,
+        ),
+        record_sender,
+        &args.stdin_name,
+    );
+
+    // The workers are done. Wait for the printer to write the remaining records.
+    printer.join().unwrap()?;
+    result?;
+    if cancel::skipped() {
+        warn!("Stopped at the deadline. The output is incomplete.");
+        std::process::exit(124);
+    }
+    let n_failed = failures.load(Ordering::Relaxed);
+    if n_failed > 0 {
+        error!(
+            "{} {} could not be hashed",
+            n_failed,
+            if n_failed == 1 { "file" } else { "files" }
+        );
+        std::process::exit(1);
+    }
+    if differs.load(Ordering::Relaxed) {
+        std::process::exit(1);
+    }
+    Ok(())
 }
 
-impl ArchiveType {
-    fn from_path(path: &Path) -> Option<Self> {
-        let is_tar = path
-            .file_stem()
-            .map_or(false, |s| s.to_string_lossy().ends_with(".tar"));
+/// Input that means stdin
+const STDIN: &str = "-";
+
+/// Hash `inputs`, which are relative to `base`, and send the records to `record_sender`.
+///
+/// Printed paths are relative to `base` as well. The current directory is never changed.
+/// `-` is stdin, printed as `stdin_name`.
+fn hash_inputs(
+    base: &Path,
+    inputs: Vec<PathBuf>,
+    flags: Flags,
+    pool_options: PoolOptions,
+    hasher_factory: BufHashFactory,
+    record_sender: mpsc::Sender<Record>,
+    stdin_name: &Path,
+) -> Result<()> {
+    let inputs: Vec<_> = inputs
+        .into_iter()
+        .map(|input| split_algorithm(base, input))
+        .collect();
 
-        match (path.extension().unwrap_or_default().to_str(), is_tar) {
-            (Some("zip"), _) => Some(ArchiveType::Zip),
-            (Some("tar"), _) => Some(ArchiveType::Tar),
-            (Some("tgz") | Some("taz"), _) => Some(ArchiveType::TarGz),
-            (Some("tz2") | Some("tbz") | Some("tbz2"), _) => Some(ArchiveType::TarBz2),
-            (Some("gz"), true) => Some(ArchiveType::TarGz),
-            (Some("zst"), true) => Some(ArchiveType::TarZstd),
-            (Some("bz2"), true) => Some(ArchiveType::TarBz2),
-            (Some("xz"), true) => Some(ArchiveType::TarXz),
-            _ => None,
+    // An archive is read sequentially, so a single archive input would leave the workers idle
+    if let [(input, algorithm)] = inputs.as_slice() {
+        let listed = pool_options
+            .listed
+            .as_ref()
+            .is_some_and(|listed| listed.contains(input));
+        let is_link = flags.no_dereference && base.join(input).is_symlink();
+        if flags.archive && base.join(input).is_file() && !listed && !is_link {
+            if let Some(archive_type) = ArchiveType::from_path(input)
+                .filter(|&archive_type| !flags.splits(input, archive_type))
+            {
+                debug!(target: "hashall::pool", "Hashing the single archive on the main thread");
+                let algorithm = algorithm.unwrap_or(flags.algorithm);
+                let mut hasher = hasher_factory.create(algorithm, Arc::from(base), record_sender);
+                // Reported like a failure in a worker
+                if let Err(e) = hasher.digest_archive(input, archive_type) {
+                    report_failure(
+                        &pool_options.failures,
+                        format_args!("{}: {:#}", input.display(), e),
+                    );
+                }
+                return Ok(());
+            }
         }
     }
+
+    let stdin_sender = record_sender.clone();
+    let mut pool = ThreadPool::new(pool_options, hasher_factory.clone(), base, record_sender);
+    // process inputs regardless of all option
+    for (input, algorithm) in inputs {
+        if cancel::should_stop() {
+            break;
+        }
+        let flags = Flags {
+            algorithm: algorithm.unwrap_or(flags.algorithm),
+            ..flags.clone()
+        };
+        if input.as_os_str() == STDIN {
+            // Read on the main thread while the workers hash the other inputs
+            let base = Arc::from(base);
+            if let Some(seq) = pool.take_seq() {
+                let (tagged_sender, tagged_receiver) = mpsc::channel();
+                let mut hasher = hasher_factory.create(flags.algorithm, base, tagged_sender);
+                hasher.digest_stdin(stdin_name)?;
+                forward_tagged(&tagged_receiver, &stdin_sender, seq);
+            } else {
+                let mut hasher = hasher_factory.create(flags.algorithm, base, stdin_sender.clone());
+                hasher.digest_stdin(stdin_name)?;
+            }
+            continue;
+        }
+        let fs_path = base.join(&input);
+        if flags.no_dereference && fs_path.is_symlink() {
+            if flags.symlinks {
+                // Not opened, so never an archive
+                pool.process_file(input, flags.algorithm);
+            } else {
+                warn!("{}: Skipped (symlink)", input.display());
+            }
+            continue;
+        }
+        if !fs_path.exists() {
+            bail!("{}: No such file or directory", input.display());
+        }
+        if fs_path.is_file() {
+            process_file(&mut pool, input, &flags);
+        } else if fs_path.is_dir() {
+            process_dir(&mut pool, base, &input, &flags)?;
+        };
+    }
+    Ok(())
 }
 
-impl<H> DigestPrint for BufHash<H>
-where
-    H: Digest + FixedOutputReset,
-    <H as digest::OutputSizeUser>::OutputSize: std::ops::Add,
-    <<H as digest::OutputSizeUser>::OutputSize as std::ops::Add>::Output:
-        digest::generic_array::ArrayLength<u8>,
-{
-    fn digest_file(&mut self, path: &Path) -> Result<()> {
-        let file = File::open(path)?;
-        self._digest_print(path, file)?;
-        Ok(())
+/// Verify the files listed in `manifest` and write `<file>: OK` or `<file>: FAILED` to `out` in
+/// the order of the manifest (only the failures if `quiet`). Returns whether all files match.
+fn check(
+    manifest: &Path,
+    base: &Path,
+    algorithm: Option<Algorithm>,
+    quiet: bool,
+    pool_options: PoolOptions,
+    hasher_factory: BufHashFactory,
+    out: &mut impl Write,
+) -> Result<bool> {
+    let mut entries = Vec::new();
+    for entry in manifest::read_manifest(manifest)? {
+        // --hash, then the algorithm named in BSD-style lines, then the length of the hash
+        let algorithm =
+            match algorithm.or_else(|| entry.label.as_deref().and_then(Algorithm::from_label)) {
+                Some(algorithm) => algorithm,
+                None => infer_algorithm(&entry.hash)
+                    .map_err(|e| anyhow::anyhow!("{}: {}", entry.path, e))?,
+            };
+        // Paths written on another OS are tried with the separators of this one
+        let mut path = PathBuf::from(&entry.path);
+        if !base.join(&path).exists() {
+            let normalized = PathBuf::from(manifest::normalize_separators(&entry.path));
+            if base.join(&normalized).exists() {
+                path = normalized;
+            }
+        }
+        entries.push((entry, path, algorithm));
     }
 
-    fn digest_archive(&mut self, path: &Path, archive_type: ArchiveType) -> Result<()> {
-        match archive_type {
-            ArchiveType::Zip => self.digest_zip(path),
-            ArchiveType::Tar => self.digest_tar(path),
-            ArchiveType::TarGz => self.digest_tar_gz(path),
-            ArchiveType::TarZstd => self.digest_tar_zstd(path),
-            ArchiveType::TarBz2 => self.digest_tar_bz2(path),
-            ArchiveType::TarXz => self.digest_tar_xz(path),
+    let (record_sender, record_receiver) = mpsc::channel();
+    let mut pool = ThreadPool::new(pool_options, hasher_factory, base, record_sender);
+    for (_, path, algorithm) in &entries {
+        if base.join(path).is_file() {
+            pool.process_file(path.clone(), *algorithm);
         }
     }
-}
+    // Wait for the workers, which drops the senders
+    drop(pool);
+    let hashes: HashMap<_, _> = record_receiver
+        .into_iter()
+        .map(|record| {
+            (
+                (record.path, record.algorithm),
+                output::to_hex(&record.hash),
+            )
+        })
+        .collect();
 
-fn process_file(pool: &mut ThreadPool, input: PathBuf, flags: Flags) {
-    if flags.archive {
-        if let Some(archive_type) = ArchiveType::from_path(&input) {
-            pool.process_archive(input, archive_type);
-        } else {
-            pool.process_file(input);
+    let mut n_failed = 0;
+    for (entry, path, algorithm) in &entries {
+        match hashes.get(&(path.clone(), *algorithm)) {
+            Some(hash) if *hash == entry.hash => {
+                if !quiet {
+                    write_status(out, &entry.path, "OK")?;
+                }
+            }
+            Some(_) => {
+                write_status(out, &entry.path, "FAILED")?;
+                n_failed += 1;
+            }
+            None => {
+                warn!("{}: No such file", entry.path);
+                write_status(out, &entry.path, "FAILED open or read")?;
+                n_failed += 1;
+            }
         }
-    } else {
-        pool.process_file(input);
     }
+    if n_failed > 0 {
+        // Not a log message, so that it's printed at any log level like in `md5sum -c`
+        eprintln!("{} of {} files FAILED", n_failed, entries.len());
+    }
+    Ok(n_failed == 0)
 }
 
-fn process_dir(pool: &mut ThreadPool, input: PathBuf, flags: Flags) -> Result<()> {
-    let walker = if flags.recursive {
-        WalkDir::new(input)
+/// Write `<path>: <status>`. Paths with a backslash or a line break are escaped, after a
+/// leading `\`, as in the sum format and `md5sum -c`.
+fn write_status(out: &mut impl Write, path: &str, status: &str) -> std::io::Result<()> {
+    match output::escape_sum_path(path) {
+        Some(escaped) => writeln!(out, "\\{}: {}", escaped, status),
+        None => writeln!(out, "{}: {}", path, status),
+    }
+}
+
+/// Paths listed in `list` (or stdin if it's `-`), one per line or NUL-terminated if `zero`.
+/// Blank lines are skipped.
+fn read_file_list(list: &Path, zero: bool) -> Result<Vec<PathBuf>> {
+    let reader: Box<dyn std::io::BufRead> = if list.as_os_str() == STDIN {
+        Box::new(std::io::stdin().lock())
     } else {
-        WalkDir::new(input).min_depth(1).max_depth(1)
+        let file = File::open(list).map_err(|e| anyhow::anyhow!("{}: {}", list.display(), e))?;
+        Box::new(std::io::BufReader::new(file))
     };
-    for entry in walker
-        .into_iter()
-        .filter_entry(|e| flags.all || !is_hidden(e))
-    {
-        let entry = entry?;
-        if entry.file_type().is_file() {
-            process_file(pool, entry.into_path(), flags);
+    let mut paths = Vec::new();
+    if zero {
+        for path in std::io::BufRead::split(reader, b'\0') {
+            let path = path?;
+            if !path.is_empty() {
+                paths.push(PathBuf::from(String::from_utf8(path)?));
+            }
         }
+        return Ok(paths);
     }
-    Ok(())
+    for line in std::io::BufRead::lines(reader) {
+        let line = line?;
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        if !line.is_empty() {
+            paths.push(PathBuf::from(line));
+        }
+    }
+    Ok(paths)
 }
 
-fn main() -> Result<()> {
-    env_logger::init();
-    let args = Args::parse();
-
-    let buffer_size: usize = parse_size::parse_size(&args.buffer).map_err(|e| {
-        anyhow::anyhow!(
-            "Failed to parse buffer size: {} (example: 1M, 1MiB, 1MB, 1Mib, 1m, 1, ...)",
-            e
-        )
-    })? as usize;
-    debug!("buffer_size: {}", buffer_size);
+/// Maximum number of workers with `--io-bound`
+const IO_BOUND_JOBS: usize = 4;
 
-    if args.format == PrintFormat::Csv {
-        println!("hash,filename");
+/// Number of workers for `--jobs 0`. `tasks` is the number of jobs of the inputs if they are
+/// all files (or stdin), each job hashed by a single worker, so more workers than that would
+/// be idle.
+fn auto_jobs(cores: usize, io_bound: bool, tasks: Option<usize>) -> usize {
+    let mut n_jobs = cores;
+    if io_bound {
+        n_jobs = n_jobs.min(IO_BOUND_JOBS);
     }
+    if let Some(tasks) = tasks {
+        n_jobs = n_jobs.min(tasks);
+    }
+    n_jobs.max(1)
+}
 
-    let n_jobs = if args.jobs == 0 {
-        std::thread::available_parallelism()?.get()
-    } else {
-        args.jobs
+/// Fraction of the available memory that the buffers of `--buffer auto` use together
+const AUTO_BUFFER_MEMORY_DIVISOR: u64 = 16;
+/// Bounds of the buffer size of each worker with `--buffer auto`
+const AUTO_BUFFER_MIN: usize = 64 * 1024;
+const AUTO_BUFFER_MAX: usize = 16 * 1024 * 1024;
+/// Buffer size with `--buffer auto` if the available memory is unknown (the default of --buffer)
+const AUTO_BUFFER_FALLBACK: usize = 1024 * 1024;
+
+/// Buffer size of each worker for `--buffer auto`: 1/16 of the available memory divided
+/// among the `n_jobs` workers, between 64 KiB and 16 MiB
+fn auto_buffer_size(available: Option<u64>, n_jobs: usize) -> usize {
+    let Some(available) = available else {
+        return AUTO_BUFFER_FALLBACK;
     };
-    debug!("n_jobs: {}", n_jobs);
+    let share = available / AUTO_BUFFER_MEMORY_DIVISOR / n_jobs.max(1) as u64;
+    usize::try_from(share)
+        .unwrap_or(usize::MAX)
+        .clamp(AUTO_BUFFER_MIN, AUTO_BUFFER_MAX)
+}
 
-    let mut pool = ThreadPool::new(
+/// Fail if the buffers of all workers (`buffer_size * n_jobs` bytes) don't fit in the
+/// `available` memory, and warn if they take more than half of it
+fn check_buffer_memory(buffer_size: usize, n_jobs: usize, available: Option<u64>) -> Result<()> {
+    let Some(available) = available else {
+        return Ok(());
+    };
+    let total = (buffer_size as u64).saturating_mul(n_jobs as u64);
+    let message = format!(
+        "{} jobs with a buffer of {} each allocate {} in total, but only {} of memory is available",
         n_jobs,
-        BufHashFactory::new(buffer_size, args.format, args.hash),
+        output::human_size(buffer_size as u64),
+        output::human_size(total),
+        output::human_size(available)
     );
+    if total > available {
+        bail!(
+            "{}. Use a smaller --buffer (or --buffer auto) or fewer --jobs.",
+            message
+        );
+    }
+    if total > available / 2 {
+        warn!("{}. Consider a smaller --buffer or fewer --jobs.", message);
+    }
+    Ok(())
+}
 
-    let flags = Flags::from(&args);
+/// Memory available for new allocations without swapping, in bytes. `None` if unknown.
+#[cfg(target_os = "linux")]
+fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    // e.g. `MemAvailable:   12345678 kB`
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
 
-    // process inputs regardless of all option
-    for input in args.input {
-        if !input.exists() {
-            bail!("{}: No such file or directory", input.display());
-        }
-        if input.is_file() {
-            process_file(&mut pool, input, flags);
-        } else if input.is_dir() {
-            process_dir(&mut pool, input, flags)?;
-        };
+#[cfg(not(target_os = "linux"))]
+fn available_memory() -> Option<u64> {
+    None
+}
+
+/// Algorithm of `hash` by its number of hex digits. Fails if no algorithm or more than one
+/// gives hashes of that length, e.g. 64 digits for SHA256, SHA3-256, and BLAKE3.
+fn infer_algorithm(hash: &str) -> Result<Algorithm> {
+    match Algorithm::with_hex_len(hash.len()).as_slice() {
+        [algorithm] => Ok(*algorithm),
+        [] => bail!(
+            "can't infer the algorithm from the hash {}. Use --hash.",
+            hash
+        ),
+        algorithms => bail!(
+            "hashes of {} hex digits can be {}. Use --hash.",
+            hash.len(),
+            algorithms
+                .iter()
+                .map(|algorithm| algorithm.label().to_ascii_lowercase())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
     }
-    Ok(())
+}
+
+/// Split `path:algorithm` into the path and the algorithm.
+/// An existing file or directory is never split, so `:` can still be part of a name.
+fn split_algorithm(base: &Path, input: PathBuf) -> (PathBuf, Option<Algorithm>) {
+    if base.join(&input).exists() {
+        return (input, None);
+    }
+    let split = input.to_str().and_then(|s| {
+        let (path, algorithm) = s.rsplit_once(':')?;
+        let algorithm = <Algorithm as clap::ValueEnum>::from_str(algorithm, true).ok()?;
+        Some((PathBuf::from(path), Some(algorithm)))
+    });
+    split.unwrap_or((input, None))
 }
 
 #[cfg(test)]
@@ -460,61 +2220,150 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_archive_type() {
-        assert!(ArchiveType::from_path(Path::new("file.txt")).is_none());
-        assert_eq!(
-            ArchiveType::from_path(Path::new("archive.zip")).unwrap(),
-            ArchiveType::Zip
-        );
-        assert_eq!(
-            ArchiveType::from_path(Path::new("archive.tar")).unwrap(),
-            ArchiveType::Tar
-        );
+    fn test_open_files() {
+        let open_files = Arc::new(OpenFiles::new(2));
+        let open = Arc::new(Mutex::new((0, 0)));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let open_files = Arc::clone(&open_files);
+                let open = Arc::clone(&open);
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        let _guard = open_files.acquire();
+                        {
+                            let mut open = open.lock().unwrap();
+                            open.0 += 1;
+                            open.1 = open.1.max(open.0);
+                        }
+                        thread::yield_now();
+                        open.lock().unwrap().0 -= 1;
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let (now, max) = *open.lock().unwrap();
+        assert_eq!(now, 0);
+        assert!(max <= 2, "{max} files open at once");
+    }
 
-        assert_eq!(
-            ArchiveType::from_path(Path::new("archive.tar.gz")).unwrap(),
-            ArchiveType::TarGz
-        );
-        assert_eq!(
-            ArchiveType::from_path(Path::new("archive.tgz")).unwrap(),
-            ArchiveType::TarGz
-        );
-        assert_eq!(
-            ArchiveType::from_path(Path::new("archive.taz")).unwrap(),
-            ArchiveType::TarGz
-        );
-        assert!(ArchiveType::from_path(Path::new("archive.gz")).is_none(),);
-        assert!(ArchiveType::from_path(Path::new("archive.tar.gz.txt")).is_none(),);
-        // This should be None.
-        // Leaving it as TarGz for now because directory input does not reach this function.
-        assert_eq!(
-            ArchiveType::from_path(Path::new("archive.tar.gz/")).unwrap(),
-            ArchiveType::TarGz
-        );
+    #[test]
+    fn test_split_entries() {
+        assert_eq!(split_entries(10, 3), [0..4, 4..8, 8..10]);
+        assert_eq!(split_entries(9, 3), [0..3, 3..6, 6..9]);
+        assert_eq!(split_entries(2, 4), [0..1, 1..2]);
+        assert_eq!(split_entries(5, 1).len(), 1);
+        assert!(split_entries(0, 4).is_empty());
+    }
 
+    #[test]
+    fn test_auto_jobs() {
+        // Inputs with directories
+        assert_eq!(auto_jobs(16, false, None), 16);
+        assert_eq!(auto_jobs(16, true, None), 4);
+        assert_eq!(auto_jobs(2, true, None), 2);
+        // A few large files
+        assert_eq!(auto_jobs(16, false, Some(3)), 3);
+        assert_eq!(auto_jobs(16, true, Some(3)), 3);
+        assert_eq!(auto_jobs(16, true, Some(100)), 4);
+        assert_eq!(auto_jobs(8, false, Some(100)), 8);
+        // There is always a worker
+        assert_eq!(auto_jobs(8, false, Some(0)), 1);
+    }
+
+    #[test]
+    fn test_auto_buffer_size() {
+        const GIB: u64 = 1024 * 1024 * 1024;
+        // 16 GiB / 16 / 64 jobs = 16 MiB
+        assert_eq!(auto_buffer_size(Some(16 * GIB), 64), 16 * 1024 * 1024);
+        assert_eq!(auto_buffer_size(Some(16 * GIB), 1), AUTO_BUFFER_MAX);
+        // 1 GiB / 16 / 32 jobs = 2 MiB
+        assert_eq!(auto_buffer_size(Some(GIB), 32), 2 * 1024 * 1024);
+        assert_eq!(auto_buffer_size(Some(GIB / 16), 1024), AUTO_BUFFER_MIN);
+        assert_eq!(auto_buffer_size(None, 8), AUTO_BUFFER_FALLBACK);
+    }
+
+    #[test]
+    fn test_check_buffer_memory() {
+        const MIB: usize = 1024 * 1024;
+        assert!(check_buffer_memory(MIB, 8, Some(1 << 30)).is_ok());
+        // 1G * 64 jobs
+        let error = check_buffer_memory(1024 * MIB, 64, Some(16 << 30)).unwrap_err();
+        assert!(error.to_string().contains("64G in total"), "{}", error);
+        // More than half is allowed with a warning
+        assert!(check_buffer_memory(1024 * MIB, 12, Some(16 << 30)).is_ok());
+        assert!(check_buffer_memory(usize::MAX, usize::MAX, Some(u64::MAX - 1)).is_err());
+        // Unknown
+        assert!(check_buffer_memory(usize::MAX, 64, None).is_ok());
+    }
+
+    #[test]
+    fn test_hash_inputs_concurrently() {
+        let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+        let flags = Flags {
+            hidden_files: false,
+            hidden_dirs: false,
+            recursive: false,
+            max_depth: None,
+            archive: false,
+            sniff_archives: false,
+            threads_per_archive: 1,
+            symlinks: false,
+            follow_symlinks: false,
+            no_dereference: false,
+            warn_empty: false,
+            algorithm: Algorithm::Md5,
+            exclude: None,
+            include: None,
+            output: None,
+        };
+        let factory = BufHashFactory::new(1024, HashOptions::default(), None, Vec::new());
+        let handles: Vec<_> = [data.clone(), data.join("directory")]
+            .into_iter()
+            .map(|base| {
+                let factory = factory.clone();
+                let flags = flags.clone();
+                thread::spawn(move || {
+                    let (sender, receiver) = mpsc::channel();
+                    hash_inputs(
+                        &base,
+                        vec!["file.txt".into()],
+                        flags,
+                        PoolOptions {
+                            n_jobs: 2,
+                            pin_threads: false,
+                            listed: None,
+                            open_files: None,
+                            ordered: false,
+                            failures: Arc::default(),
+                        },
+                        factory,
+                        sender,
+                        Path::new(STDIN),
+                    )
+                    .unwrap();
+                    receiver
+                        .into_iter()
+                        .map(|r| (r.full_path(), output::to_hex(&r.hash)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
         assert_eq!(
-            ArchiveType::from_path(Path::new("archive.tar.zst")).unwrap(),
-            ArchiveType::TarZstd
-        );
-        assert_eq!(
-            ArchiveType::from_path(Path::new("archive.tar.bz2")).unwrap(),
-            ArchiveType::TarBz2
-        );
-        assert_eq!(
-            ArchiveType::from_path(Path::new("archive.tz2")).unwrap(),
-            ArchiveType::TarBz2
-        );
-        assert_eq!(
-            ArchiveType::from_path(Path::new("archive.tbz")).unwrap(),
-            ArchiveType::TarBz2
-        );
-        assert_eq!(
-            ArchiveType::from_path(Path::new("archive.tbz2")).unwrap(),
-            ArchiveType::TarBz2
-        );
-        assert_eq!(
-            ArchiveType::from_path(Path::new("archive.tar.xz")).unwrap(),
-            ArchiveType::TarXz
+            results,
+            [
+                [(
+                    PathBuf::from("file.txt"),
+                    "ac175545a9b0f6da0d5c03f5135563d8".to_string()
+                )],
+                [(
+                    PathBuf::from("file.txt"),
+                    "6657b6593444bd9a13d0131d47bef4f5".to_string()
+                )]
+            ]
         );
     }
 }
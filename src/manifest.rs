@@ -0,0 +1,179 @@
+//! Checksum files in the `sum` format (`<hash>  <path>`, same as md5sum) or the BSD-style
+//! `tag` format (`MD5 (<path>) = <hash>`).
+
+use anyhow::{bail, Result};
+use std::path::Path;
+
+/// Line of a checksum file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub hash: String,
+    pub path: String,
+    /// Name of the algorithm in the `tag` format, e.g. `MD5`
+    pub label: Option<String>,
+}
+
+/// Reverse `output::escape_sum_path`.
+fn unescape_path(path: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next()? {
+                '\\' => unescaped.push('\\'),
+                'n' => unescaped.push('\n'),
+                'r' => unescaped.push('\r'),
+                _ => return None,
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    Some(unescaped)
+}
+
+/// Parse `<hash>  <path>` or `<ALGORITHM> (<path>) = <hash>`. `<hash> *<path>` (binary mode
+/// of md5sum) is also accepted. A leading `\` means that the path is escaped as in GNU
+/// coreutils.
+pub fn parse_line(line: &str) -> Option<ManifestEntry> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+    let (hash, path, label) = match split_sum(line) {
+        Some((hash, path)) => (hash, path, None),
+        None => {
+            let (label, path, hash) = split_tag(line)?;
+            (hash, path, Some(label.to_string()))
+        }
+    };
+    if hash.is_empty() || path.is_empty() || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let path = if escaped {
+        unescape_path(path)?
+    } else {
+        path.to_string()
+    };
+    Some(ManifestEntry {
+        hash: hash.to_ascii_lowercase(),
+        path,
+        label,
+    })
+}
+
+/// `(hash, path)` of `<hash>  <path>` or `<hash> *<path>`
+fn split_sum(line: &str) -> Option<(&str, &str)> {
+    let (hash, path) = line.split_once(' ')?;
+    Some((hash, path.strip_prefix([' ', '*'])?))
+}
+
+/// `(algorithm, path, hash)` of `<ALGORITHM> (<path>) = <hash>`
+fn split_tag(line: &str) -> Option<(&str, &str, &str)> {
+    let (label, rest) = line.split_once(" (")?;
+    let (path, hash) = rest.rsplit_once(") = ")?;
+    if label.is_empty()
+        || !label
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    {
+        return None;
+    }
+    Some((label, path, hash))
+}
+
+/// Path with `\\` replaced by `/`, to match paths written on another OS.
+pub fn normalize_separators(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+/// Read a checksum file. Blank lines and `#` comments (e.g. of `--total`) are skipped, as in
+/// `md5sum -c`.
+pub fn read_manifest(path: &Path) -> Result<Vec<ManifestEntry>> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    let mut entries = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_line(line) {
+            Some(entry) => entries.push(entry),
+            None => bail!("{}:{}: improperly formatted line", path.display(), i + 1),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let entry = ManifestEntry {
+            hash: "ac175545a9b0f6da0d5c03f5135563d8".to_string(),
+            path: "./file name.txt".to_string(),
+            label: None,
+        };
+        assert_eq!(
+            parse_line("ac175545a9b0f6da0d5c03f5135563d8  ./file name.txt"),
+            Some(entry.clone())
+        );
+        assert_eq!(
+            parse_line("AC175545A9B0F6DA0D5C03F5135563D8 *./file name.txt"),
+            Some(entry)
+        );
+        assert_eq!(
+            parse_line("ac175545a9b0f6da0d5c03f5135563d8 file.txt"),
+            None
+        );
+        assert_eq!(
+            parse_line("\\ac175545a9b0f6da0d5c03f5135563d8  a\\\\b\\nc\\rd"),
+            Some(ManifestEntry {
+                hash: "ac175545a9b0f6da0d5c03f5135563d8".to_string(),
+                path: "a\\b\nc\rd".to_string(),
+                label: None,
+            })
+        );
+        assert_eq!(
+            parse_line("\\ac175545a9b0f6da0d5c03f5135563d8  a\\tb"),
+            None
+        );
+        assert_eq!(parse_line("not-a-hash  file.txt"), None);
+        assert_eq!(parse_line("ac175545a9b0f6da0d5c03f5135563d8  "), None);
+    }
+
+    #[test]
+    fn test_parse_tag_line() {
+        assert_eq!(
+            parse_line("MD5 (dir/a (1).txt) = AC175545A9B0F6DA0D5C03F5135563D8"),
+            Some(ManifestEntry {
+                hash: "ac175545a9b0f6da0d5c03f5135563d8".to_string(),
+                path: "dir/a (1).txt".to_string(),
+                label: Some("MD5".to_string()),
+            })
+        );
+        assert_eq!(
+            parse_line("\\SHA1 (a\\nb) = 598a9d1de5d569bd7e24280c122a5cfb0adf4b50"),
+            Some(ManifestEntry {
+                hash: "598a9d1de5d569bd7e24280c122a5cfb0adf4b50".to_string(),
+                path: "a\nb".to_string(),
+                label: Some("SHA1".to_string()),
+            })
+        );
+        assert_eq!(parse_line("MD5 (file.txt) = not-a-hash"), None);
+        assert_eq!(parse_line("MD5 file.txt = ac175545"), None);
+        assert_eq!(parse_line("M D5 (file.txt) = ac175545"), None);
+    }
+
+    #[test]
+    fn test_normalize_separators() {
+        assert_eq!(
+            normalize_separators("dir\\sub\\file.txt"),
+            "dir/sub/file.txt"
+        );
+        assert_eq!(normalize_separators("dir/sub/file.txt"), "dir/sub/file.txt");
+        assert_eq!(normalize_separators(".\\dir/file.txt"), "./dir/file.txt");
+    }
+}
@@ -0,0 +1,99 @@
+//! Headers of OSTree content objects, to compute the checksums that OSTree gives files.
+//!
+//! The checksum of a content object is the SHA-256 of a file header followed by the contents
+//! (nothing for symlinks). The header is the GVariant `(uuuusa(ayay))` of
+//! `(uid, gid, mode, rdev, symlink_target, xattrs)`. The integers are stored big-endian, rdev
+//! is always 0, and the target is empty for regular files. It is hashed as:
+//!
+//! | Bytes | Contents                                                   |
+//! |-------|------------------------------------------------------------|
+//! | 4     | Size of the variant, big-endian                            |
+//! | 4     | Zero padding                                               |
+//! | 16    | uid, gid, mode (with the file type bits), and rdev (0)     |
+//! | n + 1 | Symlink target, NUL-terminated                             |
+//! | 0     | Extended attributes (always empty)                         |
+//! | 1-8   | Offset of the end of the target (GVariant framing offset)  |
+//!
+//! Extended attributes are not read, so the checksums match repositories committed without
+//! xattrs (e.g. `bare-user-only` repositories or `ostree commit --no-xattrs`).
+
+/// Header of a content object, as hashed before the contents
+pub fn file_header(uid: u32, gid: u32, mode: u32, symlink_target: &[u8]) -> Vec<u8> {
+    let mut variant = Vec::with_capacity(32 + symlink_target.len());
+    for value in [uid, gid, mode, 0] {
+        variant.extend_from_slice(&value.to_be_bytes());
+    }
+    variant.extend_from_slice(symlink_target);
+    variant.push(0);
+    // The empty xattrs array takes no bytes. The end of the target is the only framing
+    // offset, and its width depends on the size of the whole variant.
+    let end = variant.len();
+    let width = offset_width(end);
+    variant.extend_from_slice(&end.to_le_bytes()[..width]);
+
+    let mut header = Vec::with_capacity(8 + variant.len());
+    header.extend_from_slice(&(variant.len() as u32).to_be_bytes());
+    header.extend_from_slice(&[0; 4]);
+    header.extend_from_slice(&variant);
+    header
+}
+
+/// Width of the framing offsets of a container whose contents take `len` bytes before the
+/// single offset
+fn offset_width(len: usize) -> usize {
+    [1, 2, 4, 8]
+        .into_iter()
+        .find(|&width| width == 8 || len + width < 1 << (8 * width))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_header() {
+        assert_eq!(
+            file_header(0, 0, 0o100644, b""),
+            [
+                0, 0, 0, 18, 0, 0, 0, 0, // size and padding
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0o201, 0o244, 0, 0, 0,
+                0,  // uid, gid, mode, rdev
+                0,  // target
+                17, // framing offset
+            ]
+        );
+        let header = file_header(1000, 100, 0o120777, b"file.txt");
+        assert_eq!(header[..8], [0, 0, 0, 26, 0, 0, 0, 0]);
+        assert_eq!(header[24..33], *b"file.txt\0");
+        assert_eq!(header[33], 25);
+
+        // Offsets take 2 bytes when the variant is longer than 255 bytes
+        let target = [b'a'; 300];
+        let header = file_header(0, 0, 0o120777, &target);
+        assert_eq!(header.len(), 8 + 16 + 301 + 2);
+        assert_eq!(header[header.len() - 2..], 317u16.to_le_bytes());
+    }
+
+    #[test]
+    fn test_checksum() {
+        use sha2::{Digest, Sha256};
+        let checksum = |header: Vec<u8>, contents: &[u8]| {
+            crate::output::to_hex(&Sha256::digest([&header, contents].concat()))
+        };
+        // "hello\n" with mode 644 owned by root, and by uid and gid 1000
+        assert_eq!(
+            checksum(file_header(0, 0, 0o100644, b""), b"hello\n"),
+            "44f778e59f0a4748d6b0c90a47347212a231c4ad1e8f7ea5c5dffc7749153a6b"
+        );
+        assert_eq!(
+            checksum(file_header(1000, 1000, 0o100644, b""), b"hello\n"),
+            "7e4bad27e0a4c4a24dd8978133cc8b0c9b222c9d3156a0f554b5490876b37f88"
+        );
+        // A symlink to `file.txt`
+        assert_eq!(
+            checksum(file_header(1000, 1000, 0o120777, b"file.txt"), b""),
+            "0a8894c7d1c565b25a09f8d2500ab5bc4a5f99adbe0ae40188128a14193ea02c"
+        );
+    }
+}
@@ -0,0 +1,1529 @@
+//! Formatting of the hashes computed by the workers.
+
+use crate::{
+    chunker::{merkle_root, Chunk},
+    manifest::{normalize_separators, ManifestEntry},
+    spill::SpillSorter,
+    Algorithm,
+};
+use anyhow::{bail, Result};
+use base64::Engine as _;
+use flate2::write::GzEncoder;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, warn};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, IsTerminal, Write},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum PrintFormat {
+    /// Hash and filename (same format as md5sum)
+    Sum,
+    /// CSV
+    Csv,
+    /// Hash and filename separated by a tab, without a header (for `join`)
+    Tsv,
+    /// SPDX file entries (FileName and FileChecksum)
+    Spdx,
+    /// `SHA256 (path) = hash` lines that `signify -C` verifies (sha256 and sha512 only)
+    Signify,
+    /// BSD-style `MD5 (path) = hash` lines, as printed by `md5sum --tag`
+    Tag,
+    /// A JSON object per line with the hashes of the content-defined chunks of each file
+    ChunksJson,
+    /// A JSON object per line, after a header object with the schema version
+    Jsonl,
+    /// A JSON object per line as in `jsonl`, without the header, for tools that expect
+    /// every line to be a record
+    Json,
+    /// `path  sha256-<base64>` lines with Subresource Integrity strings, as in the `integrity`
+    /// attribute of Bazel's `http_archive` (sha256 and sha512 only)
+    Bazel,
+    /// Hash and filename separated by --field-sep, each record ended by --record-sep
+    Fields,
+    /// Git LFS pointer files (sha256 only), each after a `==> path <==` line unless the only
+    /// input is a file
+    GitLfs,
+    /// Checksums of OSTree content objects (sha256 only), as the sum format. The hashed
+    /// header includes the owner and the mode of each file.
+    Ostree,
+}
+
+impl PrintFormat {
+    /// Whether the hashes can be printed in another encoding, case, or grouping. Formats read
+    /// by other tools need them as lower-case hex.
+    pub fn allows_hash_encoding(self) -> bool {
+        matches!(
+            self,
+            PrintFormat::Sum
+                | PrintFormat::Csv
+                | PrintFormat::Tsv
+                | PrintFormat::Fields
+                | PrintFormat::Tag
+                | PrintFormat::Jsonl
+                | PrintFormat::Json
+        )
+    }
+}
+
+/// Version of the `jsonl` output. Incremented when fields are removed or change meaning.
+/// Adding fields doesn't change the version.
+pub const JSONL_SCHEMA_VERSION: u32 = 1;
+
+/// Compression of the output file
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Compression {
+    /// gzip
+    Gz,
+    /// Zstandard
+    Zst,
+}
+
+/// What to do when the reader of the output goes away (e.g. `hashall ... | head`)
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum BrokenPipe {
+    /// Exit quietly with status 0
+    Quiet,
+    /// Exit quietly with status 141, like a process killed by SIGPIPE in a shell
+    Sigpipe,
+    /// Print the error and exit with status 1, like other errors
+    Error,
+}
+
+impl BrokenPipe {
+    /// Exit if `error` is a broken pipe. Called while the workers are still sending records,
+    /// so that they never see the printer disconnected.
+    pub fn handle(self, error: &anyhow::Error) {
+        let broken_pipe = error
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe);
+        if !broken_pipe {
+            return;
+        }
+        match self {
+            BrokenPipe::Quiet => std::process::exit(0),
+            BrokenPipe::Sigpipe => std::process::exit(141),
+            BrokenPipe::Error => {
+                eprintln!("Error: {:?}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+impl Compression {
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::Gz => "gz",
+            Compression::Zst => "zst",
+        }
+    }
+}
+
+/// Where the printer writes to
+pub enum Output {
+    Stdout(std::io::Stdout),
+    File(BufWriter<File>),
+    Gz(GzEncoder<BufWriter<File>>),
+    Zst(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl Output {
+    /// Stdout if `path` is `None`. The extension of `compression` is appended to `path`
+    /// unless it already ends with it.
+    pub fn create(path: Option<&Path>, compression: Option<Compression>) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(Output::Stdout(std::io::stdout()));
+        };
+        let path = Self::file_path(path, compression);
+        let file = File::create(&path)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+            .map(BufWriter::new)?;
+        Ok(match compression {
+            None => Output::File(file),
+            Some(Compression::Gz) => {
+                Output::Gz(GzEncoder::new(file, flate2::Compression::default()))
+            }
+            Some(Compression::Zst) => Output::Zst(zstd::Encoder::new(file, 0)?),
+        })
+    }
+
+    /// Path of the file that `create` writes for `path`
+    pub fn file_path(path: &Path, compression: Option<Compression>) -> PathBuf {
+        match compression {
+            Some(compression)
+                if path.extension().and_then(|e| e.to_str()) != Some(compression.extension()) =>
+            {
+                let mut path = path.as_os_str().to_owned();
+                path.push(".");
+                path.push(compression.extension());
+                PathBuf::from(path)
+            }
+            _ => path.to_path_buf(),
+        }
+    }
+
+    /// Write the end of the compressed stream and flush.
+    pub fn finish(self) -> Result<()> {
+        match self {
+            Output::Stdout(mut out) => out.flush()?,
+            Output::File(mut out) => out.flush()?,
+            Output::Gz(out) => out.finish()?.flush()?,
+            Output::Zst(out) => out.finish()?.flush()?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Stdout(out) => out.write(buf),
+            Output::File(out) => out.write(buf),
+            Output::Gz(out) => out.write(buf),
+            Output::Zst(out) => out.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Stdout(out) => out.flush(),
+            Output::File(out) => out.flush(),
+            Output::Gz(out) => out.flush(),
+            Output::Zst(out) => out.flush(),
+        }
+    }
+}
+
+/// Sends each line written to `inner` to the system logger as well, for `--syslog`
+pub struct SyslogTee<W: Write, B: Write = syslog::LoggerBackend> {
+    inner: W,
+    logger: Option<syslog::Logger<B, syslog::Formatter3164>>,
+    /// Bytes of the line being written
+    line: Vec<u8>,
+}
+
+fn syslog_formatter() -> syslog::Formatter3164 {
+    syslog::Formatter3164 {
+        facility: syslog::Facility::LOG_USER,
+        hostname: None,
+        process: "hashall".to_string(),
+        pid: std::process::id(),
+    }
+}
+
+impl<W: Write> SyslogTee<W> {
+    /// Connect to the local syslog if `enabled`. If it's unavailable (e.g. on Windows), only
+    /// `inner` is written after a warning.
+    pub fn new(inner: W, enabled: bool) -> Self {
+        let logger = if enabled {
+            syslog::unix(syslog_formatter())
+                .map_err(|e| warn!("Failed to connect to syslog ({}). Ignoring --syslog.", e))
+                .ok()
+        } else {
+            None
+        };
+        SyslogTee {
+            inner,
+            logger,
+            line: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write, B: Write> SyslogTee<W, B> {
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn send_line(&mut self) {
+        let Some(logger) = &mut self.logger else {
+            return;
+        };
+        let line = String::from_utf8_lossy(&self.line).into_owned();
+        if let Err(e) = logger.info(line) {
+            warn!(
+                "Failed to write to syslog ({}). Stopped logging to syslog.",
+                e
+            );
+            self.logger = None;
+        }
+        self.line.clear();
+    }
+}
+
+impl<W: Write, B: Write> Write for SyslogTee<W, B> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if self.logger.is_some() {
+            for &byte in &buf[..n] {
+                if byte == b'\n' {
+                    self.send_line();
+                } else {
+                    self.line.push(byte);
+                }
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hash of a file or an archive entry
+#[derive(Debug)]
+pub struct Record {
+    /// Archive containing `path`. `None` for files in the file system.
+    pub archive: Option<PathBuf>,
+    pub path: PathBuf,
+    pub hash: Vec<u8>,
+    pub algorithm: Algorithm,
+    /// Size of the contents in bytes. `None` if the contents were not read.
+    pub size: Option<u64>,
+    /// Content-defined chunks, for `PrintFormat::ChunksJson`
+    pub chunks: Vec<Chunk>,
+    /// CRC-32 computed in the same read, for `--also-crc32`
+    pub crc32: Option<u32>,
+    /// Permission bits, for `--show-mode`
+    pub mode: Option<u32>,
+    /// Hashes with the other algorithms of `--hash`, computed in the same read
+    pub also_hashes: Vec<(Algorithm, Vec<u8>)>,
+    /// Position of the input in submission order, for `PrintOptions::ordered`
+    pub seq: u64,
+}
+
+/// Values printed after the hash, each if enabled in `PrintOptions`
+#[derive(Debug, Clone, Copy, Default)]
+struct Columns<'a> {
+    /// Hashes with the other algorithms, formatted like the hash
+    also: &'a [(Algorithm, String)],
+    size: Option<u64>,
+    crc32: Option<u32>,
+    mode: Option<u32>,
+}
+
+impl Record {
+    /// Path with the archive path prepended, e.g. `archive.zip/file.txt`
+    pub fn full_path(&self) -> PathBuf {
+        full_path(self.archive.as_deref(), &self.path)
+    }
+
+    /// Path for `PrintOptions::sort`: the archive for archive entries
+    fn sort_path(&self) -> &Path {
+        self.archive.as_deref().unwrap_or(&self.path)
+    }
+}
+
+fn full_path(archive: Option<&Path>, path: &Path) -> PathBuf {
+    match archive {
+        Some(archive) => archive.join(path),
+        None => path.to_path_buf(),
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(hex, "{:02x}", b).unwrap();
+    }
+    hex
+}
+
+/// `hex` with `-` inserted every `n` characters, e.g. `ac175545-a9b0f6da-...` for 8
+fn group_hex(hex: &str, n: usize) -> String {
+    let mut grouped = String::with_capacity(hex.len() + hex.len() / n);
+    for (i, c) in hex.chars().enumerate() {
+        if i > 0 && i.is_multiple_of(n) {
+            grouped.push('-');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+/// Bytes of `path` in the order of `Path::cmp`: each component after a tag of its kind and
+/// followed by NUL, which can't be in a path
+fn sort_key(path: &Path) -> Vec<u8> {
+    let mut key = Vec::new();
+    for component in path.components() {
+        key.push(match component {
+            Component::Prefix(_) => 0,
+            Component::RootDir => 1,
+            Component::CurDir => 2,
+            Component::ParentDir => 3,
+            Component::Normal(_) => 4,
+        });
+        key.extend_from_slice(component.as_os_str().as_encoded_bytes());
+        key.push(0);
+    }
+    key
+}
+
+/// Path without `.` components, with `/` as the separator on any OS
+fn canonical_path(path: &Path) -> String {
+    let components: Vec<_> = path
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
+
+/// Size with a binary unit for the total line, e.g. `512 B` or `1.3 GiB`
+fn total_size(size: u64) -> String {
+    let human = human_size(size);
+    match human.strip_suffix(|c: char| c.is_ascii_alphabetic()) {
+        Some(value) => format!("{} {}iB", value, &human[value.len()..]),
+        None => format!("{} B", human),
+    }
+}
+
+/// Size in powers of 1024 with a unit suffix, like `ls -h`: `512`, `1.5K`, `340K`, `1.2M`.
+/// Values below 10 have one decimal. Values are rounded to the nearest.
+pub fn human_size(size: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+    if size < 1024 {
+        return size.to_string();
+    }
+    let mut value = size as f64;
+    for unit in UNITS {
+        value /= 1024.0;
+        // Move on to the next unit if rounding would give 1024
+        if value.round() < 1024.0 || unit == "E" {
+            return if value < 9.95 {
+                format!("{:.1}{}", value, unit)
+            } else {
+                format!("{:.0}{}", value, unit)
+            };
+        }
+    }
+    unreachable!()
+}
+
+/// Reverse `to_hex`. Upper-case digits are accepted.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn escape_csv(s: &str) -> String {
+    if s.contains(',') {
+        format!("\"{}\"", s.replace('\"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn escaped_display(path: &Path) -> String {
+    escape_csv(&path.display().to_string())
+}
+
+/// Escape a file name for the sum format like GNU coreutils.
+/// Names containing `\`, `\n`, or `\r` are escaped and the line must start with `\`.
+pub fn escape_sum_path(path: &str) -> Option<String> {
+    // `\` is the path separator and the others can't be in file names on Windows
+    if cfg!(windows) || !path.contains(['\\', '\n', '\r']) {
+        return None;
+    }
+    Some(
+        path.replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r"),
+    )
+}
+
+/// SPDX file names are relative paths starting with `./`.
+fn spdx_file_name(path: &Path) -> String {
+    let name = path.display().to_string();
+    if matches!(path.components().next(), Some(Component::Normal(_))) {
+        format!("./{}", name)
+    } else {
+        name
+    }
+}
+
+/// Status of a file compared to the baseline
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Unchanged,
+    New,
+    Changed,
+    Deleted,
+}
+
+impl Status {
+    fn label(self) -> &'static str {
+        match self {
+            Status::Unchanged => "UNCHANGED",
+            Status::New => "NEW",
+            Status::Changed => "CHANGED",
+            Status::Deleted => "DELETED",
+        }
+    }
+
+    fn symbol(self) -> char {
+        match self {
+            Status::Unchanged => '=',
+            Status::New => '+',
+            Status::Changed => '*',
+            Status::Deleted => '-',
+        }
+    }
+}
+
+/// Hashes of a previous run to report changes against
+pub struct Baseline {
+    hashes: BTreeMap<String, String>,
+    /// Paths in `hashes` by their `/`-separated forms
+    normalized: HashMap<String, String>,
+    seen: HashSet<String>,
+    show_deleted: bool,
+    /// Print every record prefixed with its status symbol instead of only the changes
+    annotate: bool,
+}
+
+impl Baseline {
+    pub fn new(entries: Vec<ManifestEntry>, show_deleted: bool, annotate: bool) -> Self {
+        let hashes: BTreeMap<_, _> = entries.into_iter().map(|e| (e.path, e.hash)).collect();
+        let normalized = hashes
+            .keys()
+            .map(|path| (normalize_separators(path), path.clone()))
+            .collect();
+        Baseline {
+            hashes,
+            normalized,
+            seen: HashSet::new(),
+            show_deleted,
+            annotate,
+        }
+    }
+
+    /// Status of `path`. Paths in the baseline match regardless of the separators (`/` or `\\`)
+    /// so that checksum files can be used across OSes.
+    fn status(&mut self, path: String, hash: &str) -> Status {
+        let path = if self.hashes.contains_key(&path) {
+            path
+        } else {
+            match self.normalized.get(&normalize_separators(&path)) {
+                Some(baseline_path) => baseline_path.clone(),
+                None => path,
+            }
+        };
+        let status = match self.hashes.get(&path) {
+            Some(expected) if expected.eq_ignore_ascii_case(hash) => Status::Unchanged,
+            Some(_) => Status::Changed,
+            None => Status::New,
+        };
+        self.seen.insert(path);
+        status
+    }
+}
+
+/// Files collected by hash to print the sets of files with the same contents
+pub struct Groups {
+    paths: BTreeMap<String, Vec<PathBuf>>,
+    /// Omit hashes shared by no other file
+    duplicates_only: bool,
+}
+
+impl Groups {
+    pub fn new(duplicates_only: bool) -> Self {
+        Groups {
+            paths: BTreeMap::new(),
+            duplicates_only,
+        }
+    }
+}
+
+/// Hashes of two trees by path relative to each root, for `--compare`
+pub struct Comparison {
+    roots: [PathBuf; 2],
+    hashes: [BTreeMap<String, String>; 2],
+    /// Set in `finish` if the trees differ
+    differs: Arc<AtomicBool>,
+    /// Print the paths grouped by status instead of a unified diff
+    by_status: bool,
+}
+
+impl Comparison {
+    pub fn new(a: PathBuf, b: PathBuf, differs: Arc<AtomicBool>, by_status: bool) -> Self {
+        Comparison {
+            roots: [a, b],
+            hashes: [BTreeMap::new(), BTreeMap::new()],
+            differs,
+            by_status,
+        }
+    }
+
+    /// Add `path` to the tree under the longest matching root.
+    fn insert(&mut self, path: &Path, hash: String) {
+        let matched = (0..2)
+            .filter_map(|i| Some((i, path.strip_prefix(&self.roots[i]).ok()?)))
+            .max_by_key(|(i, _)| self.roots[*i].components().count());
+        if let Some((i, relative)) = matched {
+            let relative = normalize_separators(&relative.display().to_string());
+            self.hashes[i].insert(relative, hash);
+        }
+    }
+}
+
+/// Hashes of the files under each input by path relative to it, for `--tree-hash`
+pub struct Trees {
+    /// Inputs with the algorithm of their files
+    roots: Vec<(PathBuf, Algorithm)>,
+    hashes: Vec<BTreeMap<String, String>>,
+}
+
+impl Trees {
+    pub fn new(roots: Vec<(PathBuf, Algorithm)>) -> Self {
+        let hashes = vec![BTreeMap::new(); roots.len()];
+        Trees { roots, hashes }
+    }
+
+    /// Add `path` to the tree of every root it is under that is hashed with `algorithm`, so
+    /// that overlapping inputs don't change each other's tree. A root that is a file is
+    /// listed by its name.
+    fn insert(&mut self, path: &Path, algorithm: Algorithm, hash: &str) {
+        for ((root, root_algorithm), hashes) in self.roots.iter().zip(&mut self.hashes) {
+            if *root_algorithm != algorithm {
+                continue;
+            }
+            let Ok(relative) = path.strip_prefix(root) else {
+                continue;
+            };
+            let relative = match relative.as_os_str().is_empty() {
+                true => path.file_name().map(Path::new).unwrap_or(path),
+                false => relative,
+            };
+            let relative = normalize_separators(&relative.display().to_string());
+            hashes.insert(relative, hash.to_string());
+        }
+    }
+}
+
+/// Contents of the sidecar files
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum SidecarContents {
+    /// The hash only
+    Hash,
+    /// `<hash>  <file name>` as in the sum format, to verify with `sha256sum -c` and friends
+    Sum,
+}
+
+/// Writes the hash of each file to `<file>.<algorithm>` next to it instead of the output.
+#[derive(Debug, Clone)]
+pub struct Sidecar {
+    pub contents: SidecarContents,
+    /// Directory the record paths are relative to
+    pub base: PathBuf,
+}
+
+impl Sidecar {
+    /// Write the sidecar of `path`. Files that can't be written are reported and skipped.
+    fn write(&self, path: &Path, hash: &str, algorithm: Algorithm) -> Result<()> {
+        let extension = algorithm.label().to_ascii_lowercase();
+        if path.extension().is_some_and(|e| e == extension.as_str()) {
+            debug!("{}: Skipped (sidecar)", path.display());
+            return Ok(());
+        }
+        let mut sidecar = self.base.join(path).into_os_string();
+        sidecar.push(".");
+        sidecar.push(&extension);
+        let contents = match (self.contents, path.file_name()) {
+            (SidecarContents::Sum, Some(name)) => {
+                format!("{}  {}\n", hash, name.to_string_lossy())
+            }
+            _ => format!("{}\n", hash),
+        };
+        if let Err(e) = std::fs::write(&sidecar, contents) {
+            warn!("{}: {}", PathBuf::from(sidecar).display(), e);
+        }
+        Ok(())
+    }
+}
+
+/// Byte order of integer-valued hashes (CRC32 and XXH3)
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ByteOrder {
+    /// Big-endian (network order)
+    Be,
+    /// Little-endian
+    Le,
+}
+
+/// How each record is printed
+#[derive(Debug, Clone)]
+pub struct PrintOptions {
+    pub format: PrintFormat,
+    /// Algorithm of the baseline entries and the directory hashes
+    pub algorithm: Algorithm,
+    /// Other algorithms of `--hash`, printed after the hash in this order
+    pub also_hash: Vec<Algorithm>,
+    /// Print archive paths in a separate CSV column
+    pub archive_column: bool,
+    pub byte_order: ByteOrder,
+    /// Also print a hash of each directory's files
+    pub dir_hashes: bool,
+    /// Print the sizes after the hashes
+    pub size: bool,
+    /// Print the sizes in human-readable units
+    pub human: bool,
+    /// Print the number of files and their total size at the end
+    pub total: bool,
+    /// Print the CRC-32 after the hash
+    pub crc32: bool,
+    /// Print the permission bits after the hash
+    pub mode: bool,
+    /// Sort by path and print paths with `/` and without `./` (sum format only)
+    pub canonical: bool,
+    /// Print the records sorted by path (of the archive for archive entries) at the end
+    pub sort: bool,
+    /// Print the records in submission order (`Record::seq`) at the end
+    pub ordered: bool,
+    /// Separator of the fields in a record for `PrintFormat::Fields`
+    pub field_sep: String,
+    /// Terminator of each record for `PrintFormat::Fields`
+    pub record_sep: String,
+    /// Print the hashes of the blocks in `Record::chunks` and their Merkle root (sum format)
+    pub block_tree: bool,
+    /// Insert `-` every N hex characters of the printed hashes
+    pub group_hash: Option<usize>,
+    /// Print the hex digits `A` to `F` in upper case
+    pub uppercase: bool,
+    /// Print the hashes in base64 (standard alphabet, padded) instead of hex
+    pub base64: bool,
+    /// End the records with NUL instead of `\n` and don't escape the paths
+    pub zero: bool,
+    /// Print the paths relative to this directory
+    pub base_dir: Option<PathBuf>,
+    /// Print archive entries without the archive path
+    pub strip_archive: bool,
+    /// Join archive paths and entry paths with this instead of `/`
+    pub archive_separator: Option<String>,
+    /// Print `==> path <==` before each Git LFS pointer
+    pub pointer_headers: bool,
+    /// What to do when the output is closed before everything is written
+    pub broken_pipe: BrokenPipe,
+    /// Write the hashes to sidecar files instead of the output
+    pub sidecar: Option<Sidecar>,
+    /// Number of records sorted in memory for `canonical` and `sort` before spilling to
+    /// temporary files
+    pub spill_threshold: usize,
+    /// Show the number of records and bytes received so far on stderr, if it's a terminal
+    pub progress: bool,
+}
+
+/// `record` with its path (the archive path for archive entries) relative to `base_dir`.
+/// Paths outside `base_dir` are kept, with a warning about the first one.
+fn relative_to(base_dir: &Path, mut record: Record, warned: &mut bool) -> Record {
+    let path = record.archive.as_mut().unwrap_or(&mut record.path);
+    match path.strip_prefix(base_dir) {
+        Ok(relative) if relative.as_os_str().is_empty() => *path = PathBuf::from("."),
+        Ok(relative) => *path = relative.to_path_buf(),
+        Err(_) => {
+            if !*warned {
+                warn!(
+                    "{}: not under --base-dir {}. Such paths are printed as they are.",
+                    path.display(),
+                    base_dir.display()
+                );
+                *warned = true;
+            }
+        }
+    }
+    record
+}
+
+/// `record` with the archive path of an archive entry joined to the entry path with
+/// `separator`, or removed if there's none
+fn join_archive(mut record: Record, separator: Option<&str>) -> Record {
+    if let (Some(archive), Some(separator)) = (record.archive.take(), separator) {
+        let mut path = archive.into_os_string();
+        path.push(separator);
+        path.push(&record.path);
+        record.path = PathBuf::from(path);
+    }
+    record
+}
+
+/// Spinner on stderr for `PrintOptions::progress`. `None` if stderr is not a terminal.
+fn progress_bar() -> Option<ProgressBar> {
+    if !std::io::stderr().is_terminal() {
+        return None;
+    }
+    let bar = ProgressBar::new_spinner().with_style(
+        ProgressStyle::with_template(
+            "{spinner} {msg} files, {bytes} [{elapsed_precise}, {binary_bytes_per_sec}]",
+        )
+        .unwrap(),
+    );
+    bar.enable_steady_tick(std::time::Duration::from_millis(100));
+    Some(bar)
+}
+
+/// Counts of the records received so far, shown on the spinner of `progress_bar`
+struct Progress {
+    bar: ProgressBar,
+    n_files: u64,
+}
+
+impl Progress {
+    fn new(bar: ProgressBar) -> Self {
+        Progress { bar, n_files: 0 }
+    }
+
+    /// Count `record`: the number of files is the message and the bytes are the position
+    fn update(&mut self, record: &Record) {
+        self.n_files += 1;
+        self.bar.set_message(self.n_files.to_string());
+        self.bar.inc(record.size.unwrap_or(0));
+    }
+}
+
+/// Receives records from the workers and writes them in the selected format.
+pub struct Printer<W: Write> {
+    out: W,
+    options: PrintOptions,
+    baseline: Option<Baseline>,
+    groups: Option<Groups>,
+    comparison: Option<Comparison>,
+    trees: Option<Trees>,
+    /// `(name, hash)` of the files in each directory for `dir_hashes`
+    directories: BTreeMap<PathBuf, Vec<(String, String)>>,
+    /// Number of records received
+    n_files: u64,
+    /// Sum of the sizes of the records
+    n_bytes: u64,
+}
+
+impl<W: Write> Printer<W> {
+    pub fn new(
+        out: W,
+        options: PrintOptions,
+        baseline: Option<Baseline>,
+        groups: Option<Groups>,
+        comparison: Option<Comparison>,
+        trees: Option<Trees>,
+    ) -> Self {
+        Printer {
+            out,
+            options,
+            baseline,
+            groups,
+            comparison,
+            trees,
+            directories: BTreeMap::new(),
+            n_files: 0,
+            n_bytes: 0,
+        }
+    }
+
+    /// Print the records until all senders are dropped and return the writer.
+    pub fn run(mut self, receiver: mpsc::Receiver<Record>) -> Result<W> {
+        if let Err(e) = self.write_all(&receiver) {
+            self.options.broken_pipe.handle(&e);
+            return Err(e);
+        }
+        Ok(self.out)
+    }
+
+    fn write_all(&mut self, receiver: &mpsc::Receiver<Record>) -> Result<()> {
+        if self.options.format == PrintFormat::Csv
+            && !self.options.zero
+            && self.baseline.is_none()
+            && self.groups.is_none()
+            && self.comparison.is_none()
+        {
+            // The hash columns are named by algorithm if there are several
+            let hash = if self.options.also_hash.is_empty() {
+                "hash,".to_string()
+            } else {
+                std::iter::once(self.options.algorithm)
+                    .chain(self.options.also_hash.iter().copied())
+                    .map(|algorithm| algorithm.label().to_ascii_lowercase() + ",")
+                    .collect()
+            };
+            let crc32 = if self.options.crc32 { "crc32," } else { "" };
+            let mode = if self.options.mode { "mode," } else { "" };
+            let size = if self.options.size { "size," } else { "" };
+            if self.options.archive_column {
+                writeln!(
+                    self.out,
+                    "{}{}{}{}archive,filename",
+                    hash, crc32, mode, size
+                )?;
+            } else {
+                writeln!(self.out, "{}{}{}{}filename", hash, crc32, mode, size)?;
+            }
+        }
+        if self.options.format == PrintFormat::Jsonl
+            && self.baseline.is_none()
+            && self.groups.is_none()
+            && self.comparison.is_none()
+        {
+            let header = serde_json::json!({
+                "schema_version": JSONL_SCHEMA_VERSION,
+                "algorithm": self.options.algorithm.label().to_ascii_lowercase(),
+            });
+            writeln!(self.out, "{}", header)?;
+        }
+        let progress = self.options.progress.then(progress_bar).flatten();
+        let records = {
+            let mut progress = progress.clone().map(Progress::new);
+            receiver.iter().inspect(move |record| {
+                if let Some(progress) = &mut progress {
+                    progress.update(record);
+                }
+            })
+        };
+        let records = {
+            let base_dir = self.options.base_dir.clone();
+            let mut warned = false;
+            records.map(move |record| match &base_dir {
+                Some(base_dir) => relative_to(base_dir, record, &mut warned),
+                None => record,
+            })
+        };
+        let records = {
+            let strip = self.options.strip_archive;
+            let separator = self.options.archive_separator.clone();
+            records.map(move |record| {
+                if strip || separator.is_some() {
+                    join_archive(record, separator.as_deref())
+                } else {
+                    record
+                }
+            })
+        };
+        if self.options.canonical {
+            self.write_sorted(records, |record| {
+                canonical_path(&record.full_path()).into_bytes()
+            })?;
+        } else if self.options.sort && self.prints_independently() {
+            // Archive entries have the same key, so they keep the order of the archive
+            self.write_sorted(records, |record| sort_key(record.sort_path()))?;
+        } else if self.options.sort || self.options.ordered {
+            let mut records: Vec<_> = records.collect();
+            // Stable, so the entries of each archive keep the order of the archive
+            if self.options.sort {
+                records.sort_by(|a, b| a.sort_path().cmp(b.sort_path()));
+            } else {
+                records.sort_by_key(|record| record.seq);
+            }
+            for record in &records {
+                self.print(record)?;
+            }
+        } else {
+            for record in records {
+                self.print(&record)?;
+            }
+        }
+        if let Some(bar) = progress {
+            bar.finish();
+        }
+        self.finish()
+    }
+
+    /// Whether each record is printed without the state of the others, so that the records can
+    /// be rendered as they arrive and reordered afterwards
+    fn prints_independently(&self) -> bool {
+        self.baseline.is_none()
+            && self.groups.is_none()
+            && self.comparison.is_none()
+            && self.trees.is_none()
+            && self.options.sidecar.is_none()
+            && !self.options.dir_hashes
+            && !self.options.pointer_headers
+    }
+
+    /// Render the records as they arrive and print them in the order of `key`, spilling to
+    /// temporary files beyond `spill_threshold` records
+    fn write_sorted(
+        &mut self,
+        records: impl Iterator<Item = Record>,
+        key: impl Fn(&Record) -> Vec<u8>,
+    ) -> Result<()> {
+        let mut renderer = Printer::new(Vec::new(), self.options.clone(), None, None, None, None);
+        let mut sorter = SpillSorter::new(self.options.spill_threshold);
+        for record in records {
+            renderer.print(&record)?;
+            sorter.push(key(&record), std::mem::take(&mut renderer.out))?;
+        }
+        sorter.finish(|line| Ok(self.out.write_all(line)?))?;
+        self.n_files = renderer.n_files;
+        self.n_bytes = renderer.n_bytes;
+        Ok(())
+    }
+
+    fn print(&mut self, record: &Record) -> Result<()> {
+        self.n_files += 1;
+        self.n_bytes += record.size.unwrap_or(0);
+        let hash = self.format_hash(record.algorithm, &record.hash);
+        if self.options.dir_hashes && record.archive.is_none() {
+            // Files given directly as inputs (without a parent) are not in a walked directory
+            if let (Some(dir), Some(name)) = (record.path.parent(), record.path.file_name()) {
+                if !dir.as_os_str().is_empty() {
+                    self.directories
+                        .entry(dir.to_path_buf())
+                        .or_default()
+                        .push((name.to_string_lossy().into_owned(), hash.clone()));
+                }
+            }
+        }
+        if let Some(groups) = &mut self.groups {
+            groups
+                .paths
+                .entry(hash)
+                .or_default()
+                .push(record.full_path());
+            return Ok(());
+        }
+        if let Some(comparison) = &mut self.comparison {
+            comparison.insert(&record.full_path(), hash);
+            return Ok(());
+        }
+        if let Some(trees) = &mut self.trees {
+            // Hex regardless of the encoding, so that the root hashes are comparable
+            trees.insert(&record.full_path(), record.algorithm, &to_hex(&record.hash));
+            return Ok(());
+        }
+        if let Some(sidecar) = &self.options.sidecar {
+            if record.archive.is_some() {
+                debug!("{}: Skipped (archive entry)", record.full_path().display());
+                return Ok(());
+            }
+            return sidecar.write(&record.path, &hash, record.algorithm);
+        }
+        if let Some(baseline) = &mut self.baseline {
+            let path = record.full_path().display().to_string();
+            let status = baseline.status(path.clone(), &hash);
+            if baseline.annotate {
+                write!(self.out, "{}", status.symbol())?;
+            } else {
+                // Only changes are printed
+                if status != Status::Unchanged {
+                    writeln!(self.out, "{}: {}", path, status.label())?;
+                }
+                return Ok(());
+            }
+        }
+        if self.options.format == PrintFormat::ChunksJson {
+            return self.write_chunks(&hash, record);
+        }
+        if self.options.block_tree {
+            return self.write_block_tree(record);
+        }
+        let also: Vec<_> = record
+            .also_hashes
+            .iter()
+            .map(|(algorithm, hash)| (*algorithm, self.format_hash(*algorithm, hash)))
+            .collect();
+        let columns = Columns {
+            also: &also,
+            size: record.size,
+            crc32: record.crc32,
+            mode: record.mode,
+        };
+        self.write_record(
+            &hash,
+            record.algorithm,
+            columns,
+            record.archive.as_deref(),
+            &record.path,
+        )
+    }
+
+    /// `hash` in the selected encoding, byte order, and grouping
+    fn format_hash(&self, algorithm: Algorithm, hash: &[u8]) -> String {
+        let hash = if self.options.byte_order == ByteOrder::Le && algorithm.is_integer() {
+            let mut reversed = hash.to_vec();
+            reversed.reverse();
+            self.encode(&reversed)
+        } else {
+            self.encode(hash)
+        };
+        match self.options.group_hash {
+            Some(n) => group_hex(&hash, n),
+            None => hash,
+        }
+    }
+
+    /// `crc32` of `--also-crc32` in the encoding of the hashes, like `--hash crc32`
+    fn format_crc32(&self, crc32: u32) -> String {
+        self.format_hash(Algorithm::Crc32, &crc32.to_be_bytes())
+    }
+
+    /// `bytes` in base64 or in hex of the selected case
+    fn encode(&self, bytes: &[u8]) -> String {
+        if self.options.base64 {
+            return base64::engine::general_purpose::STANDARD.encode(bytes);
+        }
+        let hex = to_hex(bytes);
+        if self.options.uppercase {
+            hex.to_ascii_uppercase()
+        } else {
+            hex
+        }
+    }
+
+    fn write_record(
+        &mut self,
+        hash: &str,
+        algorithm: Algorithm,
+        columns: Columns,
+        archive: Option<&Path>,
+        path: &Path,
+    ) -> Result<()> {
+        // The other hashes, the CRC-32, the mode, and the size are printed as columns after
+        // the hash
+        let Columns {
+            also,
+            size,
+            crc32,
+            mode,
+        } = columns;
+        let mut columns = hash.to_string();
+        if matches!(
+            self.options.format,
+            PrintFormat::Sum
+                | PrintFormat::Ostree
+                | PrintFormat::Csv
+                | PrintFormat::Tsv
+                | PrintFormat::Fields
+        ) {
+            let separator = match self.options.format {
+                PrintFormat::Csv => ",",
+                PrintFormat::Tsv => "\t",
+                PrintFormat::Fields => &self.options.field_sep,
+                _ => "  ",
+            };
+            for (_, hash) in also {
+                write!(columns, "{}{}", separator, hash)?;
+            }
+            if self.options.crc32 {
+                let crc32 = crc32.map_or("-".to_string(), |crc32| self.format_crc32(crc32));
+                write!(columns, "{}{}", separator, crc32)?;
+            }
+            if self.options.mode {
+                let mode = mode.map_or("-".to_string(), |mode| format!("{:o}", mode));
+                write!(columns, "{}{}", separator, mode)?;
+            }
+            if self.options.size {
+                let size = match size {
+                    Some(size) if self.options.human => human_size(size),
+                    Some(size) => size.to_string(),
+                    None => "-".to_string(),
+                };
+                write!(columns, "{}{}", separator, size)?;
+            }
+        }
+        let hash = columns.as_str();
+        let end = if self.options.zero { '\0' } else { '\n' };
+        if self.options.format == PrintFormat::Csv && self.options.archive_column {
+            write!(
+                self.out,
+                "{},{},{}{}",
+                hash,
+                escaped_display(archive.unwrap_or(Path::new(""))),
+                escaped_display(path),
+                end
+            )?;
+            return Ok(());
+        }
+        let full_path = full_path(archive, path);
+        let path = full_path.as_path();
+        match self.options.format {
+            PrintFormat::Sum | PrintFormat::Ostree => {
+                let path = if self.options.canonical {
+                    canonical_path(path)
+                } else {
+                    path.display().to_string()
+                };
+                match escape_sum_path(&path).filter(|_| !self.options.zero) {
+                    Some(escaped) => writeln!(self.out, "\\{}  {}", hash, escaped)?,
+                    None => write!(self.out, "{}  {}{}", hash, path, end)?,
+                }
+            }
+            PrintFormat::Csv => {
+                write!(self.out, "{},{}{}", hash, escaped_display(path), end)?;
+            }
+            PrintFormat::Tsv => {
+                write!(self.out, "{}\t{}{}", hash, path.display(), end)?;
+            }
+            PrintFormat::Signify => {
+                writeln!(
+                    self.out,
+                    "{} ({}) = {}",
+                    algorithm.label(),
+                    path.display(),
+                    hash
+                )?;
+            }
+            PrintFormat::Tag => {
+                let path = path.display().to_string();
+                match escape_sum_path(&path).filter(|_| !self.options.zero) {
+                    Some(escaped) => {
+                        writeln!(self.out, "\\{} ({}) = {}", algorithm.label(), escaped, hash)?
+                    }
+                    None => write!(
+                        self.out,
+                        "{} ({}) = {}{}",
+                        algorithm.label(),
+                        path,
+                        hash,
+                        end
+                    )?,
+                }
+            }
+            PrintFormat::Fields => {
+                let path = path.display().to_string();
+                let PrintOptions {
+                    field_sep,
+                    record_sep,
+                    ..
+                } = &self.options;
+                // Separators are never in the other fields
+                if path.contains(field_sep.as_str()) || path.contains(record_sep.as_str()) {
+                    bail!(
+                        "{:?}: the path contains the field or record separator",
+                        path
+                    );
+                }
+                write!(self.out, "{}{}{}{}", hash, field_sep, path, record_sep)?;
+            }
+            PrintFormat::Bazel => {
+                // Re-encoded from the hex, which the options that change it can't be used with
+                let Some(digest) = from_hex(hash) else {
+                    bail!("{}: not a hex hash: {}", path.display(), hash);
+                };
+                writeln!(
+                    self.out,
+                    "{}  {}-{}",
+                    path.display(),
+                    algorithm.label().to_ascii_lowercase(),
+                    base64::engine::general_purpose::STANDARD.encode(digest)
+                )?;
+            }
+            PrintFormat::GitLfs => {
+                let Some(size) = size else {
+                    bail!("{}: the size is unknown", path.display());
+                };
+                if self.options.pointer_headers {
+                    if self.n_files > 1 {
+                        writeln!(self.out)?;
+                    }
+                    writeln!(self.out, "==> {} <==", path.display())?;
+                }
+                writeln!(
+                    self.out,
+                    "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}",
+                    hash, size
+                )?;
+            }
+            PrintFormat::Spdx => {
+                writeln!(
+                    self.out,
+                    "FileName: {}\nFileChecksum: {}: {}\n",
+                    spdx_file_name(path),
+                    algorithm.label(),
+                    hash
+                )?;
+            }
+            PrintFormat::ChunksJson => {
+                let object = serde_json::json!({
+                    "path": path.display().to_string(),
+                    "hash": hash,
+                });
+                writeln!(self.out, "{}", object)?;
+            }
+            PrintFormat::Jsonl | PrintFormat::Json => {
+                let mut object = serde_json::json!({
+                    "path": path.display().to_string(),
+                    "hash": hash,
+                    "algorithm": algorithm.label().to_ascii_lowercase(),
+                    "size": size,
+                });
+                if !also.is_empty() {
+                    let hashes: serde_json::Map<_, _> = std::iter::once((algorithm, hash))
+                        .chain(
+                            also.iter()
+                                .map(|(algorithm, hash)| (*algorithm, hash.as_str())),
+                        )
+                        .map(|(algorithm, hash)| {
+                            (algorithm.label().to_ascii_lowercase(), hash.into())
+                        })
+                        .collect();
+                    object["hashes"] = hashes.into();
+                }
+                if let Some(crc32) = crc32 {
+                    object["crc32"] = self.format_crc32(crc32).into();
+                }
+                if self.options.mode {
+                    object["mode"] = mode.map(|mode| format!("{:o}", mode)).into();
+                }
+                writeln!(self.out, "{}", object)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `<hash>  <index>  <path>` for each block, then `<root hash>  root  <path>`
+    fn write_block_tree(&mut self, record: &Record) -> Result<()> {
+        let path = record.full_path().display().to_string();
+        // Escaped like the sum format
+        let (prefix, path) = match escape_sum_path(&path) {
+            Some(escaped) => ("\\", escaped),
+            None => ("", path),
+        };
+        for (i, block) in record.chunks.iter().enumerate() {
+            let hash = self.encode(&block.hash);
+            writeln!(self.out, "{}{}  {}  {}", prefix, hash, i, path)?;
+        }
+        let leaves: Vec<_> = record.chunks.iter().map(|b| b.hash.clone()).collect();
+        let root = merkle_root(&leaves, |data| record.algorithm.digest(data));
+        writeln!(self.out, "{}{}  root  {}", prefix, self.encode(&root), path)?;
+        Ok(())
+    }
+
+    /// `{"path":...,"hash":...,"chunks":[{"offset":...,"len":...,"hash":...}]}`
+    fn write_chunks(&mut self, hash: &str, record: &Record) -> Result<()> {
+        let chunks: Vec<_> = record
+            .chunks
+            .iter()
+            .map(|chunk| {
+                serde_json::json!({
+                    "offset": chunk.offset,
+                    "len": chunk.len,
+                    "hash": self.encode(&chunk.hash),
+                })
+            })
+            .collect();
+        let object = serde_json::json!({
+            "path": record.full_path().display().to_string(),
+            "hash": hash,
+            "chunks": chunks,
+        });
+        writeln!(self.out, "{}", object)?;
+        Ok(())
+    }
+
+    /// Unified diff of `<hash>  <relative path>` lines, without hunk headers
+    fn write_unified_diff(&mut self, comparison: &Comparison) -> Result<()> {
+        let [a, b] = &comparison.hashes;
+        let paths: BTreeSet<_> = a.keys().chain(b.keys()).collect();
+        for path in paths {
+            let (old, new) = (a.get(path), b.get(path));
+            if old == new {
+                continue;
+            }
+            if !comparison.differs.swap(true, Ordering::Relaxed) {
+                writeln!(self.out, "--- {}", comparison.roots[0].display())?;
+                writeln!(self.out, "+++ {}", comparison.roots[1].display())?;
+            }
+            if let Some(hash) = old {
+                writeln!(self.out, "-{}  {}", hash, path)?;
+            }
+            if let Some(hash) = new {
+                writeln!(self.out, "+{}  {}", hash, path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `<relative path>: <status>` lines, grouped by status and sorted by path in each
+    fn write_statuses(&mut self, comparison: &Comparison) -> Result<()> {
+        let [a, b] = &comparison.hashes;
+        let added: Vec<_> = b.keys().filter(|path| !a.contains_key(*path)).collect();
+        let removed: Vec<_> = a.keys().filter(|path| !b.contains_key(*path)).collect();
+        let changed: Vec<_> = a
+            .iter()
+            .filter(|(path, hash)| b.get(*path).is_some_and(|other| other != *hash))
+            .map(|(path, _)| path)
+            .collect();
+        for (paths, status) in [(added, "ADDED"), (removed, "REMOVED"), (changed, "CHANGED")] {
+            for path in paths {
+                comparison.differs.store(true, Ordering::Relaxed);
+                writeln!(self.out, "{}: {}", path, status)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        for (dir, mut files) in std::mem::take(&mut self.directories) {
+            // Hash of the files listed in the sum format, sorted by name
+            files.sort();
+            let listing: String = files
+                .iter()
+                .map(|(name, hash)| format!("{}  {}\n", hash, name))
+                .collect();
+            let algorithm = self.options.algorithm;
+            let hash = to_hex(&algorithm.digest(listing.as_bytes()));
+            let dir = PathBuf::from(format!("{}{}", dir.display(), std::path::MAIN_SEPARATOR));
+            self.write_record(&hash, algorithm, Columns::default(), None, &dir)?;
+        }
+        if let Some(groups) = self.groups.take() {
+            let mut first = true;
+            for (hash, mut paths) in groups.paths {
+                if groups.duplicates_only && paths.len() < 2 {
+                    continue;
+                }
+                if !first {
+                    writeln!(self.out)?;
+                }
+                first = false;
+                writeln!(self.out, "{}", hash)?;
+                paths.sort();
+                for path in paths {
+                    writeln!(self.out, "{}", path.display())?;
+                }
+            }
+        }
+        if let Some(comparison) = self.comparison.take() {
+            if comparison.by_status {
+                self.write_statuses(&comparison)?;
+            } else {
+                self.write_unified_diff(&comparison)?;
+            }
+        }
+        if let Some(trees) = self.trees.take() {
+            // Hash of the files listed in the sum format, sorted by relative path
+            for ((root, algorithm), files) in trees.roots.iter().zip(&trees.hashes) {
+                let algorithm = *algorithm;
+                let listing: String = files
+                    .iter()
+                    .map(|(path, hash)| format!("{}  {}\n", hash, path))
+                    .collect();
+                let hash = self.format_hash(algorithm, &algorithm.digest(listing.as_bytes()));
+                self.write_record(&hash, algorithm, Columns::default(), None, root)?;
+            }
+        }
+        if let Some(baseline) = self.baseline.take() {
+            if baseline.show_deleted {
+                for (path, hash) in &baseline.hashes {
+                    if baseline.seen.contains(path) {
+                        continue;
+                    }
+                    if baseline.annotate {
+                        write!(self.out, "{}", Status::Deleted.symbol())?;
+                        let algorithm = self.options.algorithm;
+                        let columns = Columns::default();
+                        self.write_record(hash, algorithm, columns, None, Path::new(path))?;
+                    } else {
+                        writeln!(self.out, "{}: {}", path, Status::Deleted.label())?;
+                    }
+                }
+            }
+        }
+        if self.options.total {
+            if matches!(
+                self.options.format,
+                PrintFormat::ChunksJson | PrintFormat::Jsonl | PrintFormat::Json
+            ) {
+                let object = serde_json::json!({ "files": self.n_files, "bytes": self.n_bytes });
+                writeln!(self.out, "{}", object)?;
+            } else {
+                let files = if self.n_files == 1 { "file" } else { "files" };
+                let total = format!("# {} {}, {}", self.n_files, files, total_size(self.n_bytes));
+                if matches!(
+                    self.options.format,
+                    PrintFormat::Csv | PrintFormat::Tsv | PrintFormat::Fields
+                ) {
+                    // Every line of these formats is a row
+                    eprintln!("{}", total);
+                } else {
+                    // A comment, which `md5sum -c` and SPDX tools skip
+                    writeln!(self.out, "{}", total)?;
+                }
+            }
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syslog_tee() {
+        let logger = syslog::Logger::new(Vec::new(), syslog_formatter());
+        let mut tee = SyslogTee {
+            inner: Vec::new(),
+            logger: Some(logger),
+            line: Vec::new(),
+        };
+        write!(
+            tee,
+            "ac175545a9b0f6da0d5c03f5135563d8  ./file.txt\nb1946ac9"
+        )
+        .unwrap();
+        let message = String::from_utf8(tee.logger.as_ref().unwrap().backend.clone()).unwrap();
+        // The priority is user.info, and only the complete line is sent
+        assert!(message.starts_with("<14>"), "{}", message);
+        assert!(
+            message.ends_with(&format!(
+                "hashall[{}]: ac175545a9b0f6da0d5c03f5135563d8  ./file.txt",
+                std::process::id()
+            )),
+            "{}",
+            message
+        );
+        assert_eq!(
+            tee.into_inner(),
+            b"ac175545a9b0f6da0d5c03f5135563d8  ./file.txt\nb1946ac9"
+        );
+    }
+
+    #[test]
+    fn test_progress() {
+        let mut progress = Progress::new(ProgressBar::hidden());
+        for size in [Some(3), None, Some(4)] {
+            let record = Record {
+                archive: None,
+                path: PathBuf::from("file.txt"),
+                hash: Vec::new(),
+                algorithm: Algorithm::Md5,
+                size,
+                chunks: Vec::new(),
+                crc32: None,
+                mode: None,
+                also_hashes: Vec::new(),
+                seq: 0,
+            };
+            progress.update(&record);
+        }
+        // Files without a known size are counted without bytes
+        assert_eq!(progress.bar.message(), "3");
+        assert_eq!(progress.bar.position(), 7);
+    }
+
+    #[test]
+    fn test_sort_key() {
+        let paths = [
+            "/a", "a", "a/b", "a-b", "a/b/c", "ab", "./a", "../a", "a//b", "a/./b", "b", "",
+        ];
+        for a in paths {
+            for b in paths {
+                let (a, b) = (Path::new(a), Path::new(b));
+                assert_eq!(sort_key(a).cmp(&sort_key(b)), a.cmp(b), "{:?} {:?}", a, b);
+            }
+        }
+    }
+}
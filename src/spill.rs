@@ -0,0 +1,146 @@
+//! External merge sort of `(key, value)` pairs, for sorted output of more records than fit in
+//! memory.
+//!
+//! Pairs are buffered up to a threshold, then sorted and written to an anonymous temporary file
+//! (a run). At the end, the runs are merged with a heap holding the next pair of each run.
+
+use anyhow::Result;
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, Write},
+};
+
+/// Default number of pairs kept in memory before spilling
+pub const DEFAULT_THRESHOLD: usize = 1 << 20;
+
+/// Sorts pairs by key. Pairs with the same key keep the order in which they were pushed.
+pub struct SpillSorter {
+    threshold: usize,
+    buffer: Vec<(Vec<u8>, Vec<u8>)>,
+    runs: Vec<File>,
+}
+
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    out.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    out.write_all(bytes)
+}
+
+/// Bytes written by `write_bytes`, or `None` at the end of the run
+fn read_bytes(input: &mut impl Read) -> std::io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 8];
+    match input.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut bytes = vec![0; u64::from_le_bytes(len) as usize];
+    input.read_exact(&mut bytes)?;
+    Ok(Some(bytes))
+}
+
+fn read_pair(input: &mut impl Read) -> std::io::Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let Some(key) = read_bytes(input)? else {
+        return Ok(None);
+    };
+    let value = read_bytes(input)?.ok_or(std::io::ErrorKind::UnexpectedEof)?;
+    Ok(Some((key, value)))
+}
+
+impl SpillSorter {
+    pub fn new(threshold: usize) -> Self {
+        assert!(threshold > 0);
+        SpillSorter {
+            threshold,
+            buffer: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.buffer.push((key, value));
+        if self.buffer.len() >= self.threshold {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        // Stable, so that equal keys stay in order within the run
+        self.buffer.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut out = BufWriter::new(tempfile::tempfile()?);
+        for (key, value) in self.buffer.drain(..) {
+            write_bytes(&mut out, &key)?;
+            write_bytes(&mut out, &value)?;
+        }
+        let mut file = out.into_inner()?;
+        file.rewind()?;
+        self.runs.push(file);
+        log::debug!("Spilled run {} of sorted records", self.runs.len());
+        Ok(())
+    }
+
+    /// Call `f` with each value in order of the keys.
+    pub fn finish(mut self, mut f: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        if self.runs.is_empty() {
+            self.buffer.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (_, value) in &self.buffer {
+                f(value)?;
+            }
+            return Ok(());
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        let mut runs: Vec<_> = self.runs.into_iter().map(BufReader::new).collect();
+        // The run index breaks ties, so that earlier runs come first
+        let mut heap = BinaryHeap::new();
+        for (i, run) in runs.iter_mut().enumerate() {
+            if let Some((key, value)) = read_pair(run)? {
+                heap.push(Reverse((key, i, value)));
+            }
+        }
+        while let Some(Reverse((_, i, value))) = heap.pop() {
+            f(&value)?;
+            if let Some((key, value)) = read_pair(&mut runs[i])? {
+                heap.push(Reverse((key, i, value)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(threshold: usize, keys: &[u32]) -> Vec<(u32, usize)> {
+        let mut sorter = SpillSorter::new(threshold);
+        for (i, key) in keys.iter().enumerate() {
+            let value = (i as u64).to_le_bytes().to_vec();
+            sorter.push(key.to_be_bytes().to_vec(), value).unwrap();
+        }
+        let mut values = Vec::new();
+        sorter
+            .finish(|value| {
+                let i = u64::from_le_bytes(value.try_into().unwrap()) as usize;
+                values.push((keys[i], i));
+                Ok(())
+            })
+            .unwrap();
+        values
+    }
+
+    #[test]
+    fn test_spill_sorter() {
+        let keys: Vec<u32> = (0..1000).map(|i| (i * 7919) % 101).collect();
+        let mut expected: Vec<_> = keys.iter().copied().zip(0..).collect();
+        expected.sort();
+        // In memory, with a few runs, and with a run per pair
+        for threshold in [usize::MAX, 300, 1] {
+            assert_eq!(sorted(threshold, &keys), expected);
+        }
+        assert!(sorted(1, &[]).is_empty());
+    }
+}
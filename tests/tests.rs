@@ -48,9 +48,42 @@ fn setup() {
             }
         }
     });
+}
+
+fn data_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data")
+}
+
+/// Command running in the test data directory.
+/// The current directory of the test process is left untouched because tests run in parallel.
+fn hashall() -> Command {
+    let mut cmd = Command::cargo_bin("hashall").unwrap();
+    cmd.current_dir(data_dir());
+    cmd
+}
+
+/// Zip of `entries` (name and contents) compressed with `method`, with `patch` applied to the
+/// bytes from the central directory header of the last entry (its `PK\x01\x02` signature)
+fn zip_with(
+    entries: &[(&str, &[u8])],
+    method: zip::CompressionMethod,
+    patch: impl FnOnce(&mut [u8]),
+) -> Result<Vec<u8>> {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::FileOptions::default().compression_method(method);
+    for (name, contents) in entries {
+        zip.start_file(*name, options)?;
+        std::io::Write::write_all(&mut zip, contents)?;
+    }
+    let mut bytes = zip.finish()?.into_inner();
+    let central = bytes.windows(4).rposition(|w| w == b"PK\x01\x02").unwrap();
+    patch(&mut bytes[central..]);
+    Ok(bytes)
+}
 
-    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data");
-    std::env::set_current_dir(test_dir).unwrap();
+/// Corrupt the CRC-32 in a central directory header for `zip_with`
+fn bad_crc32(central: &mut [u8]) {
+    central[16..20].copy_from_slice(&0x12345678u32.to_le_bytes());
 }
 
 static mut OUT_FILE: &str = "ac175545a9b0f6da0d5c03f5135563d8  ./file.txt";
@@ -82,7 +115,7 @@ fn sort_output(output: Vec<u8>) -> Result<String> {
 #[test]
 fn test_files() -> Result<()> {
     setup();
-    let mut cmd = Command::cargo_bin("hashall").unwrap();
+    let mut cmd = hashall();
     cmd.arg(".");
     cmd.assert().success();
     // sorting is necessary because the order of the output is not guaranteed
@@ -104,7 +137,7 @@ fn test_files() -> Result<()> {
         .join("\n")
     );
 
-    let mut cmd = Command::cargo_bin("hashall").unwrap();
+    let mut cmd = hashall();
     cmd.args([".", "-a"]);
     cmd.assert().success();
     let output = sort_output(cmd.output()?.stdout)?;
@@ -126,7 +159,7 @@ fn test_files() -> Result<()> {
         .join("\n")
     );
 
-    let mut cmd = Command::cargo_bin("hashall").unwrap();
+    let mut cmd = hashall();
     cmd.args([".", "-ar"]);
     cmd.assert().success();
     let output = sort_output(cmd.output()?.stdout)?;
@@ -155,7 +188,7 @@ fn test_files() -> Result<()> {
 #[test]
 fn test_zip() -> Result<()> {
     setup();
-    let mut cmd = Command::cargo_bin("hashall").unwrap();
+    let mut cmd = hashall();
     cmd.args(["archive.zip", "--archive"]);
     // no sorting necessary because the order of the output in archive file is guaranteed
     unsafe {
@@ -167,9 +200,9 @@ fn test_zip() -> Result<()> {
 #[test]
 fn test_tar() -> Result<()> {
     setup();
-    let tar_contents = unsafe { OUT_ARC_CONTENTS.replace(".zip", ".tar") };
+    let tar_contents = unsafe { OUT_ARC_CONTENTS }.replace(".zip", ".tar");
 
-    let mut cmd = Command::cargo_bin("hashall").unwrap();
+    let mut cmd = hashall();
     cmd.args(["archive.tar", "--archive"]);
     cmd.assert().success().stdout(tar_contents);
 
@@ -178,8 +211,8 @@ fn test_tar() -> Result<()> {
 
 fn test_tar_compress(extension: &str) -> Result<()> {
     setup();
-    let contents = unsafe { OUT_ARC_CONTENTS.replace(".zip", extension) };
-    let mut cmd = Command::cargo_bin("hashall").unwrap();
+    let contents = unsafe { OUT_ARC_CONTENTS }.replace(".zip", extension);
+    let mut cmd = hashall();
     cmd.args(["archive".to_owned() + extension, "--archive".to_owned()]);
     cmd.assert().success().stdout(contents);
     Ok(())
@@ -202,11 +235,3426 @@ fn test_xz() -> Result<()> {
 
 #[test]
 fn test_failure() -> Result<()> {
-    let mut cmd = Command::cargo_bin("hashall").unwrap();
+    let mut cmd = hashall();
     cmd.assert().failure();
 
-    let mut cmd = Command::cargo_bin("hashall").unwrap();
+    let mut cmd = hashall();
     cmd.arg("nonexistent");
     cmd.assert().failure();
     Ok(())
 }
+
+#[test]
+fn test_preset() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--preset", "secure"]);
+    cmd.assert()
+        .success()
+        .stdout("64c7ed6638f4224634b2223f16139fc8abb30905ff5502de0468bce246f98a2c  file.txt\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--preset", "fast"]);
+    cmd.assert()
+        .success()
+        .stdout("6ec87a8369735f01  file.txt\n");
+
+    // explicit options take precedence over the preset
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--preset", "fast", "--hash", "md5"]);
+    cmd.assert()
+        .success()
+        .stdout("ac175545a9b0f6da0d5c03f5135563d8  file.txt\n");
+    Ok(())
+}
+
+#[test]
+fn test_direct() -> Result<()> {
+    setup();
+    // Falls back to buffered read where O_DIRECT is unsupported, so the output is the same everywhere.
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--direct", "--buffer", "1000"]);
+    cmd.assert()
+        .success()
+        .stdout("ac175545a9b0f6da0d5c03f5135563d8  file.txt\n");
+
+    // Larger than the buffer and not a multiple of the alignment
+    let dir = tempfile::tempdir()?;
+    let contents: Vec<u8> = (0..3 * 4096 + 100).map(|i| i as u8).collect();
+    std::fs::write(dir.path().join("data.bin"), contents)?;
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path()).args(["data.bin"]);
+    let expected = cmd.assert().success().get_output().stdout.clone();
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["data.bin", "--direct", "--buffer", "4096"]);
+    cmd.assert().success().stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn test_mmap() {
+    setup();
+    // Small files are read with the buffer unless the threshold is lowered
+    for threshold in ["0", "1M"] {
+        let mut cmd = hashall();
+        cmd.args([
+            "file.txt",
+            "archive.zip",
+            "-j",
+            "1",
+            "--mmap",
+            "--mmap-threshold",
+            threshold,
+        ]);
+        cmd.assert().success().stdout(
+            "ac175545a9b0f6da0d5c03f5135563d8  file.txt\n\
+             96e0b59e98d0afac097caca640ae89a7  archive.zip\n",
+        );
+    }
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--mmap-threshold", "0"]);
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "--mmap-threshold requires --mmap",
+    ));
+    // The fast preset turns on --mmap
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--preset", "fast", "--mmap-threshold", "1K"]);
+    cmd.assert().success();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--mmap", "--mmap-threshold", "big"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains("Failed to parse mmap threshold"));
+}
+
+#[test]
+fn test_io_bound() -> Result<()> {
+    setup();
+    let cores = std::thread::available_parallelism()?.get();
+    for (options, n_jobs) in [
+        // No more workers than files
+        (&["file.txt", "archive.zip"][..], cores.min(2)),
+        (&["file.txt", "directory"], cores),
+        (&["file.txt", "directory", "--io-bound"], cores.min(4)),
+        (&["directory", "--io-bound", "--cpu-bound"], cores),
+        (&["directory", "--cpu-bound", "--io-bound"], cores.min(4)),
+        // Explicit jobs are used as they are
+        (&["file.txt", "archive.zip", "--io-bound", "-j", "8"], 8),
+    ] {
+        let mut cmd = hashall();
+        cmd.args(options)
+            .env_remove("HASHALL_JOBS")
+            .env("RUST_LOG", "debug");
+        cmd.assert()
+            .success()
+            .stderr(predicates::str::contains(format!("n_jobs: {}\n", n_jobs)));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_spdx() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--format", "spdx", "--hash", "sha256"]);
+    cmd.assert().success().stdout(
+        "FileName: ./file.txt\n\
+         FileChecksum: SHA256: 64c7ed6638f4224634b2223f16139fc8abb30905ff5502de0468bce246f98a2c\n\n",
+    );
+    Ok(())
+}
+
+#[test]
+fn test_env() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt"])
+        .env("HASHALL_BUFFER", "123")
+        .env("HASHALL_JOBS", "1")
+        .env("RUST_LOG", "debug");
+    cmd.assert()
+        .success()
+        .stderr(predicates::str::contains("buffer_size: 123"))
+        .stderr(predicates::str::contains("n_jobs: 1"));
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt"]).env("HASHALL_BUFFER", "invalid");
+    cmd.assert().failure();
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt"]).env("HASHALL_JOBS", "invalid");
+    cmd.assert().failure();
+
+    // explicit options take precedence over the environment variables
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--buffer", "1M", "--jobs", "1"])
+        .env("HASHALL_BUFFER", "invalid")
+        .env("HASHALL_JOBS", "invalid");
+    cmd.assert().success();
+
+    // The buffer size is computed from the available memory
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--buffer", "auto"])
+        .env("RUST_LOG", "debug");
+    cmd.assert()
+        .success()
+        .stdout("ac175545a9b0f6da0d5c03f5135563d8  file.txt\n")
+        .stderr(predicates::str::contains("buffer_size: "));
+    let mut cmd = hashall();
+    cmd.args(["file.txt"]).env("HASHALL_BUFFER", "auto");
+    cmd.assert().success();
+
+    // 1 PB per worker doesn't fit in memory
+    if cfg!(target_os = "linux") {
+        let mut cmd = hashall();
+        cmd.args(["file.txt", "--buffer", "1P", "--jobs", "2"]);
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("fewer --jobs"));
+    }
+    Ok(())
+}
+
+const MD5_HELLO: &str = "b1946ac92492d2347c6235b4d2611184"; // "hello\n"
+
+#[test]
+fn test_diff_baseline() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("same.txt"), "hello\n")?;
+    std::fs::write(dir.path().join("changed.txt"), "modified\n")?;
+    std::fs::write(dir.path().join("new.txt"), "hello\n")?;
+    let baseline = tempfile::NamedTempFile::new()?;
+    std::fs::write(
+        baseline.path(),
+        format!(
+            "{MD5_HELLO}  ./same.txt\n{MD5_HELLO}  ./changed.txt\n{MD5_HELLO}  ./deleted.txt\n"
+        ),
+    )?;
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .arg(".")
+        .arg("--diff-baseline")
+        .arg(baseline.path());
+    cmd.assert().success();
+    let output = sort_output(cmd.output()?.stdout)?;
+    assert_eq!(output, "\n./changed.txt: CHANGED\n./new.txt: NEW");
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .arg(".")
+        .arg("--diff-baseline")
+        .arg(baseline.path())
+        .arg("--show-deleted");
+    cmd.assert().success();
+    let output = sort_output(cmd.output()?.stdout)?;
+    assert_eq!(
+        output,
+        "\n./changed.txt: CHANGED\n./deleted.txt: DELETED\n./new.txt: NEW"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_directory() -> Result<()> {
+    setup();
+    let mut cmd = Command::cargo_bin("hashall").unwrap();
+    cmd.current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["-C", "tests/data", "file.txt", "directory"]);
+    cmd.assert().success();
+    let output = sort_output(cmd.output()?.stdout)?;
+    assert_eq!(
+        output,
+        "\n6657b6593444bd9a13d0131d47bef4f5  directory/file.txt\nac175545a9b0f6da0d5c03f5135563d8  file.txt"
+            .replace('/', std::path::MAIN_SEPARATOR_STR)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_status() -> Result<()> {
+    setup();
+    let baseline = tempfile::NamedTempFile::new()?;
+    std::fs::write(
+        baseline.path(),
+        "ac175545a9b0f6da0d5c03f5135563d8  ./file.txt\n\
+         00000000000000000000000000000000  ./archive.zip\n\
+         00000000000000000000000000000000  ./deleted.txt\n",
+    )?;
+    let mut cmd = hashall();
+    cmd.args([".", "--status", "--show-deleted", "--diff-baseline"])
+        .arg(baseline.path());
+    cmd.assert().success();
+    let output = sort_output(cmd.output()?.stdout)?;
+    let (zip, tar, gz, zst, bz2, xz, file) = unsafe {
+        (
+            OUT_ARC_ZIP,
+            OUT_ARC_TAR,
+            OUT_ARC_TAR_GZ,
+            OUT_ARC_TAR_ZST,
+            OUT_ARC_TAR_BZ2,
+            OUT_ARC_TAR_XZ,
+            OUT_FILE,
+        )
+    };
+    assert_eq!(
+        output,
+        [
+            "".to_string(),
+            format!("*{zip}"),
+            format!("+{bz2}"),
+            format!("+{zst}"),
+            format!("+{gz}"),
+            format!("+{tar}"),
+            format!("+{xz}"),
+            "-00000000000000000000000000000000  ./deleted.txt".to_string(),
+            format!("={file}"),
+        ]
+        .join("\n")
+    );
+
+    // No prefix without a baseline
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--status"]);
+    cmd.assert()
+        .success()
+        .stdout("ac175545a9b0f6da0d5c03f5135563d8  file.txt\n");
+    Ok(())
+}
+
+/// Reap `child` and return its wait status and its peak resident set size in KiB. Unlike
+/// `getrusage(RUSAGE_CHILDREN)`, `wait4` reports only this child, not the children of the
+/// tests running in parallel.
+#[cfg(target_os = "linux")]
+fn wait_max_rss_kib(child: std::process::Child) -> std::io::Result<(libc::c_int, i64)> {
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut usage) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok((status, usage.ru_maxrss))
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_tar_large_member() -> Result<()> {
+    use std::io::Read;
+
+    const SIZE: u64 = 256 << 20;
+    let dir = tempfile::tempdir()?;
+    let file = std::fs::File::create(dir.path().join("large.tar.zst"))?;
+    let mut builder = tar::Builder::new(zstd::Encoder::new(file, 1)?.auto_finish());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(SIZE);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, "large.bin", std::io::repeat(0).take(SIZE))?;
+    drop(builder.into_inner()?);
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("hashall"))
+        .current_dir(dir.path())
+        .args(["large.tar.zst", "--archive", "-j", "1"])
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+    let mut stdout = String::new();
+    child.stdout.take().unwrap().read_to_string(&mut stdout)?;
+    let (status, max_rss_kib) = wait_max_rss_kib(child)?;
+    assert_eq!(status, 0);
+    assert_eq!(
+        stdout,
+        "1f5039e50bd66b290c56684d8550c6c2  large.tar.zst/large.bin\n"
+    );
+    // The member is streamed, not buffered
+    assert!(max_rss_kib < 64 << 10, "{} KiB", max_rss_kib);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_escaped_round_trip() -> Result<()> {
+    let names = [
+        "plain.txt",
+        "back\\slash",
+        "new\nline",
+        "carriage\rreturn",
+        "\\\\",
+        "\\n",
+        "mixed\\\n\r name",
+    ];
+    let dir = tempfile::tempdir()?;
+    for (i, name) in names.iter().enumerate() {
+        std::fs::write(dir.path().join(name), i.to_string())?;
+    }
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path()).arg(".");
+    let manifest = cmd.output()?.stdout;
+    // Escaped lines start with a backslash, so newlines only separate lines
+    assert_eq!(
+        manifest.iter().filter(|&&b| b == b'\n').count(),
+        names.len()
+    );
+
+    let baseline = tempfile::NamedTempFile::new()?;
+    std::fs::write(baseline.path(), manifest)?;
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args([".", "--status", "--show-deleted", "--diff-baseline"])
+        .arg(baseline.path());
+    let output = String::from_utf8(cmd.output()?.stdout)?;
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), names.len());
+    assert!(lines.iter().all(|line| line.starts_with('=')), "{output}");
+
+    // --check escapes the paths in its report like `md5sum -c`
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .arg("--check")
+        .arg(baseline.path());
+    let output = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), names.len(), "{output}");
+    assert!(lines.iter().all(|line| line.ends_with(": OK")), "{output}");
+    assert!(lines.contains(&"\\./new\\nline: OK"), "{output}");
+    assert!(lines.contains(&"\\./back\\\\slash: OK"), "{output}");
+    assert!(lines.contains(&"./plain.txt: OK"), "{output}");
+    Ok(())
+}
+
+#[test]
+fn test_single_archive() -> Result<()> {
+    setup();
+    let contents = unsafe { OUT_ARC_CONTENTS }.replace(".zip", ".tar.gz");
+    let mut cmd = hashall();
+    cmd.args(["archive.tar.gz", "--archive", "--jobs", "8"])
+        .env("RUST_LOG", "debug");
+    cmd.assert()
+        .success()
+        .stdout(contents)
+        .stderr(predicates::str::contains(
+            "single archive on the main thread",
+        ));
+    Ok(())
+}
+
+#[test]
+fn test_archive_column() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args([
+        "archive.zip",
+        "file.txt",
+        "--archive",
+        "--format",
+        "csv",
+        "--archive-column",
+        "-j",
+        "1",
+    ]);
+    cmd.assert().success();
+    let output = sort_output(cmd.output()?.stdout)?;
+    assert_eq!(
+        output,
+        "
+28f9f80606380557b3a5034417227add,archive.zip,.hidden_file.txt
+6657b6593444bd9a13d0131d47bef4f5,archive.zip,directory/file.txt
+ac175545a9b0f6da0d5c03f5135563d8,,file.txt
+ac175545a9b0f6da0d5c03f5135563d8,archive.zip,file.txt
+hash,archive,filename"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_archive_paths() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args([
+        "archive.zip",
+        "file.txt",
+        "--archive",
+        "--dereference-archive-paths",
+    ]);
+    let output = sort_output(cmd.output()?.stdout)?;
+    assert_eq!(
+        output,
+        "
+28f9f80606380557b3a5034417227add  .hidden_file.txt
+6657b6593444bd9a13d0131d47bef4f5  directory/file.txt
+ac175545a9b0f6da0d5c03f5135563d8  file.txt
+ac175545a9b0f6da0d5c03f5135563d8  file.txt"
+    );
+
+    let mut cmd = hashall();
+    cmd.args([
+        "archive.tar",
+        "file.txt",
+        "--archive",
+        "--archive-separator",
+        "!",
+    ]);
+    let output = sort_output(cmd.output()?.stdout)?;
+    assert_eq!(
+        output,
+        "
+28f9f80606380557b3a5034417227add  archive.tar!.hidden_file.txt
+6657b6593444bd9a13d0131d47bef4f5  archive.tar!directory/file.txt
+ac175545a9b0f6da0d5c03f5135563d8  archive.tar!file.txt
+ac175545a9b0f6da0d5c03f5135563d8  file.txt"
+    );
+
+    for options in [
+        &["--archive-separator", "!"][..],
+        &[
+            "--archive",
+            "--dereference-archive-paths",
+            "--archive-separator",
+            "!",
+        ],
+        &[
+            "--archive",
+            "--dereference-archive-paths",
+            "--archive-column",
+        ],
+    ] {
+        let mut cmd = hashall();
+        cmd.arg("archive.zip").args(options);
+        cmd.assert().failure();
+    }
+    Ok(())
+}
+
+#[test]
+fn test_checkpoint() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    let checkpoint = dir.path().join("checkpoint");
+    let expected = "228aa74c5532873a9de66f70f258511a2af4f64d435924acb3bc536b1ff196d5  file.txt\n";
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--hash", "blake3"]);
+    cmd.assert().success().stdout(expected);
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--hash", "blake3", "--checkpoint"])
+        .arg(&checkpoint);
+    cmd.assert().success().stdout(expected);
+    assert!(!checkpoint.exists());
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--checkpoint"]).arg(&checkpoint);
+    cmd.assert().failure();
+    let mut cmd = hashall();
+    cmd.args([".", "--hash", "blake3", "--checkpoint"])
+        .arg(&checkpoint);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_blake3() -> Result<()> {
+    setup();
+    // Standard 64-hex-char digests, computed by the workers
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("empty"), "")?;
+    std::fs::copy("tests/data/file.txt", dir.path().join("file.txt"))?;
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args([".", "--hash", "blake3", "-j", "2"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let mut lines: Vec<_> = std::str::from_utf8(&output)?.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            "228aa74c5532873a9de66f70f258511a2af4f64d435924acb3bc536b1ff196d5  ./file.txt",
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262  ./empty",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_group_by_hash() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("a.txt"), "hello\n")?;
+    std::fs::write(dir.path().join("b.txt"), "hello\n")?;
+    std::fs::write(dir.path().join("c.txt"), "other\n")?;
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path()).args([".", "--group-by-hash"]);
+    cmd.assert().success().stdout(format!(
+        "{MD5_HELLO}\n./a.txt\n./b.txt\n\nba7790b1708b71cb2b61b1a30d824712\n./c.txt\n"
+    ));
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args([".", "--group-by-hash", "--duplicates-only"]);
+    cmd.assert()
+        .success()
+        .stdout(format!("{MD5_HELLO}\n./a.txt\n./b.txt\n"));
+
+    // Shorthand for both
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path()).args([".", "--find-dupes"]);
+    cmd.assert()
+        .success()
+        .stdout(format!("{MD5_HELLO}\n./a.txt\n./b.txt\n"));
+    for option in ["--group-by-hash", "--canonical", "--dir-hashes"] {
+        let mut cmd = hashall();
+        cmd.current_dir(dir.path())
+            .args([".", "--find-dupes", option]);
+        cmd.assert().failure();
+    }
+    Ok(())
+}
+
+#[test]
+fn test_output() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    for i in 0..200 {
+        std::fs::write(dir.path().join(format!("{i}.txt")), "hello\n")?;
+    }
+    let out_dir = tempfile::tempdir()?;
+    let manifest = out_dir.path().join("manifest.csv");
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args([".", "--format", "csv", "-j", "4", "--output"])
+        .arg(&manifest);
+    cmd.assert().success().stdout("");
+
+    // The header comes first, and lines written by concurrent workers are not interleaved
+    let contents = std::fs::read_to_string(&manifest)?;
+    let mut lines = contents.lines();
+    assert_eq!(lines.next(), Some("hash,filename"));
+    let mut paths: Vec<_> = lines
+        .map(|line| line.strip_prefix(&format!("{MD5_HELLO},")).unwrap())
+        .collect();
+    paths.sort();
+    let mut expected: Vec<_> = (0..200).map(|i| format!("{i}.txt")).collect();
+    expected.sort();
+    assert_eq!(paths, expected);
+    Ok(())
+}
+
+#[test]
+fn test_compress_output() -> Result<()> {
+    use std::io::Read;
+    setup();
+    let dir = tempfile::tempdir()?;
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--compress-output", "gz", "--output"])
+        .arg(dir.path().join("manifest.txt"));
+    cmd.assert().success().stdout("");
+
+    let compressed = std::fs::File::open(dir.path().join("manifest.txt.gz"))?;
+    let mut contents = String::new();
+    flate2::read::GzDecoder::new(compressed).read_to_string(&mut contents)?;
+    assert_eq!(contents, "ac175545a9b0f6da0d5c03f5135563d8  file.txt\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--compress-output", "zst", "--output"])
+        .arg(dir.path().join("manifest.zst"));
+    cmd.assert().success();
+    let contents = zstd::decode_all(std::fs::File::open(dir.path().join("manifest.zst"))?)?;
+    assert_eq!(contents, b"ac175545a9b0f6da0d5c03f5135563d8  file.txt\n");
+    Ok(())
+}
+
+#[test]
+fn test_output_in_input_dir() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("file.txt"), "hello\n")?;
+
+    // The output file isn't hashed while it's being written
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["-r", ".", "-o", "sums.md5"]);
+    cmd.assert().success();
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("sums.md5"))?,
+        format!("{MD5_HELLO}  ./file.txt\n")
+    );
+
+    // Also when the output is named by another path to the same file
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["-r", ".", "-o"])
+        .arg(dir.path().join("sums.md5"));
+    cmd.assert().success();
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("sums.md5"))?,
+        format!("{MD5_HELLO}  ./file.txt\n")
+    );
+
+    // Other files of the same name are hashed
+    std::fs::create_dir(dir.path().join("sub"))?;
+    std::fs::write(dir.path().join("sub/sums.md5"), "hello\n")?;
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["-r", ".", "--sort", "-o", "sums.md5"]);
+    cmd.assert().success();
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("sums.md5"))?,
+        format!("{MD5_HELLO}  ./file.txt\n{MD5_HELLO}  ./sub/sums.md5\n")
+    );
+    Ok(())
+}
+
+#[test]
+fn test_sha3() -> Result<()> {
+    setup();
+    // Same as `openssl dgst -sha3-256` and `-sha3-512`
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--hash", "sha3-256"]);
+    cmd.assert()
+        .success()
+        .stdout("f77e7f20752f0a0f6ff8b46aa0f08b5c521115f1fa40520a3baf46cfb5a3a6b3  file.txt\n");
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--hash", "sha3-512", "--tag"]);
+    cmd.assert().success().stdout(
+        "SHA3-512 (file.txt) = c4a54741d111927f03ff815382e810d242bced189c31ffbece125818394265f5\
+         46353bc74be62a98a35a784451b1708c78a92e8fc5c7923c4b18ad5a77a9a2fe\n",
+    );
+
+    // BSD-style lines name the algorithm, so they can be checked without --hash
+    let dir = tempfile::tempdir()?;
+    let sums = dir.path().join("sums.txt");
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "directory", "--hash", "sha3-256", "--tag", "-o"])
+        .arg(&sums);
+    cmd.assert().success();
+    let mut cmd = hashall();
+    cmd.arg("--check").arg(&sums);
+    cmd.assert().success();
+    Ok(())
+}
+
+#[test]
+fn test_xxh3() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--hash", "xxh3"]);
+    cmd.assert()
+        .success()
+        .stdout("6ec87a8369735f01  file.txt\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--hash", "xxh3", "--byte-order", "le"]);
+    cmd.assert()
+        .success()
+        .stdout("015f7369837ac86e  file.txt\n");
+
+    // Reference value of XXH3_64bits with the default seed
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("empty"), "")?;
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["empty", "--hash", "xxh3"]);
+    cmd.assert().success().stdout("2d06800538d394c2  empty\n");
+
+    // 16 hex digits are taken as XXH3
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--expect", "6ec87a8369735f01"]);
+    cmd.assert().success();
+    Ok(())
+}
+
+#[test]
+fn test_adler32() {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--hash", "adler32"]);
+    cmd.assert().success().stdout("e1050c95  file.txt\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--hash", "adler32", "--byte-order", "le"]);
+    cmd.assert().success().stdout("950c05e1  file.txt\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--hash", "adler32", "--tag"]);
+    cmd.assert()
+        .success()
+        .stdout("ADLER32 (file.txt) = e1050c95\n");
+
+    // 8 hex digits can be CRC32 or Adler32, so the algorithm must be given
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--expect", "e1050c95", "--hash", "adler32"]);
+    cmd.assert().success();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--expect", "e1050c95"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains("Use --hash."));
+}
+
+#[test]
+fn test_byte_order() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--hash", "crc32"]);
+    cmd.assert().success().stdout("42ab22cc  file.txt\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--hash", "crc32", "--byte-order", "le"]);
+    cmd.assert().success().stdout("cc22ab42  file.txt\n");
+
+    // Byte order doesn't apply to byte-string hashes
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--byte-order", "le"]);
+    cmd.assert()
+        .success()
+        .stdout("ac175545a9b0f6da0d5c03f5135563d8  file.txt\n");
+    Ok(())
+}
+
+#[test]
+fn test_signify() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--format", "signify", "--hash", "sha256"]);
+    cmd.assert().success().stdout(
+        "SHA256 (file.txt) = 64c7ed6638f4224634b2223f16139fc8abb30905ff5502de0468bce246f98a2c\n",
+    );
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--format", "signify"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_per_input_algorithm() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args([
+        "--hash",
+        "sha256",
+        "file.txt:md5",
+        "directory/file.txt",
+        "-j",
+        "1",
+    ]);
+    cmd.assert().success().stdout(
+        "ac175545a9b0f6da0d5c03f5135563d8  file.txt
+34cbf16d403dbfcecbebb73b9a0bdd2f1324634afb6f86b5eeffbaf3a90e1a50  directory/file.txt
+",
+    );
+    Ok(())
+}
+
+#[test]
+fn test_tsv() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "archive.zip", "--archive", "--tsv", "-j", "1"]);
+    cmd.assert().success();
+    let stdout = String::from_utf8(cmd.output()?.stdout)?;
+    assert_eq!(stdout.lines().count(), 4);
+    for line in stdout.lines() {
+        assert_eq!(line.matches('\t').count(), 1, "{}", line);
+    }
+    assert_eq!(
+        stdout.lines().next(),
+        Some("ac175545a9b0f6da0d5c03f5135563d8\tfile.txt")
+    );
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--format", "tsv"]);
+    cmd.assert()
+        .success()
+        .stdout("ac175545a9b0f6da0d5c03f5135563d8\tfile.txt\n");
+    Ok(())
+}
+
+#[test]
+fn test_deadline() -> Result<()> {
+    use std::io::{BufRead, Read, Write};
+    setup();
+    // Stdin is held open until the deadline has passed, so file.txt is never started
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("hashall"))
+        .current_dir(data_dir())
+        .args(["-", "file.txt", "--deadline", "10ms"])
+        .env("RUST_LOG", "hashall=debug")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    let mut stderr = std::io::BufReader::new(child.stderr.take().unwrap());
+    // Logged once the stop is requested
+    let mut line = String::new();
+    while !line.contains("Deadline exceeded") {
+        line.clear();
+        assert_ne!(stderr.read_line(&mut line)?, 0, "no deadline");
+    }
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"hello\n")?;
+    drop(stdin);
+    let mut rest = String::new();
+    stderr.read_to_string(&mut rest)?;
+    let output = child.wait_with_output()?;
+    assert_eq!(output.status.code(), Some(124), "{}", rest);
+    // The input being read is finished, and its line is complete
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        format!("{MD5_HELLO}  -\n")
+    );
+    assert!(rest.contains("The output is incomplete"), "{}", rest);
+    Ok(())
+}
+
+#[test]
+fn test_dir_hashes() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["directory", "--dir-hashes"]);
+    // md5 of "6657b6593444bd9a13d0131d47bef4f5  file.txt\n"
+    cmd.assert().success().stdout(
+        "6657b6593444bd9a13d0131d47bef4f5  directory/file.txt
+534ef7297a92fa25b049f24addb0463a  directory/
+"
+        .replace('/', std::path::MAIN_SEPARATOR_STR),
+    );
+    Ok(())
+}
+
+#[test]
+fn test_retry_archive() -> Result<()> {
+    use md5::Digest;
+    setup();
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("broken.zip"), "hello\n")?;
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["broken.zip", "--archive"]);
+    cmd.assert().failure();
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["broken.zip", "--archive", "--retry-archive"]);
+    cmd.assert()
+        .success()
+        .stdout(format!("{MD5_HELLO}  broken.zip\n"));
+
+    // Through the worker pool
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args([".", "--archive", "--retry-archive"]);
+    cmd.assert()
+        .success()
+        .stdout(format!("{MD5_HELLO}  ./broken.zip\n").replace('/', std::path::MAIN_SEPARATOR_STR));
+
+    // An archive that fails after some entries is printed only as a file
+    // The zip crate refuses the second entry after reading it
+    let bytes = zip_with(
+        &[("a.txt", b"hello\n"), ("b.txt", b"hello\n")],
+        zip::CompressionMethod::Deflated,
+        bad_crc32,
+    )?;
+    std::fs::write(dir.path().join("partial.zip"), &bytes)?;
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["partial.zip", "--archive", "--retry-archive"]);
+    cmd.assert()
+        .success()
+        .stdout(format!("{:x}  partial.zip\n", md5::Md5::digest(&bytes)));
+    Ok(())
+}
+
+#[test]
+fn test_sniff_archives() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    std::fs::copy(data_dir().join("archive.zip"), dir.path().join("download"))?;
+    std::fs::copy(
+        data_dir().join("archive.tar.gz"),
+        dir.path().join("backup.bin"),
+    )?;
+    std::fs::copy(data_dir().join("file.txt"), dir.path().join("file.txt"))?;
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["download", "backup.bin", "file.txt", "-j", "1"])
+        .args(["--archive", "--sniff-archives"]);
+    cmd.assert().success().stdout(
+        "ac175545a9b0f6da0d5c03f5135563d8  download/file.txt\n\
+         6657b6593444bd9a13d0131d47bef4f5  download/directory/file.txt\n\
+         28f9f80606380557b3a5034417227add  download/.hidden_file.txt\n\
+         ac175545a9b0f6da0d5c03f5135563d8  backup.bin/file.txt\n\
+         6657b6593444bd9a13d0131d47bef4f5  backup.bin/directory/file.txt\n\
+         28f9f80606380557b3a5034417227add  backup.bin/.hidden_file.txt\n\
+         ac175545a9b0f6da0d5c03f5135563d8  file.txt\n",
+    );
+
+    // Without the option, only the extensions are looked at
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path()).args(["download", "--archive"]);
+    cmd.assert()
+        .success()
+        .stdout("96e0b59e98d0afac097caca640ae89a7  download\n");
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["download", "--sniff-archives"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_threads_per_archive() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["archive.zip", "--archive", "--ordered", "-j", "1"]);
+    let expected = cmd.assert().success().get_output().stdout.clone();
+
+    // The entries are split across the workers, and --ordered keeps the order of the archive
+    for n in ["2", "3", "8"] {
+        let mut cmd = hashall();
+        cmd.args(["archive.zip", "--archive", "--ordered", "-j", "4"])
+            .args(["--threads-per-archive", n])
+            .env("RUST_LOG", "hashall::archive=debug");
+        cmd.assert()
+            .success()
+            .stdout(expected.clone())
+            .stderr(predicates::str::contains(
+                "archive.zip: reading entries 0..",
+            ));
+    }
+
+    // Tar archives are read whole
+    let mut cmd = hashall();
+    cmd.args(["archive.tar.gz", "--archive", "--threads-per-archive", "2"])
+        .env("RUST_LOG", "hashall::archive=debug");
+    cmd.assert()
+        .success()
+        .stderr(predicates::str::contains("tar archives are not split"));
+
+    // Archives that can't be listed are reported once
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("corrupt.zip"), "not a zip")?;
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["corrupt.zip", "--archive", "--threads-per-archive", "2"]);
+    cmd.assert()
+        .code(1)
+        .stderr(predicates::str::contains("1 file could not be hashed"));
+
+    // The ranges arrive interleaved, and a failing range can't fall back to the whole file
+    for option in ["--sort", "--retry-archive"] {
+        let mut cmd = hashall();
+        cmd.args([
+            "archive.zip",
+            "--archive",
+            "--threads-per-archive",
+            "2",
+            option,
+        ]);
+        cmd.assert()
+            .failure()
+            .stdout("")
+            .stderr(predicates::str::contains("cannot be used with"));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_archive_recursive() -> Result<()> {
+    use md5::Digest;
+    setup();
+    let dir = tempfile::tempdir()?;
+    // nested.tar.gz contains archive.zip and mid.tar, which contains archive.tar.gz
+    let mut mid = tar::Builder::new(Vec::new());
+    mid.append_path_with_name(data_dir().join("archive.tar.gz"), "archive.tar.gz")?;
+    let mid = mid.into_inner()?;
+    let file = std::fs::File::create(dir.path().join("nested.tar.gz"))?;
+    let mut outer = tar::Builder::new(flate2::write::GzEncoder::new(
+        file,
+        flate2::Compression::default(),
+    ));
+    outer.append_path_with_name(data_dir().join("archive.zip"), "archive.zip")?;
+    outer.append_path_with_name(data_dir().join("file.txt"), "file.txt")?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(mid.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    outer.append_data(&mut header, "dir/mid.tar", mid.as_slice())?;
+    outer.into_inner()?.finish()?;
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["nested.tar.gz", "--archive", "--archive-recursive"]);
+    cmd.assert().success().stdout(
+        "ac175545a9b0f6da0d5c03f5135563d8  nested.tar.gz/archive.zip/file.txt\n\
+         6657b6593444bd9a13d0131d47bef4f5  nested.tar.gz/archive.zip/directory/file.txt\n\
+         28f9f80606380557b3a5034417227add  nested.tar.gz/archive.zip/.hidden_file.txt\n\
+         ac175545a9b0f6da0d5c03f5135563d8  nested.tar.gz/file.txt\n\
+         ac175545a9b0f6da0d5c03f5135563d8  nested.tar.gz/dir/mid.tar/archive.tar.gz/file.txt\n\
+         6657b6593444bd9a13d0131d47bef4f5  nested.tar.gz/dir/mid.tar/archive.tar.gz/directory/file.txt\n\
+         28f9f80606380557b3a5034417227add  nested.tar.gz/dir/mid.tar/archive.tar.gz/.hidden_file.txt\n",
+    );
+
+    // Without the option, the archives in the archive are hashed as files
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["nested.tar.gz", "--archive"]);
+    cmd.assert().success().stdout(format!(
+        "96e0b59e98d0afac097caca640ae89a7  nested.tar.gz/archive.zip\n\
+         ac175545a9b0f6da0d5c03f5135563d8  nested.tar.gz/file.txt\n\
+         {:x}  nested.tar.gz/dir/mid.tar\n",
+        md5::Md5::digest(&mid)
+    ));
+
+    let mut cmd = hashall();
+    cmd.args(["archive.zip", "--archive-recursive"]);
+    cmd.assert().failure();
+
+    // Zips in zips 20 levels deep. The ones below the 16th level are hashed as files.
+    let mut levels = vec![b"hello\n".to_vec()];
+    for level in 1..=20 {
+        let name = if level == 1 { "file.txt" } else { "nested.zip" };
+        let entries = [(name, levels[level - 1].as_slice())];
+        levels.push(zip_with(&entries, zip::CompressionMethod::Stored, |_| {})?);
+    }
+    std::fs::write(dir.path().join("deep.zip"), &levels[20])?;
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["deep.zip", "--archive", "--archive-recursive"]);
+    cmd.assert()
+        .success()
+        .stdout(format!(
+            "{:x}  deep.zip{}\n",
+            md5::Md5::digest(&levels[3]),
+            "/nested.zip".repeat(17)
+        ))
+        .stderr(predicates::str::contains(
+            "archives nested more than 16 deep are hashed as files",
+        ));
+    Ok(())
+}
+
+#[test]
+fn test_log_targets() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args([".", "--archive"]).env("RUST_LOG", "debug");
+    let all = String::from_utf8(cmd.output()?.stderr)?;
+    assert!(all.contains("hashall::walk"));
+    assert!(all.contains("hashall::pool"));
+    assert!(all.contains("hashall::archive"));
+
+    let mut cmd = hashall();
+    cmd.args([".", "--archive"])
+        .env("RUST_LOG", "hashall::archive=debug");
+    let archive = String::from_utf8(cmd.output()?.stderr)?;
+    assert!(archive.contains("archive.zip: 3 files"));
+    assert!(!archive.contains("hashall::walk"));
+    assert!(!archive.contains("hashall::pool"));
+    assert!(archive.lines().count() < all.lines().count());
+    Ok(())
+}
+
+#[test]
+fn test_double() -> Result<()> {
+    setup();
+    // python: sha256(sha256(data).digest()).hexdigest()
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--hash", "sha256", "--double"]);
+    cmd.assert()
+        .success()
+        .stdout("759dfbe67fc5b1a415f0b583a52ba7a7f699f10b6a3f066ab5133d8ea890f959  file.txt\n");
+    Ok(())
+}
+
+#[test]
+fn test_diff_baseline_separators() -> Result<()> {
+    setup();
+    let baseline = tempfile::NamedTempFile::new()?;
+    // Written on Windows
+    std::fs::write(
+        baseline.path(),
+        "6657b6593444bd9a13d0131d47bef4f5  .\\directory\\file.txt\n",
+    )?;
+    let mut cmd = hashall();
+    cmd.args([".", "-r", "--diff-baseline"])
+        .arg(baseline.path())
+        .args(["--show-deleted", "--status"]);
+    cmd.assert().success();
+    let output = String::from_utf8(cmd.output()?.stdout)?;
+    assert!(
+        output.contains("=6657b6593444bd9a13d0131d47bef4f5  "),
+        "{}",
+        output
+    );
+    assert!(
+        !output.lines().any(|line| line.starts_with('-')),
+        "{}",
+        output
+    );
+    Ok(())
+}
+
+#[test]
+fn test_names_only() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir(dir.path().join("tree"))?;
+    std::fs::write(dir.path().join("tree/a.txt"), "hello\n")?;
+    let names_hash = || -> Result<String> {
+        let mut cmd = hashall();
+        cmd.current_dir(dir.path())
+            .args(["tree", "-r", "--names-only"]);
+        Ok(String::from_utf8(
+            cmd.assert().success().get_output().stdout.clone(),
+        )?)
+    };
+
+    // md5 of "a.txt\n"
+    let before = names_hash()?;
+    assert_eq!(before, "cc419094a83bfb26723ba8d64b6e6aab  tree\n");
+
+    std::fs::write(dir.path().join("tree/a.txt"), "modified\n")?;
+    assert_eq!(names_hash()?, before);
+
+    std::fs::write(dir.path().join("tree/b.txt"), "hello\n")?;
+    assert_ne!(names_hash()?, before);
+    Ok(())
+}
+
+#[test]
+fn test_size() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("a.bin"), vec![0u8; 1536])?;
+    std::fs::write(dir.path().join("b.bin"), vec![0u8; 340 << 10])?;
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["a.bin", "--size", "-f", "csv"]);
+    cmd.assert()
+        .success()
+        .stdout("hash,size,filename\n53e979547d8c2ea86560ac45de08ae25,1536,a.bin\n");
+
+    // Powers of 1024, one decimal below 10
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["a.bin", "b.bin", "--size", "--human", "-j", "1"]);
+    cmd.assert().success().stdout(
+        "53e979547d8c2ea86560ac45de08ae25  1.5K  a.bin\n\
+         a144cf485ae84ea5fde56f0de70a3dad  340K  b.bin\n",
+    );
+
+    // Archive entries have the size of the decompressed contents
+    let mut cmd = hashall();
+    cmd.args([
+        "archive.zip",
+        "archive.tar.xz",
+        "--archive",
+        "--size",
+        "-j",
+        "1",
+    ]);
+    cmd.assert().success().stdout(
+        "ac175545a9b0f6da0d5c03f5135563d8  35  archive.zip/file.txt\n\
+         6657b6593444bd9a13d0131d47bef4f5  36  archive.zip/directory/file.txt\n\
+         28f9f80606380557b3a5034417227add  14  archive.zip/.hidden_file.txt\n\
+         ac175545a9b0f6da0d5c03f5135563d8  35  archive.tar.xz/file.txt\n\
+         6657b6593444bd9a13d0131d47bef4f5  36  archive.tar.xz/directory/file.txt\n\
+         28f9f80606380557b3a5034417227add  14  archive.tar.xz/.hidden_file.txt\n",
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_external_hasher() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "directory", "--hash", "sha1", "-j", "1"]);
+    let builtin = cmd.assert().success().get_output().stdout.clone();
+
+    let mut cmd = hashall();
+    cmd.args([
+        "file.txt",
+        "directory",
+        "--external-hasher",
+        "sha1sum",
+        "-j",
+        "1",
+    ]);
+    cmd.assert().success().stdout(builtin);
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--external-hasher", "echo not-a-hash"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_expect() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--expect", "AC175545A9B0F6DA0D5C03F5135563D8"]);
+    cmd.assert().success().stdout("file.txt: OK\n");
+
+    // The algorithm is inferred from the length if only one algorithm gives it
+    let sha256 = "64c7ed6638f4224634b2223f16139fc8abb30905ff5502de0468bce246f98a2c";
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--expect", sha256]);
+    cmd.assert()
+        .failure()
+        .stdout("")
+        .stderr(predicates::str::contains(
+            "can be sha256, sha3-256, blake3. Use --hash.",
+        ));
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--expect", sha256, "--hash", "sha256"]);
+    cmd.assert().success().stdout("file.txt: OK\n");
+
+    // Written to --output
+    let dir = tempfile::tempdir()?;
+    let output = dir.path().join("result.txt");
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--expect", MD5_HELLO, "--output"])
+        .arg(&output);
+    cmd.assert().code(1).stdout("");
+    assert_eq!(std::fs::read_to_string(&output)?, "file.txt: FAILED\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--expect", MD5_HELLO]);
+    cmd.assert().code(1).stdout("file.txt: FAILED\n");
+
+    let mut cmd = hashall();
+    cmd.args([
+        "file.txt",
+        "--hash",
+        "sha1",
+        "--expect",
+        "ac175545a9b0f6da0d5c03f5135563d8",
+    ]);
+    cmd.assert().code(1);
+    Ok(())
+}
+
+#[test]
+fn test_chunks_json() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    // Pseudo-random bytes (64-bit LCG), so that the boundaries depend on the contents
+    let mut state: u64 = 12345;
+    let data: Vec<u8> = (0..1 << 20)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        })
+        .collect();
+    std::fs::write(dir.path().join("data.bin"), &data)?;
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["data.bin", "-f", "chunks-json", "--chunk-seed", "0"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let object: serde_json::Value = serde_json::from_slice(&output)?;
+    assert_eq!(object["path"], "data.bin");
+    assert_eq!(object["hash"], "52d11f8aa3c798756f56553529088bcf");
+    let chunks = object["chunks"].as_array().unwrap();
+    let boundaries: Vec<(u64, u64)> = chunks
+        .iter()
+        .map(|chunk| {
+            assert_eq!(chunk["hash"].as_str().unwrap().len(), 32);
+            (
+                chunk["offset"].as_u64().unwrap(),
+                chunk["len"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        boundaries[..4],
+        [
+            (0, 34059),
+            (34059, 90609),
+            (124668, 84187),
+            (208855, 123329)
+        ]
+    );
+    let mut offset = 0;
+    for (chunk_offset, len) in &boundaries {
+        assert_eq!(*chunk_offset, offset);
+        offset += len;
+    }
+    assert_eq!(offset, data.len() as u64);
+
+    // Boundaries don't depend on how the file is read
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["data.bin", "-f", "chunks-json", "-b", "1000"]);
+    cmd.assert().success().stdout(output.clone());
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path()).args([
+        "data.bin",
+        "-f",
+        "chunks-json",
+        "--mmap",
+        "--mmap-threshold",
+        "0",
+    ]);
+    cmd.assert().success().stdout(output);
+    Ok(())
+}
+
+#[test]
+fn test_worker_thread_names() {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "-j", "2", "--pin-threads"])
+        .env("RUST_LOG", "hashall::pool=debug");
+    cmd.assert()
+        .success()
+        .stderr(predicates::str::contains(
+            "Worker 0: started in thread hashall-worker-0",
+        ))
+        .stderr(predicates::str::contains(
+            "Worker 1: started in thread hashall-worker-1",
+        ));
+}
+
+#[test]
+fn test_worker_errors() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    // Archives that fail to read are reported, and the worker goes on with the next job
+    std::fs::write(dir.path().join("a.zip"), "not a zip")?;
+    std::fs::write(dir.path().join("b.zip"), "not a zip")?;
+    std::fs::write(dir.path().join("c.txt"), "hello\n")?;
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path()).args([
+        "a.zip",
+        "b.zip",
+        "c.txt",
+        "--archive",
+        "-j",
+        "1",
+        "--pin-threads",
+    ]);
+    let assert = cmd.assert().code(1).stdout(format!("{MD5_HELLO}  c.txt\n"));
+    let stderr = std::str::from_utf8(&assert.get_output().stderr)?;
+    assert!(stderr.contains("a.zip: "), "{stderr}");
+    assert!(stderr.contains("b.zip: "), "{stderr}");
+    assert!(stderr.contains("2 files could not be hashed"), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+
+    // A single archive, which is read on the main thread, is reported the same way
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path()).args(["a.zip", "--archive"]);
+    let assert = cmd.assert().code(1).stdout("");
+    let stderr = std::str::from_utf8(&assert.get_output().stderr)?;
+    assert!(stderr.contains("a.zip: "), "{stderr}");
+    assert!(stderr.contains("1 file could not be hashed"), "{stderr}");
+
+    // An archive that fails after an entry is read leaves nothing in the hash of the next file
+    let bytes = zip_with(
+        &[("hello.txt", b"hello\n")],
+        zip::CompressionMethod::Deflated,
+        bad_crc32,
+    )?;
+    std::fs::write(dir.path().join("a.zip"), bytes)?;
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["a.zip", "c.txt", "--archive", "-j", "1"]);
+    cmd.assert().code(1).stdout(format!("{MD5_HELLO}  c.txt\n"));
+    Ok(())
+}
+
+#[test]
+fn test_total() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--total"]);
+    cmd.assert()
+        .success()
+        .stdout("ac175545a9b0f6da0d5c03f5135563d8  file.txt\n# 1 file, 35 B\n");
+
+    let mut cmd = hashall();
+    cmd.args([".", "-r", "--total"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let output = String::from_utf8(output)?;
+    assert_eq!(output.lines().last(), Some("# 8 files, 12 KiB"));
+
+    // Sizes of the archive entries, not of the archives
+    let mut cmd = hashall();
+    cmd.args([".", "-r", "--archive", "--total"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let output = String::from_utf8(output)?;
+    assert_eq!(output.lines().last(), Some("# 20 files, 581 B"));
+
+    // The comment is skipped when the manifest is read back
+    let dir = tempfile::tempdir()?;
+    let manifest = dir.path().join("manifest");
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "directory", "--total", "--output"])
+        .arg(&manifest);
+    cmd.assert().success();
+    let mut cmd = hashall();
+    cmd.arg("--check").arg(&manifest);
+    cmd.assert().success().stdout(
+        "file.txt: OK\ndirectory/file.txt: OK\n".replace('/', std::path::MAIN_SEPARATOR_STR),
+    );
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "directory", "--diff-baseline"])
+        .arg(&manifest);
+    cmd.assert().success().stdout("");
+
+    // Not as a row of csv
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--total", "--format", "csv"]);
+    cmd.assert()
+        .success()
+        .stdout("hash,filename\nac175545a9b0f6da0d5c03f5135563d8,file.txt\n")
+        .stderr(predicates::str::contains("# 1 file, 35 B"));
+    Ok(())
+}
+
+#[test]
+fn test_only_text_binary() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "archive.zip", "--only-text"]);
+    cmd.assert()
+        .success()
+        .stdout("ac175545a9b0f6da0d5c03f5135563d8  file.txt\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "archive.zip", "--only-binary"]);
+    cmd.assert()
+        .success()
+        .stdout("96e0b59e98d0afac097caca640ae89a7  archive.zip\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--only-text", "--only-binary"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_jsonl() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "directory/file.txt", "-f", "jsonl", "-j", "1"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let objects = output
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(serde_json::from_slice)
+        .collect::<Result<Vec<serde_json::Value>, _>>()?;
+    assert_eq!(
+        objects,
+        [
+            serde_json::json!({"schema_version": 1, "algorithm": "md5"}),
+            serde_json::json!({
+                "path": "file.txt",
+                "hash": "ac175545a9b0f6da0d5c03f5135563d8",
+                "algorithm": "md5",
+                "size": 35
+            }),
+            serde_json::json!({
+                "path": "directory/file.txt",
+                "hash": "6657b6593444bd9a13d0131d47bef4f5",
+                "algorithm": "md5",
+                "size": 36
+            }),
+        ]
+    );
+
+    // Without the header, and with paths escaped
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("a,\"b\".txt"), "hello\n")?;
+    let mut cmd = hashall();
+    cmd.arg("-C").arg(dir.path()).args([".", "-f", "json"]);
+    cmd.assert().success().stdout(format!(
+        "{{\"path\":\"a,\\\"b\\\".txt\",\"hash\":\"{MD5_HELLO}\",\"algorithm\":\"md5\",\"size\":6}}\n"
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_verify_sizes() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    // Claim 100 bytes uncompressed in the central directory entry. Stored data is read up to
+    // the compressed size, so the entry still reads fine.
+    let bytes = zip_with(
+        &[("hello.txt", b"hello\n")],
+        zip::CompressionMethod::Stored,
+        |central| central[24..28].copy_from_slice(&100u32.to_le_bytes()),
+    )?;
+    std::fs::write(dir.path().join("crafted.zip"), bytes)?;
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["crafted.zip", "--archive", "--verify-sizes"]);
+    cmd.assert()
+        .success()
+        .stdout(format!("{MD5_HELLO}  crafted.zip/hello.txt\n"))
+        .stderr(predicates::str::contains(
+            "crafted.zip[hello.txt]: size mismatch (header 100, actual 6)",
+        ));
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["crafted.zip", "--archive"]);
+    cmd.assert()
+        .success()
+        .stderr(predicates::boolean::PredicateBooleanExt::not(
+            predicates::str::contains("size mismatch"),
+        ));
+    Ok(())
+}
+
+#[test]
+fn test_also_crc32() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--also-crc32", "--size"]);
+    cmd.assert()
+        .success()
+        .stdout("ac175545a9b0f6da0d5c03f5135563d8  42ab22cc  35  file.txt\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--also-crc32", "-f", "csv"]);
+    cmd.assert()
+        .success()
+        .stdout("hash,crc32,filename\nac175545a9b0f6da0d5c03f5135563d8,42ab22cc,file.txt\n");
+
+    // The CRC-32 follows the encoding of the hash
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--also-crc32", "--uppercase"]);
+    cmd.assert()
+        .success()
+        .stdout("AC175545A9B0F6DA0D5C03F5135563D8  42AB22CC  file.txt\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--also-crc32", "--base64", "-f", "json"]);
+    cmd.assert().success().stdout(predicates::str::contains(
+        r#""hash":"rBdVRamw9toNXAP1E1Vj2A==","algorithm":"md5","size":35,"crc32":"QqsizA==""#,
+    ));
+
+    // Corrupt the CRC-32 stored for a deflated entry
+    let dir = tempfile::tempdir()?;
+    let bytes = zip_with(
+        &[("hello.txt", b"hello\n")],
+        zip::CompressionMethod::Deflated,
+        bad_crc32,
+    )?;
+    std::fs::write(dir.path().join("corrupt.zip"), bytes)?;
+
+    // The zip crate refuses the entry
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["corrupt.zip", "--archive"]);
+    cmd.assert().failure();
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["corrupt.zip", "--archive", "--also-crc32"]);
+    cmd.assert()
+        .success()
+        .stdout(format!("{MD5_HELLO}  363a3020  corrupt.zip/hello.txt\n"))
+        .stderr(predicates::str::contains(
+            "corrupt.zip[hello.txt]: CRC32 mismatch (header 12345678, actual 363a3020)",
+        ));
+    Ok(())
+}
+
+#[test]
+fn test_bazel() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--format", "bazel", "--hash", "sha256"]);
+    cmd.assert()
+        .success()
+        .stdout("file.txt  sha256-ZMftZjj0IkY0siI/FhOfyKuzCQX/VQLeBGi84kb5iiw=\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--format", "bazel"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_skip_listed() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    let manifest = dir.path().join("partial.md5");
+    std::fs::write(
+        &manifest,
+        "ac175545a9b0f6da0d5c03f5135563d8  ./file.txt\n\
+         93bd005392ba45a764e048f936745f29  ./archive.tar\n\
+         11ead6a83b86a95427fca0f3d4dba0c7  ./archive.tar.bz2\n\
+         2d091500d5eaf8b02cab3f82aabb85e5  ./archive.tar.zst\n\
+         f067faa0bcfbda70e280a85c40d74a4e  ./archive.tar.xz\n\
+         697bfc68b92d00748110bfe0003da43e  ./archive.tar.gz\n",
+    )?;
+
+    let mut cmd = hashall();
+    cmd.args([".", "-r", "--skip-listed"]).arg(&manifest);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let mut lines: Vec<_> = std::str::from_utf8(&output)?.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            "6657b6593444bd9a13d0131d47bef4f5  ./directory/file.txt",
+            "96e0b59e98d0afac097caca640ae89a7  ./archive.zip",
+        ]
+    );
+
+    // Nothing is left to hash
+    let mut cmd = hashall();
+    cmd.args(["./file.txt", "--skip-listed"]).arg(&manifest);
+    cmd.assert().success().stdout("");
+    Ok(())
+}
+
+#[test]
+fn test_stdin_name() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.arg("-").write_stdin("hello\n");
+    cmd.assert().success().stdout(format!("{MD5_HELLO}  -\n"));
+
+    let mut cmd = hashall();
+    cmd.args(["-", "--stdin-name", "build.log"])
+        .write_stdin("hello\n");
+    cmd.assert()
+        .success()
+        .stdout(format!("{MD5_HELLO}  build.log\n"));
+    Ok(())
+}
+
+#[test]
+fn test_stdin() -> Result<()> {
+    setup();
+    // Streams longer than the buffer hash the same as the file
+    let mut cmd = hashall();
+    cmd.args(["-", "--hash", "sha256", "--buffer", "4"])
+        .write_stdin(std::fs::read(data_dir().join("file.txt"))?);
+    cmd.assert()
+        .success()
+        .stdout("64c7ed6638f4224634b2223f16139fc8abb30905ff5502de0468bce246f98a2c  -\n");
+
+    let mut cmd = hashall();
+    cmd.arg("-").write_stdin("");
+    cmd.assert()
+        .success()
+        .stdout("d41d8cd98f00b204e9800998ecf8427e  -\n");
+
+    // A zip on stdin is an archive with --archive
+    let mut cmd = hashall();
+    cmd.args(["-", "--archive", "--stdin-name", "piped.zip"])
+        .write_stdin(std::fs::read(data_dir().join("archive.zip"))?);
+    cmd.assert().success().stdout(
+        "ac175545a9b0f6da0d5c03f5135563d8  piped.zip/file.txt
+6657b6593444bd9a13d0131d47bef4f5  piped.zip/directory/file.txt
+28f9f80606380557b3a5034417227add  piped.zip/.hidden_file.txt
+",
+    );
+    let mut cmd = hashall();
+    cmd.arg("-")
+        .write_stdin(std::fs::read(data_dir().join("archive.zip"))?);
+    cmd.assert()
+        .success()
+        .stdout("96e0b59e98d0afac097caca640ae89a7  -\n");
+    // Anything else is hashed as a file, even if shorter than the signature
+    for (contents, hash) in [
+        ("hello\n", MD5_HELLO),
+        ("PK", "d71bdd22c8bb93b8d287dce6f46aed25"),
+        ("", "d41d8cd98f00b204e9800998ecf8427e"),
+    ] {
+        let mut cmd = hashall();
+        cmd.args(["-", "--archive"]).write_stdin(contents);
+        cmd.assert().success().stdout(format!("{hash}  -\n"));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_canonical() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args([
+        "./file.txt",
+        "directory",
+        "archive.zip",
+        "--archive",
+        "--canonical",
+    ]);
+    let first = cmd.assert().success().get_output().stdout.clone();
+    assert_eq!(
+        std::str::from_utf8(&first)?,
+        "28f9f80606380557b3a5034417227add  archive.zip/.hidden_file.txt\n\
+         6657b6593444bd9a13d0131d47bef4f5  archive.zip/directory/file.txt\n\
+         ac175545a9b0f6da0d5c03f5135563d8  archive.zip/file.txt\n\
+         6657b6593444bd9a13d0131d47bef4f5  directory/file.txt\n\
+         ac175545a9b0f6da0d5c03f5135563d8  file.txt\n"
+    );
+
+    // Shuffled inputs and another number of jobs
+    let mut cmd = hashall();
+    cmd.args([
+        "archive.zip",
+        "directory",
+        "file.txt",
+        "--archive",
+        "--canonical",
+        "-j",
+        "1",
+    ]);
+    cmd.assert().success().stdout(first);
+    Ok(())
+}
+
+#[test]
+fn test_canonical_spill() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    for i in 0..500 {
+        // Names in another order than the creation order
+        let name = format!("{:03}/{}.txt", (i * 37) % 100, i);
+        let path = dir.path().join(name);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, i.to_string())?;
+    }
+
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args([".", "-r", "--canonical"]);
+    let in_memory = cmd.assert().success().get_output().stdout.clone();
+    let lines: Vec<_> = std::str::from_utf8(&in_memory)?.lines().collect();
+    assert_eq!(lines.len(), 500);
+    let paths: Vec<_> = lines.iter().map(|line| &line[34..]).collect();
+    let mut sorted = paths.clone();
+    sorted.sort();
+    assert_eq!(paths, sorted);
+
+    // Dozens of runs on disk give the same output
+    let mut cmd = hashall();
+    cmd.arg("-C").arg(dir.path()).args([
+        ".",
+        "-r",
+        "--canonical",
+        "--spill-threshold",
+        "7",
+        "--total",
+    ]);
+    let mut expected = in_memory;
+    expected.extend_from_slice(b"# 500 files, 1.4 KiB\n");
+    cmd.assert().success().stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn test_sort_spill() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args([
+        "directory",
+        "archive.zip",
+        "-r",
+        "--archive",
+        "--sort",
+        "-j",
+        "4",
+    ]);
+    let in_memory = cmd.assert().success().get_output().stdout.clone();
+
+    // Runs of two records on disk, with the entries of the archive across runs
+    let mut cmd = hashall();
+    cmd.args([
+        "directory",
+        "archive.zip",
+        "-r",
+        "--archive",
+        "--sort",
+        "-j",
+        "4",
+    ])
+    .args(["--spill-threshold", "2", "--total"]);
+    let mut expected = in_memory;
+    expected.extend_from_slice(b"# 4 files, 121 B\n");
+    cmd.assert().success().stdout(expected);
+
+    // --spill-threshold only applies to sorted output
+    let mut cmd = hashall();
+    cmd.args(["directory", "--spill-threshold", "2"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_zip64_entry_count() -> Result<()> {
+    setup();
+    // More entries than a zip without zip64 can hold
+    const N_ENTRIES: usize = u16::MAX as usize + 10;
+    let dir = tempfile::tempdir()?;
+    let file = std::fs::File::create(dir.path().join("many.zip"))?;
+    let mut zip = zip::ZipWriter::new(std::io::BufWriter::new(file));
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for i in 0..N_ENTRIES {
+        zip.start_file(format!("{i}.txt"), options)?;
+        std::io::Write::write_all(&mut zip, b"hello\n")?;
+    }
+    zip.finish()?;
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["many.zip", "--archive", "--total"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let output = std::str::from_utf8(&output)?;
+    assert_eq!(output.lines().count(), N_ENTRIES + 1);
+    assert!(output.starts_with(&format!("{MD5_HELLO}  many.zip/0.txt\n")));
+    assert_eq!(
+        output.lines().last(),
+        Some(format!("# {} files, 384 KiB", N_ENTRIES).as_str())
+    );
+    Ok(())
+}
+
+#[test]
+fn test_fields() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args([
+        "file.txt",
+        "directory/file.txt",
+        "-j",
+        "1",
+        "-f",
+        "fields",
+        "--size",
+        "--field-sep",
+        ";",
+        "--record-sep",
+        "\\0",
+    ]);
+    cmd.assert().success().stdout(
+        "ac175545a9b0f6da0d5c03f5135563d8;35;file.txt\0\
+         6657b6593444bd9a13d0131d47bef4f5;36;directory/file.txt\0",
+    );
+
+    // A path containing a separator is an error rather than an ambiguous record
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("a b.txt"), "hello\n")?;
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["a b.txt", "-f", "fields", "--field-sep", " "]);
+    cmd.assert().failure();
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "-f", "fields", "--field-sep", "0"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_block_tree() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    let data: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+    std::fs::write(dir.path().join("data.bin"), data)?;
+
+    // Blocks 0 and 1 are 4096 bytes, block 2 is the remaining 1808 bytes.
+    // root = md5(md5(block 0 hash + block 1 hash) + block 2 hash)
+    let expected = "a0c16616c91907bd14e999986cf822d5  0  data.bin\n\
+                    c67faf40372a3d42b00e265e0f6b36a9  1  data.bin\n\
+                    b71574ab6a520d93d443a133e6bb3a6f  2  data.bin\n\
+                    1a97d3a6e10adef1f31e2a7f11dca0f1  root  data.bin\n";
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["data.bin", "--block-tree", "4KiB"]);
+    cmd.assert().success().stdout(expected);
+
+    // Independent of the read buffer
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["data.bin", "--block-tree", "4096", "-b", "1000"]);
+    cmd.assert().success().stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn test_compare() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    for tree in ["a", "b"] {
+        std::fs::create_dir_all(dir.path().join(tree).join("sub"))?;
+        std::fs::write(dir.path().join(tree).join("sub/same.txt"), "hello\n")?;
+    }
+    std::fs::write(dir.path().join("a/changed.txt"), "hello\n")?;
+    std::fs::write(dir.path().join("b/changed.txt"), "bye\n")?;
+
+    let mut cmd = hashall();
+    cmd.arg("-C").arg(dir.path()).args(["--compare", "a", "b"]);
+    cmd.assert().code(1).stdout(format!(
+        "--- a\n\
+         +++ b\n\
+         -{MD5_HELLO}  changed.txt\n\
+         +91fc14ad02afd60985bb8165bda320a6  changed.txt\n"
+    ));
+
+    std::fs::write(dir.path().join("b/sub/extra.txt"), "extra\n")?;
+    std::fs::write(dir.path().join("b/changed.txt"), "hello\n")?;
+    let mut cmd = hashall();
+    cmd.arg("-C").arg(dir.path()).args(["--compare", "a", "b"]);
+    cmd.assert().code(1).stdout(
+        "--- a\n\
+         +++ b\n\
+         +7b48666b13c02ffd7122df4275adc002  sub/extra.txt\n",
+    );
+
+    // Identical trees print nothing
+    std::fs::remove_file(dir.path().join("b/sub/extra.txt"))?;
+    let mut cmd = hashall();
+    cmd.arg("-C").arg(dir.path()).args(["--compare", "a", "b"]);
+    cmd.assert().success().stdout("");
+
+    for inputs in [&["a"][..], &["a", "a"], &["a", "b/changed.txt"]] {
+        let mut cmd = hashall();
+        cmd.arg("-C").arg(dir.path()).arg("--compare").args(inputs);
+        cmd.assert().failure();
+    }
+    Ok(())
+}
+
+#[test]
+fn test_diff() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    for tree in ["a", "b"] {
+        std::fs::create_dir_all(dir.path().join(tree).join("sub"))?;
+        std::fs::write(dir.path().join(tree).join("sub/same.txt"), "hello\n")?;
+    }
+    std::fs::write(dir.path().join("a/changed.txt"), "hello\n")?;
+    std::fs::write(dir.path().join("b/changed.txt"), "bye\n")?;
+    std::fs::write(dir.path().join("a/removed.txt"), "hello\n")?;
+    std::fs::write(dir.path().join("b/sub/added.txt"), "hello\n")?;
+    std::fs::write(dir.path().join("b/added.txt"), "hello\n")?;
+
+    let mut cmd = hashall();
+    cmd.arg("-C").arg(dir.path()).args(["--diff", "a", "b"]);
+    cmd.assert().code(1).stdout(
+        "added.txt: ADDED\n\
+         sub/added.txt: ADDED\n\
+         removed.txt: REMOVED\n\
+         changed.txt: CHANGED\n",
+    );
+
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args(["--diff", "a", "a/../a"]);
+    cmd.assert().success().stdout("");
+
+    for args in [&["--diff", "a"][..], &["--diff", "--compare", "a", "b"]] {
+        let mut cmd = hashall();
+        cmd.arg("-C").arg(dir.path()).args(args);
+        cmd.assert().failure();
+    }
+    Ok(())
+}
+
+#[test]
+fn test_tree_hash() -> Result<()> {
+    setup();
+    // md5 of "6657b6593444bd9a13d0131d47bef4f5  file.txt\n", the same as --dir-hashes
+    let mut cmd = hashall();
+    cmd.args(["directory", "--tree-hash"]);
+    cmd.assert()
+        .success()
+        .stdout("534ef7297a92fa25b049f24addb0463a  directory\n");
+
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir_all(dir.path().join("tree/sub/deeper"))?;
+    for (i, path) in ["a.txt", "sub/b.txt", "sub/deeper/c.txt", "z.txt"]
+        .iter()
+        .enumerate()
+    {
+        std::fs::write(dir.path().join("tree").join(path), format!("{i}\n"))?;
+    }
+    // The same regardless of the number of jobs
+    let root = |jobs: &str| -> Result<String> {
+        let mut cmd = hashall();
+        cmd.arg("-C")
+            .arg(dir.path())
+            .args(["tree", "--tree-hash", "-j", jobs]);
+        let output = cmd.assert().success().get_output().stdout.clone();
+        Ok(String::from_utf8(output)?)
+    };
+    let expected = root("1")?;
+    assert!(expected.ends_with("  tree\n"), "{}", expected);
+    assert_eq!(expected.lines().count(), 1);
+    for jobs in ["2", "8"] {
+        assert_eq!(root(jobs)?, expected);
+    }
+
+    // Overlapping inputs don't change each other's root
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args(["tree", "tree/sub", "--tree-hash"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let output = String::from_utf8(output)?;
+    let mut lines = output.lines();
+    assert_eq!(lines.next(), expected.lines().next());
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args(["tree/sub", "--tree-hash"]);
+    cmd.assert()
+        .success()
+        .stdout(format!("{}\n", lines.next().unwrap()));
+
+    // Any change in the contents or the paths changes the root
+    std::fs::rename(
+        dir.path().join("tree/sub/deeper/c.txt"),
+        dir.path().join("tree/sub/c.txt"),
+    )?;
+    assert_ne!(root("1")?, expected);
+
+    // A per-input algorithm hashes the files and the listing
+    let mut cmd = hashall();
+    cmd.args(["directory:sha1", "--tree-hash"]);
+    cmd.assert()
+        .success()
+        .stdout("45c10f5f6a2f79175610aa0fb6b2c487641b63cf  directory\n");
+    let mut cmd = hashall();
+    cmd.args(["directory", "--tree-hash", "--hash", "sha1"]);
+    cmd.assert()
+        .success()
+        .stdout("45c10f5f6a2f79175610aa0fb6b2c487641b63cf  directory\n");
+
+    for option in ["--compare", "--dir-hashes", "--find-dupes"] {
+        let mut cmd = hashall();
+        cmd.args(["directory", "--tree-hash", option]);
+        cmd.assert().failure();
+    }
+    Ok(())
+}
+
+#[test]
+fn test_group_hash() {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--group-hash", "4"]);
+    cmd.assert()
+        .success()
+        .stdout("ac17-5545-a9b0-f6da-0d5c-03f5-1355-63d8  file.txt\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--group-hash", "5", "--format", "csv"]);
+    cmd.assert()
+        .success()
+        .stdout("hash,filename\nac175-545a9-b0f6d-a0d5c-03f51-35563-d8,file.txt\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--group-hash", "0"]);
+    cmd.assert().failure();
+
+    // Formats read by other tools need the hashes as they are
+    for format in ["bazel", "git-lfs", "spdx", "signify", "ostree"] {
+        let mut cmd = hashall();
+        cmd.args(["file.txt", "--group-hash", "4", "--hash", "sha256"])
+            .args(["--format", format]);
+        cmd.assert()
+            .failure()
+            .stdout("")
+            .stderr(predicates::str::contains(
+                "--group-hash can only be used with",
+            ));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_hash_symlink_target() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir(dir.path().join("sub"))?;
+    std::fs::write(dir.path().join("file.txt"), "hello\n")?;
+    std::os::unix::fs::symlink("../file.txt", dir.path().join("sub/link"))?;
+    std::os::unix::fs::symlink("missing", dir.path().join("dangling"))?;
+
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args([".", "-r", "--hash-symlink-target"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let mut lines: Vec<_> = std::str::from_utf8(&output)?.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            format!("{MD5_HELLO}  file.txt"),
+            // md5 of "missing" and "../file.txt"
+            "ea21841da70e6405af19fabc4ff8bdd9  dangling".to_string(),
+            "f7da52c2ffd4de11227b2eb6fc67f0f9  sub/link".to_string(),
+        ]
+    );
+
+    // Without the option, symlinks given as inputs are followed
+    let mut cmd = hashall();
+    cmd.arg("-C").arg(dir.path()).arg("sub/link");
+    cmd.assert()
+        .success()
+        .stdout(format!("{MD5_HELLO}  sub/link\n"));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_follow_symlinks() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir(dir.path().join("sub"))?;
+    std::fs::write(dir.path().join("file.txt"), "hello\n")?;
+    std::fs::write(dir.path().join("sub/a.txt"), "hello\n")?;
+    std::os::unix::fs::symlink("file.txt", dir.path().join("link.txt"))?;
+    std::os::unix::fs::symlink("sub", dir.path().join("linked"))?;
+    std::os::unix::fs::symlink("..", dir.path().join("sub/up"))?;
+
+    let mut cmd = hashall();
+    cmd.arg("-C").arg(dir.path()).args([".", "-r"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let mut lines: Vec<_> = std::str::from_utf8(&output)?.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            format!("{MD5_HELLO}  file.txt"),
+            format!("{MD5_HELLO}  sub/a.txt"),
+        ]
+    );
+
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args([".", "-r", "--follow-symlinks"]);
+    let assert = cmd.assert().success();
+    let output = assert.get_output();
+    let mut lines: Vec<_> = std::str::from_utf8(&output.stdout)?.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            format!("{MD5_HELLO}  file.txt"),
+            format!("{MD5_HELLO}  link.txt"),
+            format!("{MD5_HELLO}  linked/a.txt"),
+            format!("{MD5_HELLO}  sub/a.txt"),
+        ]
+    );
+    let stderr = std::str::from_utf8(&output.stderr)?;
+    assert!(stderr.contains("sub/up points to its parent"), "{stderr}");
+    assert!(
+        stderr.contains("linked/up points to its parent"),
+        "{stderr}"
+    );
+
+    // A dangling symlink fails, but the other files are hashed
+    std::fs::remove_file(dir.path().join("sub/up"))?;
+    std::os::unix::fs::symlink("missing", dir.path().join("sub/dangling"))?;
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args(["sub", "--follow-symlinks"]);
+    let assert = cmd
+        .assert()
+        .code(1)
+        .stdout(format!("{MD5_HELLO}  sub/a.txt\n"));
+    let stderr = std::str::from_utf8(&assert.get_output().stderr)?;
+    assert!(stderr.contains("sub/dangling"), "{stderr}");
+    assert!(stderr.contains("1 file could not be hashed"), "{stderr}");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--follow-symlinks", "--hash-symlink-target"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_warn_empty() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    for sub in ["empty", "hidden", "logs", "nested/sub"] {
+        std::fs::create_dir_all(dir.path().join(sub))?;
+    }
+    std::fs::write(dir.path().join("hidden/.file.txt"), "hello\n")?;
+    std::fs::write(dir.path().join("logs/a.log"), "hello\n")?;
+    std::fs::write(dir.path().join("logs/b.log"), "hello\n")?;
+    std::fs::write(dir.path().join("nested/sub/file.txt"), "hello\n")?;
+    // An archive without entries
+    let mut zip = b"PK\x05\x06".to_vec();
+    zip.resize(22, 0);
+    std::fs::write(dir.path().join("empty.zip"), zip)?;
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["empty", "hidden", "logs", "nested", "empty.zip"])
+        .args(["--warn-empty", "--archive", "--exclude", "*.log"]);
+    let assert = cmd.assert().success().stdout("");
+    let stderr = String::from_utf8(assert.get_output().stderr.clone())?;
+    assert!(stderr.contains("empty: No files to hash\n"), "{}", stderr);
+    assert!(
+        stderr.contains(
+            "hidden: All 1 file is skipped (hidden, symlinks, --exclude, or --include)\n"
+        ),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("logs: All 2 files are skipped"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("nested: No files to hash (subdirectories are walked with -r)"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("empty.zip"), "{}", stderr);
+
+    // Silent by default and when there are files
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path()).args(["empty", "logs"]);
+    cmd.assert().success().stderr("");
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["nested", "-r", "--warn-empty"]);
+    cmd.assert().success().stderr("");
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_no_dereference() -> Result<()> {
+    use md5::Digest;
+    setup();
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir(dir.path().join("sub"))?;
+    std::fs::write(dir.path().join("file.txt"), "hello\n")?;
+    std::os::unix::fs::symlink("file.txt", dir.path().join("link.txt"))?;
+    std::os::unix::fs::symlink("sub", dir.path().join("linked"))?;
+    std::os::unix::fs::symlink("missing", dir.path().join("dangling"))?;
+
+    // Followed by default
+    let mut cmd = hashall();
+    cmd.arg("-C").arg(dir.path()).arg("link.txt");
+    cmd.assert()
+        .success()
+        .stdout(format!("{MD5_HELLO}  link.txt\n"));
+
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args(["link.txt", "linked", "file.txt", "--no-dereference"]);
+    cmd.assert()
+        .success()
+        .stdout(format!("{MD5_HELLO}  file.txt\n"))
+        .stderr(predicates::str::contains("link.txt: Skipped (symlink)"))
+        .stderr(predicates::str::contains("linked: Skipped (symlink)"));
+
+    // Hashed as links, wherever they point
+    let mut cmd = hashall();
+    cmd.arg("-C").arg(dir.path()).args([
+        "link.txt",
+        "linked",
+        "dangling",
+        "--no-dereference",
+        "--hash-symlink-target",
+        "-j",
+        "1",
+    ]);
+    cmd.assert().success().stdout(format!(
+        "{:x}  link.txt\n{:x}  linked\n{:x}  dangling\n",
+        md5::Md5::digest("file.txt"),
+        md5::Md5::digest("sub"),
+        md5::Md5::digest("missing")
+    ));
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--no-dereference", "--follow-symlinks"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_git_lfs() {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--format", "git-lfs", "--hash", "sha256"]);
+    cmd.assert().success().stdout(
+        "version https://git-lfs.github.com/spec/v1\n\
+         oid sha256:64c7ed6638f4224634b2223f16139fc8abb30905ff5502de0468bce246f98a2c\n\
+         size 35\n",
+    );
+
+    let mut cmd = hashall();
+    cmd.args([
+        "file.txt",
+        "directory",
+        "--format",
+        "git-lfs",
+        "--hash",
+        "sha256",
+        "-j",
+        "1",
+    ]);
+    cmd.assert().success().stdout(
+        "==> file.txt <==\n\
+         version https://git-lfs.github.com/spec/v1\n\
+         oid sha256:64c7ed6638f4224634b2223f16139fc8abb30905ff5502de0468bce246f98a2c\n\
+         size 35\n\
+         \n\
+         ==> directory/file.txt <==\n\
+         version https://git-lfs.github.com/spec/v1\n\
+         oid sha256:34cbf16d403dbfcecbebb73b9a0bdd2f1324634afb6f86b5eeffbaf3a90e1a50\n\
+         size 36\n",
+    );
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--format", "git-lfs"]);
+    cmd.assert().failure();
+}
+
+#[test]
+fn test_broken_pipe() -> Result<()> {
+    use std::io::BufRead;
+    setup();
+    // Far more output than a pipe buffers
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("data.bin"), vec![0; 1 << 20])?;
+
+    for (action, code) in [("quiet", 0), ("sigpipe", 141), ("error", 1)] {
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("hashall"))
+            .current_dir(dir.path())
+            .args(["data.bin", "--block-tree", "16", "--broken-pipe", action])
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+        // Read a line and close the pipe like `head -n 1`
+        let mut line = String::new();
+        std::io::BufReader::new(child.stdout.take().unwrap()).read_line(&mut line)?;
+        assert!(line.ends_with("  0  data.bin\n"), "{}", line);
+        let output = child.wait_with_output()?;
+        let stderr = String::from_utf8(output.stderr)?;
+        assert_eq!(output.status.code(), Some(code), "{}", stderr);
+        assert!(!stderr.contains("panicked"), "{}", stderr);
+        assert_eq!(stderr.contains("Broken pipe"), action == "error");
+    }
+
+    // The results of --check
+    std::fs::write(dir.path().join("empty"), "")?;
+    let manifest = "d41d8cd98f00b204e9800998ecf8427e  empty\n".repeat(20000);
+    std::fs::write(dir.path().join("manifest"), manifest)?;
+    for (action, code) in [("quiet", 0), ("sigpipe", 141), ("error", 1)] {
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("hashall"))
+            .current_dir(dir.path())
+            .args(["--check", "manifest", "--broken-pipe", action])
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+        let mut line = String::new();
+        std::io::BufReader::new(child.stdout.take().unwrap()).read_line(&mut line)?;
+        assert_eq!(line, "empty: OK\n");
+        let output = child.wait_with_output()?;
+        let stderr = String::from_utf8(output.stderr)?;
+        assert_eq!(output.status.code(), Some(code), "{}", stderr);
+        assert!(!stderr.contains("panicked"), "{}", stderr);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sidecar() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir(dir.path().join("directory"))?;
+    std::fs::copy(data_dir().join("file.txt"), dir.path().join("file.txt"))?;
+    std::fs::copy(
+        data_dir().join("directory/file.txt"),
+        dir.path().join("directory/file.txt"),
+    )?;
+
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args([".", "-r", "--hash", "sha256", "--sidecar"]);
+    cmd.assert().success().stdout("");
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("file.txt.sha256"))?,
+        "64c7ed6638f4224634b2223f16139fc8abb30905ff5502de0468bce246f98a2c  file.txt\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("directory/file.txt.sha256"))?,
+        "34cbf16d403dbfcecbebb73b9a0bdd2f1324634afb6f86b5eeffbaf3a90e1a50  file.txt\n"
+    );
+
+    // Sidecars don't get sidecars, and a sidecar that can't be written doesn't stop the others
+    std::fs::remove_file(dir.path().join("file.txt.sha256"))?;
+    std::fs::create_dir(dir.path().join("file.txt.sha256"))?;
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args([".", "-r", "--hash", "sha256", "--sidecar", "hash"]);
+    cmd.assert()
+        .success()
+        .stderr(predicates::str::contains("file.txt.sha256"));
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("directory/file.txt.sha256"))?,
+        "34cbf16d403dbfcecbebb73b9a0bdd2f1324634afb6f86b5eeffbaf3a90e1a50\n"
+    );
+    assert!(!dir.path().join("directory/file.txt.sha256.sha256").exists());
+    Ok(())
+}
+
+#[test]
+fn test_check() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    let manifest = dir.path().join("manifest");
+    std::fs::write(
+        &manifest,
+        "ac175545a9b0f6da0d5c03f5135563d8  file.txt\n\
+         51223200ab682db9686e410e25d74c22ec557a52  directory\\file.txt\n",
+    )?;
+    let mut cmd = hashall();
+    cmd.arg("--check").arg(&manifest);
+    // The path as written is escaped like `md5sum -c` does where `\` isn't a separator
+    let expected = if cfg!(windows) {
+        "file.txt: OK\ndirectory\\file.txt: OK\n"
+    } else {
+        "file.txt: OK\n\\directory\\\\file.txt: OK\n"
+    };
+    cmd.assert().success().stdout(expected);
+
+    // 64 hex digits can be SHA256, SHA3-256, or BLAKE3
+    std::fs::write(
+        &manifest,
+        "34cbf16d403dbfcecbebb73b9a0bdd2f1324634afb6f86b5eeffbaf3a90e1a50  directory/file.txt\n",
+    )?;
+    let mut cmd = hashall();
+    cmd.arg("--check").arg(&manifest);
+    cmd.assert()
+        .failure()
+        .stdout("")
+        .stderr(predicates::str::contains("Use --hash."));
+    let mut cmd = hashall();
+    cmd.args(["--hash", "sha256", "--check"]).arg(&manifest);
+    cmd.assert().success().stdout("directory/file.txt: OK\n");
+
+    // Written to --output
+    let output = dir.path().join("result.txt");
+    let mut cmd = hashall();
+    cmd.args(["--hash", "sha256", "--output"])
+        .arg(&output)
+        .arg("--check")
+        .arg(&manifest);
+    cmd.assert().success().stdout("");
+    assert_eq!(
+        std::fs::read_to_string(&output)?,
+        "directory/file.txt: OK\n"
+    );
+
+    std::fs::write(
+        &manifest,
+        "ac175545a9b0f6da0d5c03f5135563d8  file.txt\n\
+         ac175545a9b0f6da0d5c03f5135563d8  directory/file.txt\n\
+         ac175545a9b0f6da0d5c03f5135563d8  missing.txt\n",
+    )?;
+    let mut cmd = hashall();
+    cmd.arg("--check").arg(&manifest);
+    cmd.assert()
+        .code(1)
+        .stdout(
+            "file.txt: OK\n\
+             directory/file.txt: FAILED\n\
+             missing.txt: FAILED open or read\n",
+        )
+        .stderr(predicates::str::contains("2 of 3 files FAILED"));
+
+    // Only the failures
+    let mut cmd = hashall();
+    cmd.args(["--quiet", "--check"]).arg(&manifest);
+    cmd.assert()
+        .code(1)
+        .stdout(
+            "directory/file.txt: FAILED\n\
+             missing.txt: FAILED open or read\n",
+        )
+        .stderr(predicates::str::contains("2 of 3 files FAILED"));
+
+    // The summary isn't a log message
+    let mut cmd = hashall();
+    cmd.args(["--quiet", "--check"])
+        .arg(&manifest)
+        .env("RUST_LOG", "off");
+    cmd.assert().code(1).stderr("2 of 3 files FAILED\n");
+
+    // With another algorithm than the one in the manifest
+    let mut cmd = hashall();
+    cmd.args(["--hash", "sha1", "--check"]).arg(&manifest);
+    cmd.assert()
+        .code(1)
+        .stdout(predicates::str::contains("file.txt: FAILED\n"));
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--check"]).arg(&manifest);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_show_mode() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    setup();
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("file.txt"), "hello\n")?;
+    std::fs::set_permissions(
+        dir.path().join("file.txt"),
+        std::fs::Permissions::from_mode(0o600),
+    )?;
+    let mut builder = tar::Builder::new(std::fs::File::create(dir.path().join("a.tar"))?);
+    let mut header = tar::Header::new_gnu();
+    header.set_size(6);
+    header.set_mode(0o755);
+    header.set_cksum();
+    builder.append_data(&mut header, "file.txt", "hello\n".as_bytes())?;
+    builder.into_inner()?;
+
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path())
+        .args(["file.txt", "a.tar", "--archive", "--show-mode", "-j", "1"]);
+    cmd.assert().success().stdout(format!(
+        "{MD5_HELLO}  600  file.txt\n\
+         {MD5_HELLO}  755  a.tar/file.txt\n"
+    ));
+
+    // Archive entries match the extracted files regardless of their modes
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path()).args([
+        "a.tar",
+        "--archive",
+        "--show-mode",
+        "--ignore-archive-modes",
+        "--format",
+        "csv",
+    ]);
+    cmd.assert().success().stdout(format!(
+        "hash,mode,filename\n{MD5_HELLO},-,a.tar/file.txt\n"
+    ));
+
+    // The mode of a skipped file isn't left for the next record
+    std::fs::write(dir.path().join("data.bin"), [0, 1, 2])?;
+    let mut cmd = hashall();
+    cmd.current_dir(dir.path()).args([
+        "data.bin",
+        "a.tar",
+        "--archive",
+        "--show-mode",
+        "--ignore-archive-modes",
+        "--only-text",
+        "-j",
+        "1",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(format!("{MD5_HELLO}  -  a.tar/file.txt\n"));
+    Ok(())
+}
+
+#[test]
+fn test_syslog() {
+    setup();
+    // The output is the same whether or not syslog is available
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--syslog"]);
+    cmd.assert()
+        .success()
+        .stdout("ac175545a9b0f6da0d5c03f5135563d8  file.txt\n");
+}
+
+#[test]
+fn test_files_from() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["--files-from", "-", "--archive", "-j", "2"])
+        .write_stdin("file.txt\n\ndirectory/file.txt\r\narchive.zip\n");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let mut lines: Vec<_> = std::str::from_utf8(&output)?.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            "28f9f80606380557b3a5034417227add  archive.zip/.hidden_file.txt",
+            "6657b6593444bd9a13d0131d47bef4f5  archive.zip/directory/file.txt",
+            "6657b6593444bd9a13d0131d47bef4f5  directory/file.txt",
+            "ac175545a9b0f6da0d5c03f5135563d8  archive.zip/file.txt",
+            "ac175545a9b0f6da0d5c03f5135563d8  file.txt",
+        ]
+    );
+
+    // Listed paths follow the arguments
+    let dir = tempfile::tempdir()?;
+    let list = dir.path().join("list.txt");
+    std::fs::write(&list, "directory/file.txt\n")?;
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "-j", "1", "--files-from"]).arg(&list);
+    cmd.assert().success().stdout(
+        "ac175545a9b0f6da0d5c03f5135563d8  file.txt\n\
+         6657b6593444bd9a13d0131d47bef4f5  directory/file.txt\n",
+    );
+
+    let mut cmd = hashall();
+    cmd.args(["--files-from", "missing.txt"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_max_open_files() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    for i in 0..500 {
+        std::fs::write(dir.path().join(format!("{i}.txt")), "hello\n")?;
+    }
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args([".", "-j", "16", "--max-open-files", "2"]);
+    let assert = cmd.assert().success().stderr("");
+    let stdout = std::str::from_utf8(&assert.get_output().stdout)?;
+    assert_eq!(stdout.lines().count(), 500);
+    assert!(stdout.lines().all(|line| line.starts_with(MD5_HELLO)));
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--max-open-files", "0"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_ostree() -> Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    setup();
+    let dir = tempfile::tempdir()?;
+    let file = dir.path().join("file.txt");
+    std::fs::write(&file, "hello\n")?;
+    std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o644))?;
+    std::os::unix::fs::symlink("file.txt", dir.path().join("link"))?;
+    let metadata = std::fs::metadata(&file)?;
+
+    let ostree = |args: &[&str]| -> Result<String> {
+        let mut cmd = hashall();
+        cmd.arg("-C")
+            .arg(dir.path())
+            .args(args)
+            .args(["--format", "ostree", "--hash", "sha256", "-j", "1"]);
+        let output = cmd.assert().success().get_output().stdout.clone();
+        Ok(String::from_utf8(output)?)
+    };
+    let output = ostree(&["file.txt", "link", "--hash-symlink-target"])?;
+    let lines: Vec<_> = output.lines().collect();
+    let [file_line, link_line] = lines.as_slice() else {
+        panic!("{}", output);
+    };
+    let (file_checksum, _) = file_line.split_once("  ").unwrap();
+    // The checksums of the file header are in the unit tests of the header. Known checksums
+    // of the same file (mode 644) for owners that the tests commonly run as:
+    match (metadata.uid(), metadata.gid()) {
+        (0, 0) => assert_eq!(
+            file_checksum,
+            "44f778e59f0a4748d6b0c90a47347212a231c4ad1e8f7ea5c5dffc7749153a6b"
+        ),
+        (1000, 1000) => assert_eq!(
+            file_checksum,
+            "7e4bad27e0a4c4a24dd8978133cc8b0c9b222c9d3156a0f554b5490876b37f88"
+        ),
+        _ => {}
+    }
+    assert_eq!(file_checksum.len(), 64);
+    assert!(file_line.ends_with("  file.txt"), "{}", output);
+    assert!(link_line.ends_with("  link"), "{}", output);
+    // Not the plain SHA256 of the contents, nor of the target
+    assert_ne!(
+        file_checksum,
+        "64c7ed6638f4224634b2223f16139fc8abb30905ff5502de0468bce246f98a2c"
+    );
+    assert_ne!(file_line[..64], link_line[..64]);
+
+    // A skipped file leaves nothing for the next one
+    std::fs::write(dir.path().join("data.bin"), [0u8, 1, 2, 3])?;
+    let alone = ostree(&["data.bin"])?;
+    assert_eq!(ostree(&["file.txt", "data.bin", "--only-binary"])?, alone);
+
+    // The mode is part of the checksum
+    std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o755))?;
+    assert!(!ostree(&["file.txt"])?.starts_with(file_checksum));
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--format", "ostree"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_all_files_all_dirs() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir(dir.path().join(".git"))?;
+    std::fs::write(dir.path().join(".git/config"), "hello\n")?;
+    std::fs::write(dir.path().join(".env"), "hello\n")?;
+    std::fs::write(dir.path().join("file.txt"), "hello\n")?;
+    for (options, expected) in [
+        (&[][..], &["file.txt"][..]),
+        (&["--all-files"], &[".env", "file.txt"]),
+        (&["--all-dirs"], &[".git/config", "file.txt"]),
+        (
+            &["--all-files", "--all-dirs"],
+            &[".env", ".git/config", "file.txt"],
+        ),
+        (&["-a"], &[".env", ".git/config", "file.txt"]),
+    ] {
+        let mut cmd = hashall();
+        cmd.arg("-C")
+            .arg(dir.path())
+            .args([".", "-r"])
+            .args(options);
+        let output = cmd.assert().success().get_output().stdout.clone();
+        let mut paths: Vec<_> = std::str::from_utf8(&output)?
+            .lines()
+            .map(|line| line.strip_prefix(&format!("{MD5_HELLO}  ")).unwrap())
+            .collect();
+        paths.sort();
+        assert_eq!(paths, expected, "{:?}", options);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_max_depth() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir_all(dir.path().join("a/b/c"))?;
+    for path in ["top.txt", "a/1.txt", "a/b/2.txt", "a/b/c/3.txt"] {
+        std::fs::write(dir.path().join(path), "hello\n")?;
+    }
+    for (options, expected) in [
+        (&["--max-depth", "1"][..], &["top.txt"][..]),
+        (&["--max-depth", "2"], &["a/1.txt", "top.txt"]),
+        (&["--max-depth", "3"], &["a/1.txt", "a/b/2.txt", "top.txt"]),
+        (&[], &["a/1.txt", "a/b/2.txt", "a/b/c/3.txt", "top.txt"]),
+    ] {
+        let mut cmd = hashall();
+        cmd.arg("-C")
+            .arg(dir.path())
+            .args([".", "-r"])
+            .args(options);
+        let output = cmd.assert().success().get_output().stdout.clone();
+        let mut paths: Vec<_> = std::str::from_utf8(&output)?
+            .lines()
+            .map(|line| line.strip_prefix(&format!("{MD5_HELLO}  ")).unwrap())
+            .collect();
+        paths.sort();
+        assert_eq!(paths, expected, "{:?}", options);
+    }
+
+    // Requires --recursive, and 0 would walk nothing
+    let mut cmd = hashall();
+    cmd.args([".", "--max-depth", "2"]);
+    cmd.assert().failure();
+    let mut cmd = hashall();
+    cmd.args([".", "-r", "--max-depth", "0"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_exclude() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    for path in ["target/debug", "src/target", "src/node_modules/pkg", "docs"] {
+        std::fs::create_dir_all(dir.path().join(path))?;
+    }
+    for path in [
+        "file.txt",
+        "build.log",
+        "target/debug/out.txt",
+        "src/target/file.txt",
+        "src/main.txt",
+        "src/node_modules/pkg/index.txt",
+        "docs/notes.log",
+    ] {
+        std::fs::write(dir.path().join(path), "hello\n")?;
+    }
+    for (options, expected) in [
+        (
+            &["--exclude", "target"][..],
+            &[
+                "build.log",
+                "docs/notes.log",
+                "file.txt",
+                "src/main.txt",
+                "src/node_modules/pkg/index.txt",
+            ][..],
+        ),
+        (
+            &["--exclude", "target/", "--exclude", "*.log"],
+            &["file.txt", "src/main.txt", "src/node_modules/pkg/index.txt"],
+        ),
+        // Patterns with `/` are matched from the walked directory
+        (
+            &[
+                "--exclude",
+                "src/**/node_modules",
+                "--exclude",
+                "docs/*.log",
+            ],
+            &[
+                "build.log",
+                "file.txt",
+                "src/main.txt",
+                "src/target/file.txt",
+                "target/debug/out.txt",
+            ],
+        ),
+    ] {
+        let mut cmd = hashall();
+        cmd.arg("-C")
+            .arg(dir.path())
+            .args([".", "-r"])
+            .args(options);
+        let output = cmd.assert().success().get_output().stdout.clone();
+        let mut paths: Vec<_> = std::str::from_utf8(&output)?
+            .lines()
+            .map(|line| line.strip_prefix(&format!("{MD5_HELLO}  ")).unwrap())
+            .collect();
+        paths.sort();
+        assert_eq!(paths, expected, "{:?}", options);
+    }
+
+    // Files given as inputs are hashed even if they match
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args(["build.log", "--exclude", "*.log"]);
+    cmd.assert()
+        .success()
+        .stdout(format!("{MD5_HELLO}  build.log\n"));
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--exclude", "[a-"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_include() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    for path in ["src/bin", "target", ".config"] {
+        std::fs::create_dir_all(dir.path().join(path))?;
+    }
+    for path in [
+        "README.md",
+        "main.rs",
+        "src/lib.rs",
+        "src/bin/tool.rs",
+        "target/gen.rs",
+        ".config/local.rs",
+        ".hidden.rs",
+    ] {
+        std::fs::write(dir.path().join(path), "hello\n")?;
+    }
+    for (options, expected) in [
+        (
+            &["--include", "*.rs"][..],
+            &["main.rs", "src/bin/tool.rs", "src/lib.rs", "target/gen.rs"][..],
+        ),
+        (
+            &["--include", "src/*.rs", "--include", "*.md"],
+            &["README.md", "src/lib.rs"],
+        ),
+        // Exclude wins
+        (
+            &[
+                "--include",
+                "*.rs",
+                "--exclude",
+                "target",
+                "--exclude",
+                "tool.rs",
+            ],
+            &["main.rs", "src/lib.rs"],
+        ),
+        // Hidden files still need --all
+        (
+            &["--include", "*.rs", "-a", "--exclude", "target"],
+            &[
+                ".config/local.rs",
+                ".hidden.rs",
+                "main.rs",
+                "src/bin/tool.rs",
+                "src/lib.rs",
+            ],
+        ),
+    ] {
+        let mut cmd = hashall();
+        cmd.arg("-C")
+            .arg(dir.path())
+            .args([".", "-r"])
+            .args(options);
+        let output = cmd.assert().success().get_output().stdout.clone();
+        let mut paths: Vec<_> = std::str::from_utf8(&output)?
+            .lines()
+            .map(|line| line.strip_prefix(&format!("{MD5_HELLO}  ")).unwrap())
+            .collect();
+        paths.sort();
+        assert_eq!(paths, expected, "{:?}", options);
+    }
+
+    // Files given as inputs are hashed even if they don't match
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args(["README.md", "--include", "*.rs"]);
+    cmd.assert()
+        .success()
+        .stdout(format!("{MD5_HELLO}  README.md\n"));
+    Ok(())
+}
+
+#[test]
+fn test_progress() {
+    setup();
+    // stderr is not a terminal, so the output is unchanged and nothing is drawn
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--progress"]);
+    cmd.assert()
+        .success()
+        .stdout("ac175545a9b0f6da0d5c03f5135563d8  file.txt\n")
+        .stderr("");
+}
+
+#[test]
+fn test_uppercase() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "directory", "--uppercase", "-j", "1"]);
+    cmd.assert().success().stdout(
+        "AC175545A9B0F6DA0D5C03F5135563D8  file.txt\n\
+         6657B6593444BD9A13D0131D47BEF4F5  directory/file.txt\n",
+    );
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--uppercase", "--hash", "crc32", "-f", "csv"]);
+    cmd.assert()
+        .success()
+        .stdout("hash,filename\n42AB22CC,file.txt\n");
+
+    // Matches a lower-case baseline
+    let dir = tempfile::tempdir()?;
+    let baseline = dir.path().join("baseline.md5");
+    std::fs::write(&baseline, "ac175545a9b0f6da0d5c03f5135563d8  file.txt\n")?;
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--uppercase", "--diff-baseline"])
+        .arg(&baseline);
+    cmd.assert().success().stdout("");
+
+    // Formats read by other tools need lower-case hex
+    for format in ["bazel", "git-lfs", "spdx", "signify", "ostree"] {
+        let mut cmd = hashall();
+        cmd.args(["file.txt", "--uppercase", "--hash", "sha256"])
+            .args(["--format", format]);
+        cmd.assert()
+            .failure()
+            .stdout("")
+            .stderr(predicates::str::contains(
+                "--uppercase can only be used with",
+            ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_base64() {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--base64"]);
+    cmd.assert()
+        .success()
+        .stdout("rBdVRamw9toNXAP1E1Vj2A==  file.txt\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--base64", "--tag", "--hash", "sha256"]);
+    cmd.assert()
+        .success()
+        .stdout("SHA256 (file.txt) = ZMftZjj0IkY0siI/FhOfyKuzCQX/VQLeBGi84kb5iiw=\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--base64", "-f", "csv"]);
+    cmd.assert()
+        .success()
+        .stdout("hash,filename\nrBdVRamw9toNXAP1E1Vj2A==,file.txt\n");
+
+    for options in [
+        &["--uppercase"][..],
+        &["--format", "bazel", "--hash", "sha256"],
+        &["--check", "file.txt"],
+    ] {
+        let mut cmd = hashall();
+        cmd.args(["file.txt", "--base64"]).args(options);
+        cmd.assert().failure();
+    }
+}
+
+#[test]
+fn test_base_dir() -> Result<()> {
+    setup();
+    let data = data_dir().canonicalize()?;
+    let mut cmd = hashall();
+    cmd.arg(data.join("file.txt"))
+        .arg(data.join("archive.tar"))
+        .arg("--base-dir")
+        .arg(&data)
+        .args(["--archive", "-j", "1"]);
+    cmd.assert().success().stdout(
+        "ac175545a9b0f6da0d5c03f5135563d8  file.txt\n\
+         ac175545a9b0f6da0d5c03f5135563d8  archive.tar/file.txt\n\
+         6657b6593444bd9a13d0131d47bef4f5  archive.tar/directory/file.txt\n\
+         28f9f80606380557b3a5034417227add  archive.tar/.hidden_file.txt\n",
+    );
+
+    // Paths outside the directory are printed as they are
+    let mut cmd = hashall();
+    cmd.arg(data.join("file.txt"))
+        .arg("--base-dir")
+        .arg(data.join("directory"));
+    cmd.assert()
+        .success()
+        .stdout(format!(
+            "ac175545a9b0f6da0d5c03f5135563d8  {}\n",
+            data.join("file.txt").display()
+        ))
+        .stderr(predicates::str::contains("not under --base-dir"));
+    Ok(())
+}
+
+#[test]
+fn test_zero() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "directory/file.txt", "-z", "-j", "1"]);
+    cmd.assert().success().stdout(
+        "ac175545a9b0f6da0d5c03f5135563d8  file.txt\0\
+         6657b6593444bd9a13d0131d47bef4f5  directory/file.txt\0",
+    );
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--zero", "--tag"]);
+    cmd.assert()
+        .success()
+        .stdout("MD5 (file.txt) = ac175545a9b0f6da0d5c03f5135563d8\0");
+
+    // No header in zero mode
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--zero", "-f", "csv"]);
+    cmd.assert()
+        .success()
+        .stdout("ac175545a9b0f6da0d5c03f5135563d8,file.txt\0");
+
+    // NUL-separated --files-from
+    let mut cmd = hashall();
+    cmd.args(["-z", "--files-from", "-", "-j", "1"])
+        .write_stdin("file.txt\0\0directory/file.txt\0");
+    cmd.assert().success().stdout(
+        "ac175545a9b0f6da0d5c03f5135563d8  file.txt\0\
+         6657b6593444bd9a13d0131d47bef4f5  directory/file.txt\0",
+    );
+
+    // Names with newlines aren't escaped
+    #[cfg(unix)]
+    {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("a\nb"), "hello\n")?;
+        let mut cmd = hashall();
+        cmd.current_dir(dir.path()).args(["a\nb", "-z"]);
+        cmd.assert()
+            .success()
+            .stdout(format!("{}  a\nb\0", MD5_HELLO));
+    }
+
+    for options in [
+        &["--total"][..],
+        &["--format", "json"],
+        &["--check", "file.txt"],
+    ] {
+        let mut cmd = hashall();
+        cmd.args(["file.txt", "-z"]).args(options);
+        cmd.assert().failure();
+    }
+    Ok(())
+}
+
+#[test]
+fn test_tar_tee() -> Result<()> {
+    setup();
+    let mut builder = tar::Builder::new(Vec::new());
+    builder.append_path_with_name(data_dir().join("file.txt"), "file.txt")?;
+    builder.append_path_with_name(data_dir().join("directory/file.txt"), "directory/file.txt")?;
+    let mut archive = builder.into_inner()?;
+    // Bytes after the end of the archive are passed through too
+    archive.extend_from_slice(b"trailing");
+
+    let dir = tempfile::tempdir()?;
+    let hashes = dir.path().join("hashes.txt");
+    let mut cmd = hashall();
+    cmd.args(["-", "--tar-tee", "--stdin-name", "backup.tar", "-o"])
+        .arg(&hashes)
+        .write_stdin(archive.clone());
+    let output = cmd.assert().success().get_output().stdout.clone();
+    assert!(output == archive, "the output differs from the input");
+    assert_eq!(
+        std::fs::read_to_string(&hashes)?,
+        "ac175545a9b0f6da0d5c03f5135563d8  backup.tar/file.txt\n\
+         6657b6593444bd9a13d0131d47bef4f5  backup.tar/directory/file.txt\n"
+    );
+
+    // The hashes can't share stdout with the archive
+    let mut cmd = hashall();
+    cmd.args(["-", "--tar-tee"]).write_stdin(archive);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_multiple_hashes() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args([
+        "file.txt", "--hash", "md5", "--hash", "sha256", "--hash", "crc32",
+    ]);
+    cmd.assert().success().stdout(
+        "ac175545a9b0f6da0d5c03f5135563d8  \
+         64c7ed6638f4224634b2223f16139fc8abb30905ff5502de0468bce246f98a2c  42ab22cc  file.txt\n",
+    );
+
+    // The columns are named by algorithm
+    let mut cmd = hashall();
+    cmd.args([
+        "file.txt", "--hash", "sha1", "--hash", "md5", "--format", "csv", "--size",
+    ]);
+    cmd.assert().success().stdout(
+        "sha1,md5,size,filename\n\
+         598a9d1de5d569bd7e24280c122a5cfb0adf4b50,ac175545a9b0f6da0d5c03f5135563d8,35,file.txt\n",
+    );
+
+    // Archive entries and memory-mapped files are hashed in the same read too
+    let mut cmd = hashall();
+    cmd.args([
+        "file.txt",
+        "archive.tar",
+        "--archive",
+        "--mmap",
+        "--mmap-threshold",
+        "0",
+        "--hash",
+        "md5",
+        "--hash",
+        "sha256",
+        "--format",
+        "jsonl",
+        "-j",
+        "1",
+    ]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let lines: Vec<serde_json::Value> = std::str::from_utf8(&output)?
+        .lines()
+        .skip(1)
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(lines.len(), 4);
+    for line in &lines[..2] {
+        assert_eq!(
+            line["hashes"],
+            serde_json::json!({
+                "md5": "ac175545a9b0f6da0d5c03f5135563d8",
+                "sha256": "64c7ed6638f4224634b2223f16139fc8abb30905ff5502de0468bce246f98a2c",
+            })
+        );
+    }
+
+    for args in [
+        &["--hash", "md5", "--hash", "md5"][..],
+        &["--hash", "sha256", "--hash", "md5", "--format", "git-lfs"],
+        &["--hash", "sha256", "--hash", "md5", "--group-by-hash"],
+    ] {
+        let mut cmd = hashall();
+        cmd.arg("file.txt").args(args);
+        cmd.assert().failure();
+    }
+
+    // A per-input algorithm would replace the first column under its name
+    for input in ["file.txt:sha256", "file.txt:sha1"] {
+        let mut cmd = hashall();
+        cmd.args(["--hash", "md5", "--hash", "sha256", input, "-f", "csv"]);
+        cmd.assert()
+            .failure()
+            .stdout("")
+            .stderr(predicates::str::contains("per-input algorithms"));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_tag() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args([
+        "file.txt",
+        "directory",
+        "--tag",
+        "--hash",
+        "blake3",
+        "-j",
+        "1",
+    ]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    assert_eq!(
+        std::str::from_utf8(&output)?,
+        "BLAKE3 (file.txt) = 228aa74c5532873a9de66f70f258511a2af4f64d435924acb3bc536b1ff196d5\n\
+         BLAKE3 (directory/file.txt) = \
+         137b382002074fbd89dc0ce2f812e2500fe0e8203538b14633c0136f728fb7cf\n"
+    );
+
+    // Round trip with --check. The algorithm is taken from the lines, so BLAKE3 is not
+    // mistaken for SHA256.
+    let dir = tempfile::tempdir()?;
+    let manifest = dir.path().join("manifest");
+    std::fs::write(&manifest, &output)?;
+    let mut cmd = hashall();
+    cmd.arg("--check").arg(&manifest);
+    cmd.assert()
+        .success()
+        .stdout("file.txt: OK\ndirectory/file.txt: OK\n");
+
+    let mut cmd = hashall();
+    cmd.args(["file.txt", "--format", "tag", "--hash", "sha1"]);
+    cmd.assert()
+        .success()
+        .stdout("SHA1 (file.txt) = 598a9d1de5d569bd7e24280c122a5cfb0adf4b50\n");
+    Ok(())
+}
+
+#[test]
+fn test_sort() -> Result<()> {
+    setup();
+    let mut cmd = hashall();
+    cmd.args(["directory", ".", "--archive", "--sort", "-j", "4"]);
+    // Entries stay in the order of each archive
+    let archive_entries = |archive: &str| {
+        format!(
+            "ac175545a9b0f6da0d5c03f5135563d8  ./{archive}/file.txt\n\
+             6657b6593444bd9a13d0131d47bef4f5  ./{archive}/directory/file.txt\n\
+             28f9f80606380557b3a5034417227add  ./{archive}/.hidden_file.txt\n"
+        )
+    };
+    let expected = [
+        archive_entries("archive.tar"),
+        archive_entries("archive.tar.bz2"),
+        archive_entries("archive.tar.gz"),
+        archive_entries("archive.tar.xz"),
+        archive_entries("archive.tar.zst"),
+        archive_entries("archive.zip"),
+        "ac175545a9b0f6da0d5c03f5135563d8  ./file.txt\n\
+         6657b6593444bd9a13d0131d47bef4f5  directory/file.txt\n"
+            .to_string(),
+    ]
+    .concat();
+    cmd.assert().success().stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn test_ordered() -> Result<()> {
+    setup();
+    let dir = tempfile::tempdir()?;
+    // Larger files first, so that later inputs would finish first without --ordered
+    let mut names = Vec::new();
+    for i in 0..40 {
+        let name = format!("{i:02}.bin");
+        std::fs::write(dir.path().join(&name), vec![i as u8; (40 - i) * 10_000])?;
+        names.push(name);
+    }
+    names.reverse();
+    names.insert(20, "-".to_string());
+    let mut cmd = hashall();
+    cmd.arg("-C")
+        .arg(dir.path())
+        .args(&names)
+        .args(["--ordered", "-j", "8"])
+        .write_stdin("hello\n");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let paths: Vec<_> = std::str::from_utf8(&output)?
+        .lines()
+        .map(|line| line.split_once("  ").unwrap().1)
+        .collect();
+    assert_eq!(paths, names);
+    Ok(())
+}
+
+#[test]
+fn test_library_base_dirs() -> Result<()> {
+    use hashall::{Algorithm, BufHashFactory, HashOptions};
+    use std::path::Path;
+    use std::sync::{mpsc, Arc};
+
+    // Two hashers in one process, each with its own base directory and the same relative path
+    let factory = BufHashFactory::new(1 << 16, HashOptions::default(), None, Vec::new());
+    let threads: Vec<_> = [data_dir(), data_dir().join("directory")]
+        .into_iter()
+        .map(|base| {
+            let factory = factory.clone();
+            std::thread::spawn(move || -> Result<Vec<hashall::Record>> {
+                let (sender, receiver) = mpsc::channel();
+                let mut hasher = factory.create(Algorithm::Md5, Arc::from(base), sender);
+                for _ in 0..100 {
+                    hasher.digest_file(Path::new("file.txt"))?;
+                }
+                drop(hasher);
+                Ok(receiver.into_iter().collect())
+            })
+        })
+        .collect();
+    let expected = [
+        "ac175545a9b0f6da0d5c03f5135563d8",
+        "6657b6593444bd9a13d0131d47bef4f5",
+    ];
+    for (thread, expected) in threads.into_iter().zip(expected) {
+        let records = thread.join().unwrap()?;
+        assert_eq!(records.len(), 100);
+        for record in records {
+            assert_eq!(record.path, Path::new("file.txt"));
+            assert_eq!(hashall::output::to_hex(&record.hash), expected);
+        }
+    }
+    Ok(())
+}